@@ -0,0 +1,12 @@
+#[path = "src/codegen.rs"]
+mod codegen;
+
+fn main() {
+    println!("cargo:rerun-if-changed=schema/calls.txt");
+    println!("cargo:rerun-if-changed=src/codegen.rs");
+
+    let schema = std::fs::read_to_string("schema/calls.txt").expect("read schema/calls.txt");
+    let calls = codegen::parse(&schema).unwrap_or_else(|e| panic!("{}", e));
+    let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("calls.rs");
+    std::fs::write(out, codegen::rust(&calls)).expect("write calls.rs");
+}
@@ -0,0 +1,373 @@
+//! `#[derive(Data)]` for minet.
+//!
+//! Fields are encoded in declaration order, the same way
+//! a hand-written `Data` impl would with `minet::Writer`.
+//! `String` uses `Writer::str`, `Vec<T>` is prefixed with
+//! its length, and every other type goes through `minet::Field`.
+//!
+//! Container attributes:
+//!
+//! - `#[data(tag = 3)]` writes a leading `u8`
+//!   and rejects messages that don't start with it.
+//!
+//! Field attributes:
+//!
+//! - `#[data(len = "u8")]` sets the length prefix of a `String`
+//!   (which then has no exists flag) or a `Vec`:
+//!   `u8`, `u16` or `i32`. `Vec` defaults to `u16`.
+//! - `#[data(nullable)]` on `Option<String>` writes
+//!   a `false` exists flag for `None`.
+//! - `#[data(optional)]` on `Option<T>` reads the field
+//!   only if it is there, for fields at the end of a message.
+//! - `#[data(if = "flag")]` or `#[data(if = "!flag")]`
+//!   on `Option<T>` reads the field only when
+//!   an earlier `bool` field is set (or not).
+//! - `#[data(default)]` lets `from_json` fall back to
+//!   `Default::default()` when the key is missing.
+//!
+//! Generated code refers to `crate::minet`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as Tokens;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, spanned::Spanned, Data, DeriveInput, Error, Fields, GenericArgument, Ident, LitInt, LitStr, PathArguments, Type};
+
+#[proc_macro_derive(Data, attributes(data))]
+pub fn derive_data(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+#[derive(Clone, Copy)]
+enum Len {
+    U8,
+    U16,
+    I32,
+}
+
+enum Presence {
+    Always,
+    Nullable,
+    Optional,
+    If { flag: Ident, negate: bool },
+}
+
+struct FieldSpec {
+    name: Ident,
+    ty: Type,
+    len: Option<Len>,
+    presence: Presence,
+    default: bool,
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<Tokens> {
+    let mut tag = None;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("data")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("tag") {
+                tag = Some(meta.value()?.parse::<LitInt>()?.base10_parse::<u8>()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `tag`"))
+            }
+        })?;
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => return Err(Error::new(input.span(), "Data can only be derived for structs")),
+    };
+    let named = match fields {
+        Fields::Named(named) => named.named.iter().collect(),
+        Fields::Unit => Vec::new(),
+        Fields::Unnamed(_) => return Err(Error::new(fields.span(), "Data needs named fields")),
+    };
+    let specs = named.into_iter().map(field_spec).collect::<syn::Result<Vec<_>>>()?;
+
+    let names: Vec<&Ident> = specs.iter().map(|f| &f.name).collect();
+    let construct = match fields {
+        Fields::Unit => quote!(Self),
+        _ => quote!(Self { #(#names),* }),
+    };
+
+    let write_tag = tag.map(|tag| quote!(buf.u8(#tag);));
+    let read_tag = tag.map(|tag| quote! {
+        if reader.u8() != Some(#tag) {
+            return None
+        }
+    });
+
+    let serialize = specs.iter().map(serialize_field).collect::<syn::Result<Vec<_>>>()?;
+    let deserialize = specs.iter().map(deserialize_field).collect::<syn::Result<Vec<_>>>()?;
+    let json = specs.iter().map(json_field).collect::<syn::Result<Vec<_>>>()?;
+    let from_json = specs.iter().map(from_json_field).collect::<syn::Result<Vec<_>>>()?;
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics crate::minet::Data for #ident #ty_generics #where_clause {
+            fn serialize(&self, buf: &mut crate::minet::Writer) {
+                #write_tag
+                #(#serialize)*
+            }
+
+            fn deserialize(data: &[u8]) -> Option<Self> {
+                #[allow(unused_mut, unused_variables)]
+                let mut reader = crate::minet::Reader::new(data);
+                #read_tag
+                #(#deserialize)*
+                Some(#construct)
+            }
+
+            fn json(&self) -> serde_json::Value {
+                #[allow(unused_mut)]
+                let mut map = serde_json::Map::new();
+                #(#json)*
+                serde_json::Value::Object(map)
+            }
+
+            fn from_json(value: &serde_json::Value) -> Option<Self> {
+                #(#from_json)*
+                Some(#construct)
+            }
+        }
+    })
+}
+
+fn field_spec(field: &syn::Field) -> syn::Result<FieldSpec> {
+    let mut spec = FieldSpec {
+        name: field.ident.clone().unwrap(),
+        ty: field.ty.clone(),
+        len: None,
+        presence: Presence::Always,
+        default: false,
+    };
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("data")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("len") {
+                let lit = meta.value()?.parse::<LitStr>()?;
+                spec.len = Some(match lit.value().as_str() {
+                    "u8" => Len::U8,
+                    "u16" => Len::U16,
+                    "i32" => Len::I32,
+                    _ => return Err(Error::new(lit.span(), "expected \"u8\", \"u16\" or \"i32\"")),
+                });
+            } else if meta.path.is_ident("nullable") {
+                spec.presence = Presence::Nullable;
+            } else if meta.path.is_ident("optional") {
+                spec.presence = Presence::Optional;
+            } else if meta.path.is_ident("if") {
+                let lit = meta.value()?.parse::<LitStr>()?;
+                let value = lit.value();
+                let (negate, flag) = match value.strip_prefix('!') {
+                    Some(flag) => (true, flag),
+                    None => (false, value.as_str()),
+                };
+                spec.presence = Presence::If { flag: format_ident!("{}", flag.trim(), span = lit.span()), negate };
+            } else if meta.path.is_ident("default") {
+                spec.default = true;
+            } else {
+                return Err(meta.error("expected `len`, `nullable`, `optional`, `if` or `default`"));
+            }
+            Ok(())
+        })?;
+    }
+
+    let option = generic_arg(&spec.ty, "Option").is_some();
+    match (&spec.presence, option) {
+        (Presence::Always, true) => Err(Error::new(field.ty.span(), "Option fields need `nullable`, `optional` or `if`")),
+        (Presence::Always, false) => Ok(spec),
+        (_, false) => Err(Error::new(field.ty.span(), "this attribute needs an Option field")),
+        (Presence::Nullable, true) if !is_string(generic_arg(&spec.ty, "Option").unwrap()) => {
+            Err(Error::new(field.ty.span(), "`nullable` needs an Option<String> field"))
+        },
+        _ => Ok(spec),
+    }
+}
+
+/// The `T` in `Wrapper<T>`, if `ty` is one.
+fn generic_arg<'a>(ty: &'a Type, wrapper: &str) -> Option<&'a Type> {
+    let Type::Path(path) = ty else { return None };
+    let segment = path.path.segments.last()?;
+    if segment.ident != wrapper {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else { return None };
+    match args.args.first()? {
+        GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    }
+}
+
+fn is_string(ty: &Type) -> bool {
+    matches!(ty, Type::Path(path) if path.path.is_ident("String"))
+}
+
+/// The type a field holds once its presence is known.
+fn value_type(spec: &FieldSpec) -> &Type {
+    match spec.presence {
+        Presence::Always => &spec.ty,
+        _ => generic_arg(&spec.ty, "Option").unwrap(),
+    }
+}
+
+fn len_variant(len: Len) -> Tokens {
+    match len {
+        Len::U8 => quote!(crate::minet::Len::U8),
+        Len::U16 => quote!(crate::minet::Len::U16),
+        Len::I32 => quote!(crate::minet::Len::I32),
+    }
+}
+
+/// Writes `value`, a reference to a `ty`.
+fn write_value(ty: &Type, len: Option<Len>, value: Tokens) -> Tokens {
+    if is_string(ty) {
+        return match len {
+            Some(len) => {
+                let len = len_variant(len);
+                quote!(buf.prefixed_str(#value, #len);)
+            },
+            None => quote!(buf.str(#value);),
+        };
+    }
+    if let Some(inner) = generic_arg(ty, "Vec") {
+        let len = len_variant(len.unwrap_or(Len::U16));
+        let item = write_value(inner, None, quote!(item));
+        return quote! {
+            buf.length((#value).len(), #len);
+            for item in #value {
+                #item
+            }
+        };
+    }
+    quote!(crate::minet::Field::write(#value, buf);)
+}
+
+/// An `Option<ty>` expression that reads a `ty` from `reader`.
+fn read_value(ty: &Type, len: Option<Len>) -> Tokens {
+    if is_string(ty) {
+        return match len {
+            Some(len) => {
+                let len = len_variant(len);
+                quote!(reader.prefixed_str(#len))
+            },
+            None => quote!(reader.str().map(str::to_string)),
+        };
+    }
+    if let Some(inner) = generic_arg(ty, "Vec") {
+        let len = len_variant(len.unwrap_or(Len::U16));
+        let item = read_value(inner, None);
+        return quote! {
+            reader.length(#len).and_then(|n| {
+                (0..n).map(|_| #item).collect::<Option<Vec<_>>>()
+            })
+        };
+    }
+    quote!(<#ty as crate::minet::Field>::read(&mut reader))
+}
+
+/// Converts `value`, a reference to a `ty`, to JSON.
+fn json_value(ty: &Type, value: Tokens) -> Tokens {
+    if is_string(ty) {
+        return quote!(serde_json::Value::from((#value).as_str()));
+    }
+    if let Some(inner) = generic_arg(ty, "Vec") {
+        let item = json_value(inner, quote!(item));
+        return quote!(serde_json::Value::Array((#value).iter().map(|item| #item).collect()));
+    }
+    quote!(crate::minet::Field::json(#value))
+}
+
+/// An `Option<ty>` expression that reads a `ty` from the JSON `value`.
+fn from_json_value(ty: &Type, value: Tokens) -> Tokens {
+    if is_string(ty) {
+        return quote!((#value).as_str().map(str::to_string));
+    }
+    if let Some(inner) = generic_arg(ty, "Vec") {
+        let item = from_json_value(inner, quote!(item));
+        return quote! {
+            (#value).as_array().and_then(|items| {
+                items.iter().map(|item| #item).collect::<Option<Vec<_>>>()
+            })
+        };
+    }
+    quote!(<#ty as crate::minet::Field>::from_json(#value))
+}
+
+fn serialize_field(spec: &FieldSpec) -> syn::Result<Tokens> {
+    let name = &spec.name;
+    let ty = value_type(spec);
+    Ok(match &spec.presence {
+        Presence::Always => write_value(ty, spec.len, quote!(&self.#name)),
+        Presence::Nullable => quote! {
+            match &self.#name {
+                Some(value) => buf.str(value),
+                None => buf.bool(false),
+            }
+        },
+        Presence::Optional | Presence::If { .. } => {
+            let write = write_value(ty, spec.len, quote!(value));
+            quote! {
+                if let Some(value) = &self.#name {
+                    #write
+                }
+            }
+        },
+    })
+}
+
+fn deserialize_field(spec: &FieldSpec) -> syn::Result<Tokens> {
+    let name = &spec.name;
+    let ty = value_type(spec);
+    let read = read_value(ty, spec.len);
+    Ok(match &spec.presence {
+        Presence::Always => quote!(let #name = #read?;),
+        Presence::Nullable => quote!(let #name = reader.nullable_str()?.map(str::to_string);),
+        Presence::Optional => quote!(let #name = #read;),
+        Presence::If { flag, negate } => {
+            let not = negate.then(|| quote!(!));
+            quote!(let #name = if #not #flag { Some(#read?) } else { None };)
+        },
+    })
+}
+
+fn json_field(spec: &FieldSpec) -> syn::Result<Tokens> {
+    let name = &spec.name;
+    let key = name.to_string();
+    let ty = value_type(spec);
+    Ok(match &spec.presence {
+        Presence::Always => {
+            let json = json_value(ty, quote!(&self.#name));
+            quote!(map.insert(#key.to_string(), #json);)
+        },
+        _ => {
+            let json = json_value(ty, quote!(value));
+            quote! {
+                map.insert(#key.to_string(), match &self.#name {
+                    Some(value) => #json,
+                    None => serde_json::Value::Null,
+                });
+            }
+        },
+    })
+}
+
+fn from_json_field(spec: &FieldSpec) -> syn::Result<Tokens> {
+    let name = &spec.name;
+    let key = name.to_string();
+    let ty = value_type(spec);
+    let read = from_json_value(ty, quote!(value));
+    let read = match spec.presence {
+        Presence::Always => read,
+        _ => quote! {
+            if value.is_null() { Some(None) } else { (#read).map(Some) }
+        },
+    };
+    let fallback = if spec.default { quote!(.unwrap_or_default()) } else { quote!(?) };
+    Ok(quote! {
+        let #name = {
+            let value = &value[#key];
+            #read
+        }#fallback;
+    })
+}
@@ -0,0 +1,16 @@
+//! Rewrites the `Call` packet section of SPEC.md
+//! from schema/calls.txt.
+use std::error::Error;
+#[path = "../codegen.rs"]
+mod codegen;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let schema = std::fs::read_to_string("schema/calls.txt")?;
+    let calls = codegen::parse(&schema)?;
+    let spec = std::fs::read_to_string("SPEC.md")?;
+    let spec = codegen::splice_spec(&spec, &codegen::spec(&calls, "v135"))
+        .ok_or_else(|| format!("SPEC.md needs {} and {} markers", codegen::SPEC_BEGIN, codegen::SPEC_END))?;
+    std::fs::write("SPEC.md", spec)?;
+    eprintln!("wrote {} calls to SPEC.md", calls.len());
+    Ok(())
+}
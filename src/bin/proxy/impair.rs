@@ -0,0 +1,179 @@
+use std::time::Duration;
+use rand::Rng;
+use tokio::time::Instant;
+use crate::minet::{Direction, Transport};
+
+/// Network conditions to simulate
+/// in one direction.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Impairment {
+    /// Added to every message.
+    pub latency: Duration,
+    /// Messages are delayed by up to this much
+    /// more or less than `latency`.
+    pub jitter: Duration,
+    /// The link speed in bytes per second,
+    /// or 0 for no limit.
+    pub bandwidth: u64,
+    /// Chance that a UDP message is lost.
+    pub loss: f64,
+    /// Chance that a UDP message is sent twice.
+    pub duplicate: f64,
+    /// Chance that a UDP message is held back
+    /// long enough to arrive after later ones.
+    pub reorder: f64,
+}
+
+impl Impairment {
+    /// Applies a `key=value` setting.
+    /// Durations are in milliseconds.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let err = |e: &dyn std::fmt::Display| format!("{}={}: {}", key, value, e);
+        let ms = |value: &str| value.trim_end_matches("ms").parse::<u64>().map(Duration::from_millis);
+        let chance = |value: &str| value.parse::<f64>().map_err(|e| err(&e)).and_then(|p| {
+            if (0.0..=1.0).contains(&p) { Ok(p) } else { Err(err(&"must be between 0 and 1")) }
+        });
+        match key {
+            "latency" => self.latency = ms(value).map_err(|e| err(&e))?,
+            "jitter" => self.jitter = ms(value).map_err(|e| err(&e))?,
+            "bandwidth" => self.bandwidth = value.parse().map_err(|e| err(&e))?,
+            "loss" => self.loss = chance(value)?,
+            "duplicate" => self.duplicate = chance(value)?,
+            "reorder" => self.reorder = chance(value)?,
+            _ => return Err(format!("unknown setting: {}", key)),
+        }
+        Ok(())
+    }
+}
+
+/// Decides when, and how many times,
+/// each message gets through.
+#[derive(Debug)]
+pub struct Impairer {
+    client_to_server: Impairment,
+    server_to_client: Impairment,
+    /// When each direction's link
+    /// finishes sending what is queued.
+    client_to_server_free: Instant,
+    server_to_client_free: Instant,
+}
+
+impl Default for Impairer {
+    fn default() -> Self {
+        Self {
+            client_to_server: Impairment::default(),
+            server_to_client: Impairment::default(),
+            client_to_server_free: Instant::now(),
+            server_to_client_free: Instant::now(),
+        }
+    }
+}
+
+impl Impairer {
+    pub fn get(&self, direction: Direction) -> &Impairment {
+        match direction {
+            Direction::ClientToServer => &self.client_to_server,
+            Direction::ServerToClient => &self.server_to_client,
+        }
+    }
+
+    pub fn get_mut(&mut self, direction: Direction) -> &mut Impairment {
+        match direction {
+            Direction::ClientToServer => &mut self.client_to_server,
+            Direction::ServerToClient => &mut self.server_to_client,
+        }
+    }
+
+    /// Applies a command of the form
+    /// `<client->server|server->client|both> key=value...`,
+    /// or `reset` to clear every setting.
+    pub fn command(&mut self, line: &str) -> Result<(), String> {
+        let mut words = line.split_whitespace();
+        let directions = match words.next() {
+            Some("reset") => {
+                self.client_to_server = Impairment::default();
+                self.server_to_client = Impairment::default();
+                return Ok(())
+            },
+            Some("both") => vec![Direction::ClientToServer, Direction::ServerToClient],
+            Some(direction) => vec![direction.parse()?],
+            None => return Err("empty command".to_string()),
+        };
+        for setting in words {
+            let (key, value) = setting.split_once('=').ok_or_else(|| format!("expected key=value: {}", setting))?;
+            for direction in &directions {
+                self.get_mut(*direction).set(key, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the delay of every copy of a message
+    /// of `len` bytes that should be sent.
+    /// An empty list means the message is lost.
+    ///
+    /// TCP messages are always sent exactly once.
+    pub fn schedule<R: Rng>(&mut self, transport: Transport, direction: Direction, len: usize, now: Instant, rng: &mut R) -> Vec<Duration> {
+        let (settings, free) = match direction {
+            Direction::ClientToServer => (&self.client_to_server, &mut self.client_to_server_free),
+            Direction::ServerToClient => (&self.server_to_client, &mut self.server_to_client_free),
+        };
+
+        // bandwidth: the message waits for everything queued ahead of it
+        let mut queued = Duration::ZERO;
+        if settings.bandwidth > 0 {
+            let start = (*free).max(now);
+            *free = start + Duration::from_secs_f64(len as f64 / settings.bandwidth as f64);
+            queued = *free - now;
+        }
+
+        let delay = |rng: &mut R| {
+            let jitter = settings.jitter.as_secs_f64();
+            let offset = if jitter > 0.0 { rng.gen_range(-jitter..=jitter) } else { 0.0 };
+            queued + Duration::from_secs_f64((settings.latency.as_secs_f64() + offset).max(0.0))
+        };
+
+        if transport == Transport::Tcp {
+            return vec![delay(rng)];
+        }
+
+        if rng.gen_bool(settings.loss) {
+            return vec![];
+        }
+        let mut delays = vec![delay(rng)];
+        if rng.gen_bool(settings.duplicate) {
+            delays.push(delay(rng));
+        }
+        if rng.gen_bool(settings.reorder) {
+            delays[0] += (settings.jitter * 2).max(Duration::from_millis(20));
+        }
+        delays
+    }
+}
+
+#[test]
+fn test_impairer() {
+    use rand::SeedableRng;
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let now = Instant::now();
+    let mut impairer = Impairer::default();
+
+    impairer.command("client->server latency=100 loss=1 duplicate=1").unwrap();
+    assert!(impairer.schedule(Transport::Udp, Direction::ClientToServer, 10, now, &mut rng).is_empty());
+    assert_eq!(impairer.schedule(Transport::Tcp, Direction::ClientToServer, 10, now, &mut rng), vec![Duration::from_millis(100)]);
+    assert_eq!(impairer.schedule(Transport::Udp, Direction::ServerToClient, 10, now, &mut rng), vec![Duration::ZERO]);
+
+    impairer.command("both loss=0 latency=0ms").unwrap();
+    assert_eq!(impairer.schedule(Transport::Udp, Direction::ClientToServer, 10, now, &mut rng).len(), 2);
+
+    impairer.command("reset").unwrap();
+    impairer.command("server->client bandwidth=1000 jitter=10").unwrap();
+    let first = impairer.schedule(Transport::Tcp, Direction::ServerToClient, 500, now, &mut rng)[0];
+    let second = impairer.schedule(Transport::Tcp, Direction::ServerToClient, 500, now, &mut rng)[0];
+    assert!(first >= Duration::from_millis(490) && first <= Duration::from_millis(510));
+    assert!(second >= Duration::from_millis(990) && second <= Duration::from_millis(1010));
+
+    assert!(impairer.command("both loss=2").is_err());
+    assert!(impairer.command("sideways latency=1").is_err());
+    assert!(impairer.command("both speed=1").is_err());
+}
@@ -0,0 +1,446 @@
+use std::{net::SocketAddr, error::Error, fmt::Display, fs::File, io::BufWriter, sync::{Arc, Mutex}, time::{Duration, SystemTime, UNIX_EPOCH}};
+use async_std::{net::{TcpStream, TcpListener, UdpSocket}, io::{ReadExt, WriteExt}};
+use tokio::{sync::mpsc, time::Instant};
+// Dead code in minet is linted through the bot; the proxy uses a part of it.
+#[allow(dead_code)]
+#[path = "../../minet/mod.rs"]
+mod minet;
+mod rules;
+mod impair;
+mod stats;
+
+#[derive(Debug)]
+struct Err<E: Error>(String, E);
+
+impl<E: Error> Display for Err<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.0, self.1)
+    }
+}
+
+impl<E: Error> Error for Err<E> {}
+
+use minet::{Transport, Direction, Data};
+use rules::{RuleSet, Output};
+use impair::Impairer;
+use stats::Stats;
+use minet::entity::{EntityMapping, EntityTable};
+use minet::world::World;
+use minet::client::Client;
+use minet::content::ContentRegistry;
+use minet::protocol::Protocol;
+
+fn print_data(protocol: &Arc<Protocol>, transport: Transport, direction: Direction, raw_data: &[u8]) {
+    let data = minet::parse_udp(raw_data, protocol);
+    println!("{} {}", transport, direction);
+    println!("\traw: {}", raw_data.iter().map(|s| format!("{:02x} ", s)).collect::<String>());
+    if let Some(data) = data {
+        println!("\tparsed: {}", format!("{:#?}", data).replace("\n", "\n\t"));
+    }
+}
+
+/// One message as a JSON object. `fields` holds the
+/// decoded fields of packets and framework messages
+/// alike; `compressed` is null for framework messages.
+fn message_json(protocol: &Arc<Protocol>, transport: Transport, direction: Direction, raw_data: &[u8], time: u64) -> serde_json::Value {
+    let (id, name, compressed, fields) = if raw_data.first() == Some(&0xfe) {
+        match minet::FrameworkMessage::deserialize(raw_data) {
+            Some(msg) => (Some(msg.id()), Some(msg.inner().name()), None, msg.inner().json()),
+            None => (raw_data.get(1).copied(), None, None, serde_json::Value::Null),
+        }
+    } else {
+        match minet::PacketMessage::deserialize_with(raw_data, protocol) {
+            Some(msg) => (Some(msg.id()), Some(msg.packet().name()), Some(msg.compressed), msg.packet().json()),
+            None => (raw_data.first().copied(), None, None, serde_json::Value::Null),
+        }
+    };
+
+    serde_json::json!({
+        "time": time,
+        "protocol": transport.to_string(),
+        "direction": direction.to_string(),
+        "id": id,
+        "name": name,
+        "compressed": compressed,
+        "fields": fields,
+        "raw": raw_data.iter().map(|s| format!("{:02x}", s)).collect::<String>(),
+    })
+}
+
+/// Prints a message as a single line of JSON.
+fn print_json(protocol: &Arc<Protocol>, transport: Transport, direction: Direction, raw_data: &[u8]) {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+    println!("{}", message_json(protocol, transport, direction, raw_data, time as u64));
+}
+
+/// Spawns a task that writes messages to `stream`
+/// once they are due, in the order they were queued.
+fn spawn_tcp_writer(mut stream: TcpStream, name: &'static str) -> mpsc::UnboundedSender<(Instant, Vec<u8>)> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<(Instant, Vec<u8>)>();
+    tokio::spawn(async move {
+        while let Some((due, data)) = rx.recv().await {
+            tokio::time::sleep_until(due).await;
+            if let Result::Err(e) = stream.write_all(&data).await {
+                eprintln!("{}", Err(format!("{} write", name), e));
+                break
+            }
+        }
+    });
+    tx
+}
+
+/// Reads impairment commands from stdin
+/// so that conditions can be changed mid-session.
+/// `entities` lists the units and players seen so far,
+/// `players` who is online, `cores` what each
+/// team's cores hold, and `state` the wave and game state.
+fn spawn_commands(impairer: Arc<Mutex<Impairer>>, client: Arc<Mutex<Client>>) {
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Result::Err(_) => break,
+            };
+            if line.trim() == "entities" {
+                let client = client.lock().unwrap();
+                print_entities(&client.entities, &client.content);
+                continue
+            }
+            if line.trim() == "players" {
+                for p in client.lock().unwrap().players() {
+                    eprintln!("{:>6} {:<24} team {}{}{}", p.id, p.name, p.team, if p.admin { ", admin" } else { "" },
+                        p.uuid.as_ref().map(|uuid| format!(", {}", uuid)).unwrap_or_default());
+                }
+                continue
+            }
+            if line.trim() == "cores" {
+                let client = client.lock().unwrap();
+                print_cores(&client.world, &client.content);
+                continue
+            }
+            if line.trim() == "state" {
+                let state = &client.lock().unwrap().state;
+                eprintln!("wave {} in {:.0}s, {} enemies, {} tps{}{}", state.wave, state.wave_countdown_secs(),
+                    state.enemies, state.tps, if state.paused { ", paused" } else { "" }, if state.game_over { ", game over" } else { "" });
+                if state.rules_updated.is_some() {
+                    eprintln!("{}, banned: {:?}", if state.rules.pvp { "pvp" } else { "no pvp" }, state.rules.banned_blocks);
+                }
+                continue
+            }
+            let mut impairer = impairer.lock().unwrap();
+            if line.trim() == "show" {
+                eprintln!("client -> server: {:?}", impairer.get(Direction::ClientToServer));
+                eprintln!("server -> client: {:?}", impairer.get(Direction::ServerToClient));
+            } else if let Result::Err(e) = impairer.command(&line) {
+                eprintln!("impair: {}", e);
+            }
+        }
+    });
+}
+
+/// The client state kept for a server speaking `protocol`.
+/// Without an entity mapping for its build, entity
+/// snapshots are forwarded but not decoded.
+fn new_client(protocol: &Arc<Protocol>, content: &Arc<ContentRegistry>) -> Client {
+    let mapping = EntityMapping::for_protocol(protocol).unwrap_or_else(|e| {
+        eprintln!("{}, entity snapshots won't be decoded", e);
+        Default::default()
+    });
+    Client::new(protocol.clone(), content.clone(), mapping)
+}
+
+fn print_entities(entities: &EntityTable, content: &ContentRegistry) {
+    let mut entities: Vec<_> = entities.iter().collect();
+    entities.sort_by_key(|e| e.id);
+    for e in entities {
+        let what = match (&e.name, e.unit_type) {
+            (Some(name), _) => format!("player {:?}", name),
+            (None, Some(ty)) => content.units.get(ty).map_or(format!("unit type {}", ty), |u| u.name.clone()),
+            (None, None) => e.class.clone(),
+        };
+        eprintln!("{:>6} {:<20} team {} at ({:.1}, {:.1}) health {:.0} controller {:?}",
+            e.id, what, e.team, e.x / 8.0, e.y / 8.0, e.health, e.controller);
+    }
+}
+
+fn print_cores(world: &World, content: &ContentRegistry) {
+    let mut teams: Vec<u8> = world.buildings().map(|b| b.team).collect();
+    teams.sort();
+    teams.dedup();
+    for team in teams {
+        let items = world.core_items(content, team);
+        if items.is_empty() {
+            continue
+        }
+        let items: Vec<String> = items.iter()
+            .map(|(id, amount)| format!("{} {}", amount, content.items.get(*id).map_or(format!("item {}", id), |i| i.name.clone())))
+            .collect();
+        eprintln!("team {}: {}", team, items.join(", "));
+    }
+}
+
+/// Prints a summary of the traffic
+/// to stderr every `interval`.
+fn spawn_stats_summary(stats: Arc<Mutex<Stats>>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            eprint!("{}", stats.lock().unwrap().summary());
+        }
+    });
+}
+
+/// Serves the traffic statistics to Prometheus
+/// over plain HTTP on `addr`.
+async fn spawn_metrics_server(stats: Arc<Mutex<Stats>>, addr: SocketAddr) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    eprintln!("serving metrics on http://{}/metrics", addr);
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let stats = stats.clone();
+            tokio::spawn(async move {
+                // every path gets the metrics, so the request itself is irrelevant
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).await;
+                let body = stats.lock().unwrap().prometheus();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(), body);
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Where the proxy sends messages
+/// once the rules have been applied.
+struct Peers {
+    tcp_server: mpsc::UnboundedSender<(Instant, Vec<u8>)>,
+    tcp_client: mpsc::UnboundedSender<(Instant, Vec<u8>)>,
+    udp_socket: Arc<UdpSocket>,
+    server_addr: SocketAddr,
+    client_addr: SocketAddr,
+}
+
+impl Peers {
+    fn send(&self, transport: Transport, output: Output) {
+        let due = Instant::now() + output.delay;
+        match transport {
+            Transport::Tcp => {
+                let tx = match output.direction {
+                    Direction::ClientToServer => &self.tcp_server,
+                    Direction::ServerToClient => &self.tcp_client,
+                };
+                // a closed channel means the writer already reported its error
+                let _ = tx.send((due, minet::frame_tcp(&output.data)));
+            },
+            Transport::Udp => {
+                let socket = self.udp_socket.clone();
+                let addr = match output.direction {
+                    Direction::ClientToServer => self.server_addr,
+                    Direction::ServerToClient => self.client_addr,
+                };
+                tokio::spawn(async move {
+                    tokio::time::sleep_until(due).await;
+                    if let Result::Err(e) = socket.send_to(&output.data, addr).await {
+                        eprintln!("{}", Err(format!("udp write to {}", addr), e));
+                    }
+                });
+            },
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let mut capture = None;
+    let mut json = false;
+    let mut rules = RuleSet::default();
+    let mut protocol = None;
+    let mut content = None;
+    let impairer = Arc::new(Mutex::new(Impairer::default()));
+    let stats = Arc::new(Mutex::new(Stats::default()));
+    let mut stats_interval = None;
+    let mut metrics_addr: Option<SocketAddr> = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--capture" => {
+                let path = args.next().ok_or("--capture requires a file")?;
+                let file = File::create(&path).map_err(|e| Err(format!("create {}", path), e))?;
+                capture = Some(minet::CaptureWriter::new(BufWriter::new(file))?);
+            },
+            "--json" => json = true,
+            "--rules" => {
+                let path = args.next().ok_or("--rules requires a file")?;
+                rules = RuleSet::load(&path).map_err(|e| format!("{}: {}", path, e))?;
+                eprintln!("loaded {} rules from {}", rules.rules.len(), path);
+            },
+            "--impair" => {
+                let command = args.next().ok_or("--impair requires a setting")?;
+                impairer.lock().unwrap().command(&command)?;
+            },
+            "--protocol" => {
+                let loaded = Protocol::load(&args.next().ok_or("--protocol requires a name or file")?)?;
+                eprintln!("decoding with protocol {}", loaded.name);
+                protocol = Some(loaded);
+            },
+            "--stats" => {
+                let secs: u64 = args.next().ok_or("--stats requires an interval in seconds")?.parse()?;
+                stats_interval = Some(Duration::from_secs(secs.max(1)));
+            },
+            "--content" => {
+                let path = args.next().ok_or("--content requires a file")?;
+                content = Some(Arc::new(ContentRegistry::load(&path)?));
+            },
+            "--metrics" => metrics_addr = Some(args.next().ok_or("--metrics requires an address")?.parse()?),
+            _ => return Result::Err(format!("unknown argument: {}", arg).into()),
+        }
+    }
+    let protocol_fixed = protocol.is_some();
+    let mut protocol = protocol.unwrap_or_else(Protocol::fallback);
+    stats.lock().unwrap().protocol = protocol.clone();
+    let content = content.unwrap_or_else(|| {
+        eprintln!("no --content given, printing content ids without names");
+        Default::default()
+    });
+    let client = Arc::new(Mutex::new(new_client(&protocol, &content)));
+    let mut record = |transport, direction, data: &[u8]| -> std::io::Result<()> {
+        match capture.as_mut() {
+            Some(capture) => capture.record(transport, direction, data),
+            None => Ok(()),
+        }
+    };
+    let print = |protocol: &Arc<Protocol>, transport, direction, data: &[u8]| {
+        if json {
+            print_json(protocol, transport, direction, data)
+        } else {
+            print_data(protocol, transport, direction, data)
+        }
+    };
+
+    if let Some(interval) = stats_interval {
+        spawn_stats_summary(stats.clone(), interval);
+    }
+    if let Some(addr) = metrics_addr {
+        spawn_metrics_server(stats.clone(), addr).await?;
+    }
+
+    let server_addr: SocketAddr = "127.0.0.1:6567".parse().unwrap();
+    let udp_socket = Arc::new(UdpSocket::bind("0.0.0.0:5001").await?);
+    let tcp_socket = TcpListener::bind("0.0.0.0:5001").await?;
+    let (mut tcp_client_stream, client_addr) = tcp_socket.accept().await?;
+    eprintln!("client connected from {}", client_addr);
+    let mut tcp_server_stream = TcpStream::connect(server_addr).await?;
+
+    let peers = Peers {
+        tcp_server: spawn_tcp_writer(tcp_server_stream.clone(), "tcp server"),
+        tcp_client: spawn_tcp_writer(tcp_client_stream.clone(), "tcp client"),
+        udp_socket: udp_socket.clone(),
+        server_addr, client_addr,
+    };
+    spawn_commands(impairer.clone(), client.clone());
+    let mut rng = rand::thread_rng();
+    let mut forward = |transport, direction, data: &[u8]| {
+        // follow the client's build unless told otherwise
+        if !protocol_fixed && direction == Direction::ClientToServer {
+            if let Some(connect) = minet::PacketMessage::deserialize_with(data, &protocol).as_ref().and_then(|m| m.downcast::<minet::ConnectPacket>()) {
+                match Protocol::for_build(connect.version_build as i32) {
+                    Some(found) => {
+                        protocol = found;
+                        eprintln!("client is build {}, decoding with protocol {}", connect.version_build, protocol.name);
+                        // nothing has come from the server yet
+                        *client.lock().unwrap() = new_client(&protocol, &content);
+                        stats.lock().unwrap().protocol = protocol.clone();
+                    },
+                    None => eprintln!("no built-in protocol for build {}, still decoding with {}; pass --protocol to pick one", connect.version_build, protocol.name),
+                }
+            }
+        }
+        if direction == Direction::ServerToClient {
+            if let Some(msg) = minet::PacketMessage::deserialize_with(data, &protocol) {
+                if let Result::Err(e) = client.lock().unwrap().handle(&msg) {
+                    eprintln!("{}", e);
+                }
+            }
+        }
+        print(&protocol, transport, direction, data);
+        stats.lock().unwrap().record(transport, direction, data);
+        for output in rules.apply(&protocol, transport, direction, data) {
+            let delays = impairer.lock().unwrap().schedule(transport, output.direction, output.data.len(), Instant::now(), &mut rng);
+            for delay in delays {
+                peers.send(transport, Output { delay: output.delay + delay, ..output.clone() });
+            }
+        }
+    };
+
+    let mut buf = [0u8; 16384];
+    let mut buf_tcp = [0u8; 16384];
+    let mut buf_tcp2 = [0u8; 16384];
+    let mut client_frames = minet::FrameBuffer::new();
+    let mut server_frames = minet::FrameBuffer::new();
+    loop {
+        tokio::select! {
+            tcp_res = tcp_client_stream.read(&mut buf_tcp) => {
+                let len = tcp_res?;
+                if len == 0 {
+                    break
+                }
+                record(Transport::Tcp, Direction::ClientToServer, &buf_tcp[..len])?;
+
+                client_frames.push(&buf_tcp[..len]);
+                while let Some(message_data) = client_frames.next_frame() {
+                    eprintln!("tcp message from client");
+                    forward(Transport::Tcp, Direction::ClientToServer, &message_data);
+                }
+            },
+            tcp_res2 = tcp_server_stream.read(&mut buf_tcp2) => {
+                let len = tcp_res2?;
+                if len == 0 {
+                    break
+                }
+                record(Transport::Tcp, Direction::ServerToClient, &buf_tcp2[..len])?;
+
+                server_frames.push(&buf_tcp2[..len]);
+                while let Some(message_data) = server_frames.next_frame() {
+                    eprintln!("tcp message from server");
+                    forward(Transport::Tcp, Direction::ServerToClient, &message_data);
+                }
+            },
+            udp_res = udp_socket.recv_from(&mut buf) => {
+                let (len, udp_addr) = udp_res?;
+                if udp_addr == server_addr {
+//                    eprintln!("udp message from {}, assuming to be server", udp_addr);
+                    record(Transport::Udp, Direction::ServerToClient, &buf[..len])?;
+                    forward(Transport::Udp, Direction::ServerToClient, &buf[..len]);
+                } else { // message from client
+//                    eprintln!("udp message from {}, assuming to be client", udp_addr);
+                    record(Transport::Udp, Direction::ClientToServer, &buf[..len])?;
+                    forward(Transport::Udp, Direction::ClientToServer, &buf[..len]);
+                }
+            }
+        }
+    }
+
+    eprintln!("client disconnected");
+    Ok(())
+}
+
+#[test]
+fn test_message_json() {
+    let protocol = Protocol::fallback();
+    let mut chat = minet::Writer::new();
+    minet::PacketMessage::with_protocol(&protocol, minet::SendChatMessageCallPacket { message: "hi".to_string() }, false).unwrap().serialize(&mut chat);
+    let packet = message_json(&protocol, Transport::Tcp, Direction::ClientToServer, &chat.0, 0);
+    assert_eq!(packet["name"], "SendChatMessageCallPacket");
+    assert_eq!(packet["compressed"], false);
+    assert_eq!(packet["fields"]["message"], "hi");
+
+    let framework = message_json(&protocol, Transport::Udp, Direction::ClientToServer, &[0xfe, 0x03, 0, 0, 0, 5], 0);
+    assert_eq!(framework["name"], "RegisterUDP");
+    assert!(framework["compressed"].is_null());
+    assert_eq!(framework["fields"]["id"], 5);
+}
@@ -0,0 +1,357 @@
+use std::{error::Error, time::Duration};
+use serde_json::{Map, Value};
+use std::sync::Arc;
+use crate::minet::{self, Data, Direction, Transport};
+use crate::minet::protocol::Protocol;
+
+/// What to do with a message
+/// that matched a rule.
+#[derive(Clone, Debug)]
+pub enum Action {
+    /// Don't forward the message.
+    Drop,
+    /// Overwrite the given fields of the packet.
+    Modify(Map<String, Value>),
+    /// Hold the message back before forwarding it.
+    Delay(Duration),
+    /// Send an extra message after this one.
+    Inject { direction: Direction, message: Injection },
+}
+
+/// A message to inject: raw bytes, or a packet
+/// built with the session's protocol when it is sent.
+#[derive(Clone, Debug)]
+pub enum Injection {
+    Raw(Vec<u8>),
+    Packet { name: String, fields: Value },
+}
+
+impl Injection {
+    fn build(&self, protocol: &Arc<Protocol>) -> Option<Vec<u8>> {
+        match self {
+            Injection::Raw(data) => Some(data.clone()),
+            Injection::Packet { name, fields } => {
+                let msg = minet::PacketMessage::from_json(protocol, name, fields, false)?;
+                let mut buf = minet::Writer::new();
+                msg.serialize(&mut buf);
+                Some(buf.0)
+            },
+        }
+    }
+}
+
+/// A filter on messages
+/// and the actions to take on a match.
+///
+/// Every condition that is set must hold.
+#[derive(Clone, Debug, Default)]
+pub struct Rule {
+    pub direction: Option<Direction>,
+    pub transport: Option<Transport>,
+    /// The type name of the packet, e.g. `ConnectPacket`.
+    pub packet: Option<String>,
+    /// The id of a packet message.
+    pub id: Option<u8>,
+    /// Fields that must equal the given values.
+    pub fields: Map<String, Value>,
+    /// Fields that must contain one of the
+    /// given words, ignoring case.
+    pub contains: Vec<(String, Vec<String>)>,
+    pub actions: Vec<Action>,
+}
+
+/// A message to be sent
+/// once the rules have been applied.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Output {
+    pub direction: Direction,
+    pub delay: Duration,
+    /// The message, without a TCP length prefix.
+    pub data: Vec<u8>,
+}
+
+/// What the rules need to know about
+/// a message that decoded successfully.
+struct Decoded {
+    name: &'static str,
+    fields: Value,
+    packet: Option<minet::PacketMessage>,
+}
+
+fn decode(protocol: &Arc<Protocol>, data: &[u8]) -> Option<Decoded> {
+    if data.first() == Some(&0xfe) {
+        let msg = minet::FrameworkMessage::deserialize(data)?;
+        Some(Decoded { name: msg.inner().name(), fields: msg.inner().json(), packet: None })
+    } else {
+        let msg = minet::PacketMessage::deserialize_with(data, protocol)?;
+        Some(Decoded { name: msg.packet().name(), fields: msg.packet().json(), packet: Some(msg) })
+    }
+}
+
+/// Returns the id of a packet message
+/// without decoding the rest of it.
+fn packet_id(data: &[u8]) -> Option<u8> {
+    data.first().copied().filter(|id| *id != 0xfe)
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    let s: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+    if !s.len().is_multiple_of(2) {
+        return Err(format!("odd number of hex digits: {}", s));
+    }
+    (0..s.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|e| format!("{}: {}", s, e)))
+        .collect()
+}
+
+fn parse_action(value: &Value) -> Result<Action, String> {
+    if value.as_str() == Some("drop") {
+        return Ok(Action::Drop);
+    }
+    let obj = value.as_object().ok_or_else(|| format!("invalid action: {}", value))?;
+    if let Some(fields) = obj.get("modify") {
+        let fields = fields.as_object().ok_or("modify takes an object of fields")?;
+        Ok(Action::Modify(fields.clone()))
+    } else if let Some(ms) = obj.get("delay") {
+        let ms = ms.as_u64().ok_or("delay takes milliseconds")?;
+        Ok(Action::Delay(Duration::from_millis(ms)))
+    } else if let Some(inject) = obj.get("inject") {
+        let direction = inject["direction"].as_str().ok_or("inject needs a direction")?.parse()?;
+        let message = if let Some(hex) = inject["hex"].as_str() {
+            Injection::Raw(parse_hex(hex)?)
+        } else {
+            let name = inject["packet"].as_str().ok_or("inject needs hex or a packet")?;
+            let message = Injection::Packet { name: name.to_string(), fields: inject["fields"].clone() };
+            // the session's protocol isn't known yet, so any build will do
+            if !Protocol::builtin().iter().any(|p| message.build(p).is_some()) {
+                return Err(format!("cannot build {} from {}", name, inject["fields"]));
+            }
+            message
+        };
+        Ok(Action::Inject { direction, message })
+    } else {
+        Err(format!("invalid action: {}", value))
+    }
+}
+
+impl Rule {
+    /// Parses a single rule from the config file.
+    ///
+    /// ```json
+    /// {
+    ///     "direction": "client -> server",
+    ///     "packet": "SendChatMessageCallPacket",
+    ///     "contains": { "message": ["griefer"] },
+    ///     "action": "drop"
+    /// }
+    /// ```
+    pub fn from_json(value: &Value) -> Result<Self, String> {
+        let obj = value.as_object().ok_or_else(|| format!("rule is not an object: {}", value))?;
+        let mut rule = Rule::default();
+        for (key, value) in obj {
+            match key.as_str() {
+                "direction" => rule.direction = Some(value.as_str().ok_or("direction must be a string")?.parse()?),
+                "transport" => rule.transport = Some(value.as_str().ok_or("transport must be a string")?.parse()?),
+                "packet" => rule.packet = Some(value.as_str().ok_or("packet must be a string")?.to_string()),
+                "id" => rule.id = Some(value.as_u64().filter(|id| *id <= 0xff).ok_or("id must be a byte")? as u8),
+                "fields" => rule.fields = value.as_object().ok_or("fields must be an object")?.clone(),
+                "contains" => {
+                    for (field, words) in value.as_object().ok_or("contains must be an object")? {
+                        let words = words.as_array().ok_or("contains takes lists of words")?
+                            .iter()
+                            .map(|w| w.as_str().map(str::to_lowercase).ok_or("words must be strings"))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        rule.contains.push((field.clone(), words));
+                    }
+                },
+                "action" => {
+                    rule.actions = match value.as_array() {
+                        Some(actions) => actions.iter().map(parse_action).collect::<Result<_, _>>()?,
+                        None => vec![parse_action(value)?],
+                    }
+                },
+                _ => return Err(format!("unknown rule key: {}", key)),
+            }
+        }
+        Ok(rule)
+    }
+
+    fn matches(&self, transport: Transport, direction: Direction, data: &[u8], decoded: Option<&Decoded>) -> bool {
+        if self.transport.is_some_and(|t| t != transport)
+            || self.direction.is_some_and(|d| d != direction)
+            || self.id.is_some_and(|id| Some(id) != packet_id(data)) {
+            return false;
+        }
+
+        let needs_decoding = self.packet.is_some() || !self.fields.is_empty() || !self.contains.is_empty();
+        if !needs_decoding {
+            return true;
+        }
+        let decoded = match decoded {
+            Some(decoded) => decoded,
+            None => return false,
+        };
+
+        self.packet.as_ref().is_none_or(|p| p == decoded.name)
+            && self.fields.iter().all(|(k, v)| &decoded.fields[k] == v)
+            && self.contains.iter().all(|(k, words)| {
+                let text = decoded.fields[k].as_str().unwrap_or("").to_lowercase();
+                words.iter().any(|w| text.contains(w.as_str()))
+            })
+    }
+}
+
+/// An ordered list of rules.
+///
+/// Every rule that matches is applied in turn,
+/// and later rules see the message as
+/// modified by earlier ones.
+#[derive(Clone, Debug, Default)]
+pub struct RuleSet {
+    pub rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    /// Parses a config file holding
+    /// a JSON array of rules.
+    pub fn from_json(config: &str) -> Result<Self, Box<dyn Error>> {
+        let value: Value = serde_json::from_str(config)?;
+        let rules = value.as_array().ok_or("rules config must be an array")?
+            .iter()
+            .map(Rule::from_json)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    /// Runs a message laid out by `protocol` through
+    /// the rules and returns what should be sent in its place.
+    pub fn apply(&self, protocol: &Arc<Protocol>, transport: Transport, direction: Direction, data: &[u8]) -> Vec<Output> {
+        let mut data = data.to_vec();
+        let mut delay = Duration::ZERO;
+        let mut dropped = false;
+        let mut injected = Vec::new();
+
+        let mut decoded = decode(protocol, &data);
+        for rule in &self.rules {
+            if !rule.matches(transport, direction, &data, decoded.as_ref()) {
+                continue;
+            }
+
+            for action in &rule.actions {
+                match action {
+                    Action::Drop => dropped = true,
+                    Action::Delay(d) => delay += *d,
+                    Action::Inject { direction, message } => match message.build(protocol) {
+                        Some(data) => injected.push((*direction, data)),
+                        None => eprintln!("rules: cannot build {:?} with protocol {}", message, protocol.name),
+                    },
+                    Action::Modify(changes) => {
+                        let modified = decoded.as_ref()
+                            .and_then(|d| d.packet.as_ref())
+                            .and_then(|msg| {
+                                let mut fields = msg.packet().json();
+                                for (k, v) in changes {
+                                    fields[k] = v.clone();
+                                }
+                                msg.with_json(&fields)
+                            });
+                        match modified {
+                            Some(msg) => {
+                                let mut buf = minet::Writer::new();
+                                msg.serialize(&mut buf);
+                                data = buf.0;
+                                decoded = decode(protocol, &data);
+                            },
+                            None => eprintln!("rules: cannot modify message {:02x?}", packet_id(&data)),
+                        }
+                    },
+                }
+            }
+            if dropped {
+                break;
+            }
+        }
+
+        let mut outputs = Vec::new();
+        if !dropped {
+            outputs.push(Output { direction, delay, data });
+        }
+        outputs.extend(injected.into_iter().map(|(direction, data)| Output { direction, delay, data }));
+        outputs
+    }
+}
+
+#[test]
+fn test_rename_player() {
+    let protocol = Protocol::fallback();
+    let rules = RuleSet::from_json(r#"[{
+        "direction": "client->server",
+        "packet": "ConnectPacket",
+        "fields": { "player_name": "allen" },
+        "action": { "modify": { "player_name": "robot" } }
+    }]"#).unwrap();
+
+    let outputs = rules.apply(&protocol, Transport::Tcp, Direction::ClientToServer, minet::CONNECT_MESSAGE);
+    assert_eq!(outputs.len(), 1);
+    let msg = minet::PacketMessage::deserialize(&outputs[0].data).unwrap();
+    assert!(msg.compressed);
+    assert_eq!(msg.packet().json()["player_name"], "robot");
+    assert_eq!(msg.packet().json()["locale"], "en_US");
+
+    let outputs = rules.apply(&protocol, Transport::Tcp, Direction::ServerToClient, minet::CONNECT_MESSAGE);
+    assert_eq!(outputs[0].data, minet::CONNECT_MESSAGE);
+}
+
+#[test]
+fn test_drop_chat() {
+    let protocol = Protocol::fallback();
+    let rules = RuleSet::from_json(r#"[{
+        "packet": "SendChatMessageCallPacket",
+        "contains": { "message": ["griefer", "spam"] },
+        "action": ["drop", { "inject": {
+            "direction": "server->client",
+            "packet": "SendMessageCallPacket",
+            "fields": { "message": "message blocked", "unformatted": "message blocked", "sender": -1 }
+        } }]
+    }]"#).unwrap();
+
+    let chat = |message: &str| {
+        let mut buf = minet::Writer::new();
+        minet::PacketMessage::with_protocol(&protocol, minet::SendChatMessageCallPacket { message: message.to_string() }, false).unwrap().serialize(&mut buf);
+        buf.0
+    };
+
+    let outputs = rules.apply(&protocol, Transport::Tcp, Direction::ClientToServer, &chat("hello"));
+    assert_eq!(outputs.len(), 1);
+    assert_eq!(outputs[0].data, chat("hello"));
+
+    let outputs = rules.apply(&protocol, Transport::Tcp, Direction::ClientToServer, &chat("You GRIEFER"));
+    assert_eq!(outputs.len(), 1);
+    assert_eq!(outputs[0].direction, Direction::ServerToClient);
+    let msg = minet::PacketMessage::deserialize_with(&outputs[0].data, &protocol).unwrap();
+    assert_eq!(msg.packet().json()["message"], "message blocked");
+}
+
+#[test]
+fn test_block_and_delay() {
+    let protocol = Protocol::fallback();
+    let rules = RuleSet::from_json(r#"[
+        { "id": 3, "transport": "udp", "action": "drop" },
+        { "id": 3, "action": { "delay": 250 } },
+        { "packet": "RegisterUDP", "action": { "delay": 50 } }
+    ]"#).unwrap();
+
+    assert!(rules.apply(&protocol, Transport::Udp, Direction::ClientToServer, minet::CONNECT_MESSAGE).is_empty());
+
+    let outputs = rules.apply(&protocol, Transport::Tcp, Direction::ClientToServer, minet::CONNECT_MESSAGE);
+    assert_eq!(outputs[0].delay, Duration::from_millis(250));
+
+    let outputs = rules.apply(&protocol, Transport::Udp, Direction::ClientToServer, &[0xfe, 0x03, 0, 0, 0, 5]);
+    assert_eq!(outputs[0].delay, Duration::from_millis(50));
+
+    assert!(RuleSet::from_json(r#"[{ "action": "explode" }]"#).is_err());
+}
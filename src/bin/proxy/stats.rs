@@ -0,0 +1,237 @@
+use std::{collections::{BTreeMap, VecDeque}, fmt::Write, sync::Arc, time::{Duration, Instant}};
+use crate::minet::{self, Data, Direction, Transport};
+use crate::minet::protocol::Protocol;
+
+/// Identifies one row of the statistics.
+/// Framework messages are keyed by their
+/// own id, separately from packets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Key {
+    direction: u8,
+    transport: u8,
+    framework: bool,
+    id: u8,
+}
+
+impl Key {
+    fn new(transport: Transport, direction: Direction, data: &[u8]) -> Option<Self> {
+        let framework = data.first() == Some(&0xfe);
+        let id = if framework { data.get(1) } else { data.first() };
+        Some(Self {
+            direction: match direction {
+                Direction::ClientToServer => 0,
+                Direction::ServerToClient => 1,
+            },
+            transport: match transport {
+                Transport::Tcp => 0,
+                Transport::Udp => 1,
+            },
+            framework,
+            id: *id?,
+        })
+    }
+
+    fn direction(&self) -> &'static str {
+        if self.direction == 0 { "client_to_server" } else { "server_to_client" }
+    }
+
+    fn transport(&self) -> &'static str {
+        if self.transport == 0 { "tcp" } else { "udp" }
+    }
+
+    fn labels(&self) -> String {
+        format!("direction=\"{}\",protocol=\"{}\",kind=\"{}\",id=\"{}\"",
+            self.direction(), self.transport(),
+            if self.framework { "framework" } else { "packet" }, self.id)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Counter {
+    pub messages: u64,
+    pub bytes: u64,
+    /// Payload bytes of compressed packets as sent,
+    /// and what they decompress to.
+    pub compressed_bytes: u64,
+    pub uncompressed_bytes: u64,
+    /// The name of a framework message, once one has been
+    /// decoded. Packet names depend on the protocol, which
+    /// may change mid-session, so they are looked up instead.
+    pub name: Option<&'static str>,
+    /// `messages` at the time of the last summary.
+    last_messages: u64,
+}
+
+/// How far back snapshot rates look.
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// A Prometheus metric name, its help text,
+/// and how to read it from a counter.
+type Metric = (&'static str, &'static str, fn(&Counter) -> u64);
+
+/// Counts proxied traffic by direction,
+/// transport and packet id.
+pub struct Stats {
+    pub counters: BTreeMap<Key, Counter>,
+    /// The protocol packet names are looked up in.
+    pub protocol: Arc<Protocol>,
+    /// When recent snapshots arrived, by packet name.
+    snapshots: BTreeMap<String, VecDeque<Instant>>,
+    start: Instant,
+    last_summary: Instant,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            counters: BTreeMap::new(),
+            protocol: Protocol::fallback(),
+            snapshots: BTreeMap::new(),
+            start: Instant::now(),
+            last_summary: Instant::now(),
+        }
+    }
+}
+
+impl Stats {
+    /// Counts one message, without its TCP length prefix.
+    pub fn record(&mut self, transport: Transport, direction: Direction, data: &[u8]) {
+        let key = match Key::new(transport, direction, data) {
+            Some(key) => key,
+            None => return,
+        };
+        if let Some(name) = self.protocol.name(key.id).filter(|name| !key.framework && name.ends_with("SnapshotCallPacket")) {
+            let now = Instant::now();
+            let times = self.snapshots.entry(name.to_string()).or_default();
+            times.push_back(now);
+            while times.front().is_some_and(|t| now.duration_since(*t) > RATE_WINDOW) {
+                times.pop_front();
+            }
+        }
+        let counter = self.counters.entry(key).or_default();
+        counter.messages += 1;
+        counter.bytes += data.len() as u64;
+
+        if key.framework {
+            if counter.name.is_none() {
+                counter.name = minet::FrameworkMessage::deserialize(data).map(|m| m.inner().name());
+            }
+            return
+        }
+
+        // id: u8 | length: u16 | compressed: bool | <data>
+        let mut reader = minet::Reader::new(data);
+        let header = (reader.u8(), reader.u16(), reader.bool());
+        if let (Some(_), Some(len), Some(true)) = header {
+            counter.compressed_bytes += reader.bytes_remaining().len() as u64;
+            counter.uncompressed_bytes += len as u64;
+        }
+    }
+
+    /// Snapshots per second of each kind
+    /// over the last `RATE_WINDOW`.
+    pub fn snapshot_rates(&self) -> Vec<(&str, f64)> {
+        let now = Instant::now();
+        self.snapshots.iter()
+            .map(|(name, times)| {
+                let recent = times.iter().filter(|t| now.duration_since(**t) <= RATE_WINDOW).count();
+                (name.as_str(), recent as f64 / RATE_WINDOW.as_secs_f64())
+            })
+            .collect()
+    }
+
+    /// Renders a table of every counter, with the message
+    /// rate since the previous summary.
+    pub fn summary(&mut self) -> String {
+        let now = Instant::now();
+        let window = now.duration_since(self.last_summary).as_secs_f64().max(0.001);
+        self.last_summary = now;
+
+        let mut out = String::new();
+        let _ = writeln!(out, "{:<17} {:<5} {:<28} {:>9} {:>11} {:>8} {:>7}",
+            "direction", "proto", "message", "count", "bytes", "msg/s", "ratio");
+        for (key, counter) in self.counters.iter_mut() {
+            let name = match key.framework {
+                true => counter.name.map_or(format!("framework [{}]", key.id), |name| format!("{} [{}]", name, key.id)),
+                false => self.protocol.name(key.id).map_or(format!("packet [{}]", key.id), |name| format!("{} [{}]", name, key.id)),
+            };
+            let rate = (counter.messages - counter.last_messages) as f64 / window;
+            counter.last_messages = counter.messages;
+            let ratio = if counter.compressed_bytes > 0 {
+                format!("{:.2}", counter.uncompressed_bytes as f64 / counter.compressed_bytes as f64)
+            } else {
+                "-".to_string()
+            };
+            let _ = writeln!(out, "{:<17} {:<5} {:<28} {:>9} {:>11} {:>8.1} {:>7}",
+                key.direction(), key.transport(), name, counter.messages, counter.bytes, rate, ratio);
+        }
+        let rates: Vec<String> = self.snapshot_rates().iter().map(|(name, rate)| format!("{} {:.1}", name, rate)).collect();
+        if !rates.is_empty() {
+            let _ = writeln!(out, "snapshots/s: {}", rates.join(", "));
+        }
+        out
+    }
+
+    /// Renders the counters in the Prometheus text format.
+    pub fn prometheus(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# TYPE minbot_proxy_uptime_seconds gauge");
+        let _ = writeln!(out, "minbot_proxy_uptime_seconds {}", self.start.elapsed().as_secs_f64());
+
+        let metrics: [Metric; 4] = [
+            ("minbot_proxy_messages_total", "Messages proxied", |c| c.messages),
+            ("minbot_proxy_bytes_total", "Bytes proxied, without TCP length prefixes", |c| c.bytes),
+            ("minbot_proxy_compressed_bytes_total", "Payload bytes of compressed packets", |c| c.compressed_bytes),
+            ("minbot_proxy_uncompressed_bytes_total", "Decompressed size of compressed packets", |c| c.uncompressed_bytes),
+        ];
+        for (metric, help, value) in metrics {
+            let _ = writeln!(out, "# HELP {} {}", metric, help);
+            let _ = writeln!(out, "# TYPE {} counter", metric);
+            for (key, counter) in &self.counters {
+                let _ = writeln!(out, "{}{{{}}} {}", metric, key.labels(), value(counter));
+            }
+        }
+
+        let _ = writeln!(out, "# HELP minbot_proxy_snapshots_per_second Snapshots per second over the last {} seconds", RATE_WINDOW.as_secs());
+        let _ = writeln!(out, "# TYPE minbot_proxy_snapshots_per_second gauge");
+        for (name, rate) in self.snapshot_rates() {
+            let _ = writeln!(out, "minbot_proxy_snapshots_per_second{{packet=\"{}\"}} {}", name, rate);
+        }
+        out
+    }
+}
+
+#[test]
+fn test_stats() {
+    let connect = minet::CONNECT_MESSAGE;
+    let mut stats = Stats::default();
+    stats.record(Transport::Tcp, Direction::ClientToServer, connect);
+    stats.record(Transport::Udp, Direction::ClientToServer, &[0xfe, 0x03, 0, 0, 0, 5]);
+    stats.record(Transport::Udp, Direction::ClientToServer, &[0xfe, 0x03, 0, 0, 0, 5]);
+    stats.record(Transport::Udp, Direction::ServerToClient, &[]);
+
+    assert_eq!(stats.counters.len(), 2);
+    let connect_counter = stats.counters.iter().find(|(k, _)| !k.framework && k.id == 3).unwrap().1;
+    assert_eq!(connect_counter.uncompressed_bytes, 0x44);
+    assert_eq!(connect_counter.compressed_bytes, connect.len() as u64 - 4);
+
+    let summary = stats.summary();
+    assert!(summary.contains("RegisterUDP [3]"));
+    assert!(summary.contains("ConnectPacket [3]"));
+
+    // packets seen before the protocol is known are renamed once it is
+    stats.record(Transport::Tcp, Direction::ClientToServer, &[0x90, 0, 0, 0]);
+    assert!(!stats.summary().contains("SendChatMessageCallPacket [144]"));
+    stats.protocol = Arc::new(Protocol::new("v141", 141, Default::default(), &[("SendChatMessageCallPacket", 0x90)]));
+    assert!(stats.summary().contains("SendChatMessageCallPacket [144]"));
+
+    // entitySnapshot is 0x1e in v135
+    stats.protocol = Protocol::fallback();
+    stats.record(Transport::Udp, Direction::ServerToClient, &[0x1e, 0, 0, 0]);
+    stats.record(Transport::Udp, Direction::ServerToClient, &[0x1e, 0, 0, 0]);
+    assert_eq!(stats.snapshot_rates(), [("EntitySnapshotCallPacket", 0.4)]);
+    assert!(stats.summary().contains("snapshots/s: EntitySnapshotCallPacket 0.4"));
+    assert!(stats.prometheus().contains("minbot_proxy_snapshots_per_second{packet=\"EntitySnapshotCallPacket\"} 0.4"));
+    let metrics = stats.prometheus();
+    assert!(metrics.contains("minbot_proxy_messages_total{direction=\"client_to_server\",protocol=\"udp\",kind=\"framework\",id=\"3\"} 2"));
+}
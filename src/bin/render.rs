@@ -0,0 +1,40 @@
+// Render only needs saves, content and the renderer.
+#[allow(dead_code)]
+#[path = "../minet/mod.rs"]
+mod minet;
+
+use std::error::Error;
+
+const USAGE: &str = "usage: render <map.msav> <out.png> --content dump.json [--scale pixels]
+
+  --content  a content dump exported from the game, to take block sizes and colors from
+  --scale    pixels per tile, 1 by default";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = std::env::args().skip(1);
+    let (path, out) = match (args.next(), args.next()) {
+        (Some(path), Some(out)) => (path, out),
+        _ => return Err(USAGE.into()),
+    };
+
+    let mut scale = 1;
+    let mut content = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scale" => scale = args.next().ok_or(USAGE)?.parse()?,
+            "--content" => content = Some(minet::content::ContentRegistry::load(&args.next().ok_or(USAGE)?)?),
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    let content = content.ok_or("--content is required: pass a content dump exported from the game")?;
+    let save = minet::save::Save::load(&path)?;
+    let world = save.world(&content);
+    if let Err(e) = world.check_content(&content) {
+        eprintln!("sizes and colors will be off: {}", e);
+    }
+    let image = minet::render::render(&world, &content, None, scale);
+    image.save(&out)?;
+    eprintln!("rendered {}x{} map to {}", save.map.width, save.map.height, out);
+    Ok(())
+}
@@ -0,0 +1,128 @@
+use std::{net::SocketAddr, error::Error, time::Duration};
+use async_std::{net::{TcpStream, TcpListener, UdpSocket}, io::{ReadExt, WriteExt}};
+use tokio::time::{sleep, timeout, Instant};
+// Replay only needs the capture format and message framing.
+#[allow(dead_code)]
+#[path = "../minet/mod.rs"]
+mod minet;
+
+use minet::{Transport, Direction, Record};
+
+const USAGE: &str = "usage: replay <capture> (client [--listen addr] | server [--server addr]) [--speed factor]
+
+  client   play the recorded server side to a connecting client
+  server   play the recorded client side against a server
+  --speed  playback speed; 1 keeps the original timing, 0 sends as fast as possible";
+
+/// Sleeps until `record` is due,
+/// relative to when playback started.
+async fn wait_for(record: &Record, start: Instant, speed: f64) {
+    if speed <= 0.0 {
+        return
+    }
+    let due = start + Duration::from_secs_f64(record.time as f64 / 1_000_000.0 / speed);
+    tokio::time::sleep_until(due).await;
+}
+
+/// Reads and discards everything the peer sends
+/// so that it never blocks on a full socket buffer.
+fn drain_tcp(mut stream: TcpStream) {
+    tokio::spawn(async move {
+        let mut buf = [0u8; 16384];
+        while let Ok(len) = stream.read(&mut buf).await {
+            if len == 0 {
+                break
+            }
+        }
+    });
+}
+
+/// Plays the server side of a capture to the first client
+/// that connects to `listen`.
+async fn play_to_client(records: &[Record], listen: SocketAddr, speed: f64) -> Result<(), Box<dyn Error>> {
+    let udp_socket = UdpSocket::bind(listen).await?;
+    let tcp_listener = TcpListener::bind(listen).await?;
+    eprintln!("waiting for client on {}", listen);
+    let (mut tcp_stream, client_addr) = tcp_listener.accept().await?;
+    eprintln!("client connected from {}", client_addr);
+    drain_tcp(tcp_stream.clone());
+
+    let mut udp_client_addr = None;
+    let start = Instant::now();
+    for record in records.iter().filter(|r| r.direction == Direction::ServerToClient) {
+        wait_for(record, start, speed).await;
+        match record.transport {
+            Transport::Tcp => tcp_stream.write_all(&record.data).await?,
+            Transport::Udp => {
+                // the client's UDP port is only known
+                // once it has sent us something
+                if udp_client_addr.is_none() {
+                    let mut buf = [0u8; 16384];
+                    match timeout(Duration::from_secs(5), udp_socket.recv_from(&mut buf)).await {
+                        Ok(res) => udp_client_addr = Some(res?.1),
+                        Err(_) => {
+                            eprintln!("client never sent a UDP message, skipping UDP record");
+                            continue
+                        },
+                    }
+                }
+                udp_socket.send_to(&record.data, udp_client_addr.unwrap()).await?;
+            },
+        }
+    }
+
+    eprintln!("replay finished");
+    Ok(())
+}
+
+/// Plays the client side of a capture to the server at `server_addr`.
+async fn play_to_server(records: &[Record], server_addr: SocketAddr, speed: f64) -> Result<(), Box<dyn Error>> {
+    let udp_socket = UdpSocket::bind("0.0.0.0:0").await?;
+    udp_socket.connect(server_addr).await?;
+    let mut tcp_stream = TcpStream::connect(server_addr).await?;
+    eprintln!("connected to {}", server_addr);
+    drain_tcp(tcp_stream.clone());
+
+    let start = Instant::now();
+    for record in records.iter().filter(|r| r.direction == Direction::ClientToServer) {
+        wait_for(record, start, speed).await;
+        match record.transport {
+            Transport::Tcp => tcp_stream.write_all(&record.data).await?,
+            Transport::Udp => { udp_socket.send(&record.data).await?; },
+        }
+    }
+
+    // give the server a moment to process the tail of the session
+    sleep(Duration::from_millis(500)).await;
+    eprintln!("replay finished");
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = std::env::args().skip(1);
+    let (path, mode) = match (args.next(), args.next()) {
+        (Some(path), Some(mode)) => (path, mode),
+        _ => return Err(USAGE.into()),
+    };
+
+    let mut addr: Option<SocketAddr> = None;
+    let mut speed = 1.0;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" | "--server" => addr = Some(args.next().ok_or(USAGE)?.parse()?),
+            "--speed" => speed = args.next().ok_or(USAGE)?.parse()?,
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    let data = std::fs::read(&path)?;
+    let records = minet::read_capture(&data).ok_or("not a capture file")?;
+    eprintln!("loaded {} records from {}", records.len(), path);
+
+    match mode.as_str() {
+        "client" => play_to_client(&records, addr.unwrap_or_else(|| "0.0.0.0:6567".parse().unwrap()), speed).await,
+        "server" => play_to_server(&records, addr.unwrap_or_else(|| "127.0.0.1:6567".parse().unwrap()), speed).await,
+        _ => Err(USAGE.into()),
+    }
+}
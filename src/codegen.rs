@@ -0,0 +1,347 @@
+//! Generates `Call` packets from `schema/calls.txt`.
+//!
+//! Shared by `build.rs`, which emits the Rust code,
+//! and the `gen-spec` binary, which rewrites
+//! the matching section of SPEC.md.
+//!
+//! Each schema entry is one line:
+//!
+//! ```text
+//! /// Doc comment, copied to the struct and SPEC.md.
+//! sendChatMessage client->server reliable v135=0x4b (message: str)
+//! ```
+//!
+//! Entries marked `manual` are listed in the id tables
+//! and SPEC.md, but their structs are written by hand.
+
+// each user only needs half of this module
+#![allow(dead_code)]
+
+use std::fmt::Write;
+
+/// A parameter type and how to
+/// encode it with `minet::Writer`/`Reader`.
+struct Type {
+    name: &'static str,
+    rust: &'static str,
+    write: &'static str,
+    read: &'static str,
+    json: &'static str,
+    from_json: &'static str,
+    /// Whether reading it depends on the protocol's
+    /// `Layout`, which `read` then uses as `layout`.
+    layout: bool,
+}
+
+/// `{}` in the templates is replaced with
+/// the field name.
+const TYPES: &[Type] = &[
+    Type { name: "bool", rust: "bool", write: "buf.bool(self.{})", read: "reader.bool()?", json: "self.{}", from_json: "value[\"{}\"].as_bool()?", layout: false },
+    Type { name: "i8", rust: "i8", write: "buf.i8(self.{})", read: "reader.i8()?", json: "self.{}", from_json: "value[\"{}\"].as_i64()? as i8", layout: false },
+    Type { name: "u8", rust: "u8", write: "buf.u8(self.{})", read: "reader.u8()?", json: "self.{}", from_json: "value[\"{}\"].as_u64()? as u8", layout: false },
+    Type { name: "u16", rust: "u16", write: "buf.u16(self.{})", read: "reader.u16()?", json: "self.{}", from_json: "value[\"{}\"].as_u64()? as u16", layout: false },
+    Type { name: "i16", rust: "i16", write: "buf.i16(self.{})", read: "reader.i16()?", json: "self.{}", from_json: "value[\"{}\"].as_i64()? as i16", layout: false },
+    Type { name: "i32", rust: "i32", write: "buf.i32(self.{})", read: "reader.i32()?", json: "self.{}", from_json: "value[\"{}\"].as_i64()? as i32", layout: false },
+    Type { name: "i64", rust: "i64", write: "buf.i64(self.{})", read: "reader.i64()?", json: "self.{}", from_json: "value[\"{}\"].as_i64()?", layout: false },
+    Type { name: "f32", rust: "f32", write: "buf.f32(self.{})", read: "reader.f32()?", json: "self.{}", from_json: "value[\"{}\"].as_f64()? as f32", layout: false },
+    Type { name: "f64", rust: "f64", write: "buf.f64(self.{})", read: "reader.f64()?", json: "self.{}", from_json: "value[\"{}\"].as_f64()?", layout: false },
+    Type { name: "str", rust: "String", write: "buf.str(&self.{})", read: "reader.str()?.to_string()", json: "self.{}", from_json: "value[\"{}\"].as_str()?.to_string()", layout: false },
+    Type { name: "bytes", rust: "Vec<u8>", write: "{ buf.i16(self.{}.len() as i16); buf.bytes(&self.{}) }", read: "{ let len = reader.i16()? as usize; reader.bytes(len)?.to_vec() }", json: "self.{}", from_json: "value[\"{}\"].as_array()?.iter().map(|b| b.as_u64().map(|b| b as u8)).collect::<Option<Vec<u8>>>()?", layout: false },
+    Type { name: "object", rust: "minet::TypeValue", write: "minet::Field::write(&self.{}, buf)", read: "minet::TypeValue::read_with(&mut reader, layout)?", json: "minet::Field::json(&self.{})", from_json: "<minet::TypeValue as minet::Field>::from_json(&value[\"{}\"])?", layout: true },
+    Type { name: "unit", rust: "minet::UnitRef", write: "minet::Field::write(&self.{}, buf)", read: "<minet::UnitRef as minet::Field>::read(&mut reader)?", json: "minet::Field::json(&self.{})", from_json: "<minet::UnitRef as minet::Field>::from_json(&value[\"{}\"])?", layout: false },
+    Type { name: "plans", rust: "Vec<minet::BuildPlan>", write: "minet::write_plans(buf, &self.{})", read: "minet::read_plans(&mut reader, layout)?", json: "self.{}.iter().map(minet::Field::json).collect::<Vec<_>>()", from_json: "value[\"{}\"].as_array()?.iter().map(<minet::BuildPlan as minet::Field>::from_json).collect::<Option<Vec<_>>>()?", layout: true },
+];
+
+pub struct Param {
+    pub name: String,
+    pub ty: String,
+}
+
+pub struct Call {
+    /// The remote method name, e.g. `sendChatMessage`.
+    pub method: String,
+    pub direction: String,
+    pub reliable: bool,
+    pub manual: bool,
+    /// Ids by protocol profile name.
+    pub ids: Vec<(String, u8)>,
+    pub params: Vec<Param>,
+    pub doc: Vec<String>,
+}
+
+impl Call {
+    /// The name of the generated struct,
+    /// e.g. `SendChatMessageCallPacket`.
+    pub fn struct_name(&self) -> String {
+        let mut chars = self.method.chars();
+        match chars.next() {
+            Some(c) => format!("{}{}CallPacket", c.to_ascii_uppercase(), chars.as_str()),
+            None => String::new(),
+        }
+    }
+}
+
+fn parse_id(s: &str) -> Result<u8, String> {
+    let parsed = match s.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|e| format!("invalid id {}: {}", s, e))
+}
+
+/// Parses the schema, reporting
+/// the first bad line.
+pub fn parse(schema: &str) -> Result<Vec<Call>, String> {
+    let mut calls = Vec::new();
+    let mut doc = Vec::new();
+    for (n, line) in schema.lines().enumerate() {
+        let err = |e: String| format!("calls.txt:{}: {}", n + 1, e);
+        let line = line.trim();
+        if let Some(comment) = line.strip_prefix("///") {
+            doc.push(comment.trim().to_string());
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            doc.clear();
+            continue;
+        }
+
+        let (head, params) = line.split_once('(').ok_or_else(|| err("missing parameter list".to_string()))?;
+        let params = params.strip_suffix(')').ok_or_else(|| err("unclosed parameter list".to_string()))?;
+        let mut words = head.split_whitespace();
+        let method = words.next().ok_or_else(|| err("missing method name".to_string()))?.to_string();
+        let direction = words.next().ok_or_else(|| err("missing direction".to_string()))?.to_string();
+        if direction != "client->server" && direction != "server->client" && direction != "both" {
+            return Err(err(format!("invalid direction: {}", direction)));
+        }
+        let reliable = match words.next() {
+            Some("reliable") => true,
+            Some("unreliable") => false,
+            other => return Err(err(format!("expected reliable or unreliable, got {:?}", other))),
+        };
+
+        let mut manual = false;
+        let mut ids = Vec::new();
+        for word in words {
+            match word.split_once('=') {
+                Some((profile, id)) => ids.push((profile.to_string(), parse_id(id).map_err(err)?)),
+                None if word == "manual" => manual = true,
+                None => return Err(err(format!("unexpected {}", word))),
+            }
+        }
+
+        let params = params.split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(|p| {
+                let (name, ty) = p.split_once(':').ok_or_else(|| err(format!("expected name: type, got {}", p)))?;
+                let ty = ty.trim().to_string();
+                if !manual && !TYPES.iter().any(|t| t.name == ty) {
+                    return Err(err(format!("unknown type {}", ty)));
+                }
+                Ok(Param { name: name.trim().to_string(), ty })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        calls.push(Call { method, direction, reliable, manual, ids, params, doc: std::mem::take(&mut doc) });
+    }
+    Ok(calls)
+}
+
+fn ty(name: &str) -> &'static Type {
+    TYPES.iter().find(|t| t.name == name).unwrap()
+}
+
+fn fill(template: &str, field: &str) -> String {
+    template.replace("{}", field)
+}
+
+/// Emits the structs, their `Data` and `Packet` impls,
+/// and the registry functions used by `PacketMessage`
+/// and `Protocol`.
+pub fn rust(calls: &[Call]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "// @generated from schema/calls.txt by src/codegen.rs");
+
+    for call in calls.iter().filter(|c| !c.manual) {
+        let name = call.struct_name();
+        let _ = writeln!(out);
+        for line in &call.doc {
+            let _ = writeln!(out, "/// {}", line);
+        }
+        if !call.doc.is_empty() {
+            let _ = writeln!(out, "///");
+        }
+        let _ = writeln!(out, "/// `{}`, sent {}.", call.method, call.direction);
+        let _ = writeln!(out, "#[derive(Clone, Debug)]");
+        let _ = writeln!(out, "pub struct {} {{", name);
+        for p in &call.params {
+            let _ = writeln!(out, "    pub {}: {},", p.name, ty(&p.ty).rust);
+        }
+        let _ = writeln!(out, "}}\n");
+
+        let mut deserialize = String::new();
+        if !call.params.is_empty() {
+            let _ = writeln!(deserialize, "        let mut reader = minet::Reader::new(data);");
+        }
+        for p in &call.params {
+            let _ = writeln!(deserialize, "        let {} = {};", p.name, ty(&p.ty).read);
+        }
+        let fields: Vec<&str> = call.params.iter().map(|p| p.name.as_str()).collect();
+        let _ = writeln!(deserialize, "        Some(Self {{ {} }})", fields.join(", "));
+
+        // configs and plans are read by the protocol's layout,
+        // or by `Layout::default()` outside of packets
+        let layout = call.params.iter().any(|p| ty(&p.ty).layout);
+        if layout {
+            let _ = writeln!(out, "impl {} {{", name);
+            let _ = writeln!(out, "    fn deserialize_with(data: &[u8], layout: &minet::protocol::Layout) -> Option<Self> {{");
+            let _ = write!(out, "{}", deserialize);
+            let _ = writeln!(out, "    }}");
+            let _ = writeln!(out, "}}\n");
+        }
+        let _ = writeln!(out, "impl Data for {} {{", name);
+        let _ = writeln!(out, "    fn serialize(&self, {}buf: &mut minet::Writer) {{", if call.params.is_empty() { "_" } else { "" });
+        for p in &call.params {
+            let _ = writeln!(out, "        {};", fill(ty(&p.ty).write, &p.name));
+        }
+        let _ = writeln!(out, "    }}\n");
+        let _ = writeln!(out, "    fn deserialize({}data: &[u8]) -> Option<Self> {{", if call.params.is_empty() { "_" } else { "" });
+        if layout {
+            let _ = writeln!(out, "        Self::deserialize_with(data, &minet::protocol::Layout::default())");
+        } else {
+            let _ = write!(out, "{}", deserialize);
+        }
+        let _ = writeln!(out, "    }}\n");
+        let _ = writeln!(out, "    fn json(&self) -> serde_json::Value {{");
+        let _ = writeln!(out, "        serde_json::json!({{");
+        for p in &call.params {
+            let _ = writeln!(out, "            \"{}\": {},", p.name, fill(ty(&p.ty).json, &p.name));
+        }
+        let _ = writeln!(out, "        }})");
+        let _ = writeln!(out, "    }}\n");
+        let _ = writeln!(out, "    fn from_json({}value: &serde_json::Value) -> Option<Self> {{", if call.params.is_empty() { "_" } else { "" });
+        let _ = writeln!(out, "        Some(Self {{");
+        for p in &call.params {
+            let _ = writeln!(out, "            {}: {},", p.name, fill(ty(&p.ty).from_json, &p.name));
+        }
+        let _ = writeln!(out, "        }})");
+        let _ = writeln!(out, "    }}");
+        let _ = writeln!(out, "}}\n");
+        let _ = writeln!(out, "impl Packet for {} {{", name);
+        let _ = writeln!(out, "    const NAME: &'static str = \"{}\";", name);
+        if !call.reliable {
+            let _ = writeln!(out, "    const RELIABLE: bool = false;");
+        }
+        if layout {
+            let _ = writeln!(out, "\n    fn read(data: &[u8], protocol: &Protocol) -> Option<Self> {{");
+            let _ = writeln!(out, "        Self::deserialize_with(data, &protocol.layout)");
+            let _ = writeln!(out, "    }}");
+        }
+        let _ = writeln!(out, "}}");
+    }
+
+    let mut profiles: Vec<&str> = calls.iter().flat_map(|c| c.ids.iter().map(|(p, _)| p.as_str())).collect();
+    profiles.sort();
+    profiles.dedup();
+    let _ = writeln!(out, "\n/// Ids of the `Call` packets in each built-in protocol profile.");
+    let _ = writeln!(out, "pub(crate) fn call_ids(profile: &str) -> Vec<(&'static str, u8)> {{");
+    let _ = writeln!(out, "    match profile {{");
+    for profile in profiles {
+        let _ = writeln!(out, "        \"{}\" => vec![", profile);
+        for call in calls {
+            if let Some((_, id)) = call.ids.iter().find(|(p, _)| p == profile) {
+                let _ = writeln!(out, "            (\"{}\", {:#04x}),", call.struct_name(), id);
+            }
+        }
+        let _ = writeln!(out, "        ],");
+    }
+    let _ = writeln!(out, "        _ => vec![],");
+    let _ = writeln!(out, "    }}");
+    let _ = writeln!(out, "}}");
+
+    // Only the proxy builds packets from JSON.
+    for (attr, func, args, call_expr) in [
+        ("", "decode_call", "data: &[u8], protocol: &Protocol", "read(data, protocol)"),
+        ("#[allow(dead_code)]\n", "call_from_json", "value: &serde_json::Value", "from_json(value)"),
+    ] {
+        let _ = writeln!(out, "\n{}pub(crate) fn {}(name: &str, {}) -> Option<Box<dyn PacketClone>> {{", attr, func, args);
+        let _ = writeln!(out, "    let packet: Box<dyn PacketClone> = match name {{");
+        for call in calls.iter().filter(|c| !c.manual) {
+            let _ = writeln!(out, "        \"{0}\" => Box::new({0}::{1}?),", call.struct_name(), call_expr);
+        }
+        let _ = writeln!(out, "        _ => return None,");
+        let _ = writeln!(out, "    }};");
+        let _ = writeln!(out, "    Some(packet)");
+        let _ = writeln!(out, "}}");
+    }
+    out
+}
+
+/// Renders SPEC.md sections for every call,
+/// using the ids of `profile`.
+pub fn spec(calls: &[Call], profile: &str) -> String {
+    let mut out = String::new();
+    for call in calls {
+        let id = call.ids.iter().find(|(p, _)| p == profile).map(|(_, id)| id);
+        match id {
+            Some(id) => { let _ = writeln!(out, "## {} [x{:02X}]\n", call.struct_name(), id); },
+            None => { let _ = writeln!(out, "## {}\n", call.struct_name()); },
+        }
+        let _ = writeln!(out, "`{}`, sent {}, {}.\n", call.method, call.direction, if call.reliable { "reliable" } else { "unreliable" });
+        for line in &call.doc {
+            let _ = writeln!(out, "{}", line);
+        }
+        if !call.doc.is_empty() {
+            let _ = writeln!(out);
+        }
+        let params: Vec<String> = call.params.iter().map(|p| format!("{}: {}", p.name, p.ty)).collect();
+        let _ = writeln!(out, "```\n{}\n```\n", params.join(" | "));
+    }
+    out
+}
+
+pub const SPEC_BEGIN: &str = "<!-- calls:begin -->";
+pub const SPEC_END: &str = "<!-- calls:end -->";
+
+/// Replaces the generated section of SPEC.md.
+pub fn splice_spec(spec_md: &str, generated: &str) -> Option<String> {
+    let begin = spec_md.find(SPEC_BEGIN)? + SPEC_BEGIN.len();
+    let end = spec_md.find(SPEC_END)?;
+    if end < begin {
+        return None;
+    }
+    Some(format!("{}\n\n{}{}", &spec_md[..begin], generated, &spec_md[end..]))
+}
+
+#[test]
+fn test_codegen() {
+    let calls = parse("
+        # comment
+        /// Moves a unit.
+        moveUnit both unreliable v135=0x10 (x: f32, y: f32, id: i32)
+    ").unwrap();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].struct_name(), "MoveUnitCallPacket");
+    assert_eq!(calls[0].doc, vec!["Moves a unit."]);
+    assert!(!calls[0].reliable);
+
+    let code = rust(&calls);
+    assert!(code.contains("pub struct MoveUnitCallPacket"));
+    assert!(code.contains("const RELIABLE: bool = false;"));
+    assert!(code.contains("(\"MoveUnitCallPacket\", 0x10)"));
+    let object = parse("configure client->server reliable v135=0x20 (value: object)").unwrap();
+    assert!(rust(&object).contains("pub value: minet::TypeValue,"));
+    let plans = rust(&parse("build client->server reliable v135=0x21 (plans: plans)").unwrap());
+    assert!(plans.contains("minet::write_plans(buf, &self.plans);"));
+    assert!(plans.contains("Self::deserialize_with(data, &protocol.layout)"));
+    assert!(!code.contains("fn read("));
+
+    let spec_md = format!("intro\n{}\nold\n{}\n", SPEC_BEGIN, SPEC_END);
+    let spliced = splice_spec(&spec_md, &spec(&calls, "v135")).unwrap();
+    assert!(spliced.contains("## MoveUnitCallPacket [x10]"));
+    assert!(!spliced.contains("old"));
+
+    assert!(parse("bad client->server reliable (x: u128)").is_err());
+    assert!(parse("bad client->server reliable").is_err());
+}
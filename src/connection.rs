@@ -0,0 +1,175 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc};
+use std::time::Duration;
+use crate::minet;
+use crate::minet::protocol::Protocol;
+
+/// How often the UDP reader checks
+/// whether the TCP connection has closed.
+const UDP_POLL: Duration = Duration::from_millis(500);
+
+/// The bot's sockets to a server, past the handshake.
+///
+/// Snapshots go over UDP and everything
+/// else over TCP, in both directions.
+pub struct Connection {
+    tcp: TcpStream,
+    udp: UdpSocket,
+}
+
+impl Connection {
+    /// `udp` must be connected to the server.
+    pub fn new(tcp: TcpStream, udp: UdpSocket) -> Self {
+        Self { tcp, udp }
+    }
+
+    /// Reads TCP frames and UDP datagrams on two threads,
+    /// as they arrive. The channel closes with the TCP connection.
+    pub fn spawn_readers(&self) -> io::Result<mpsc::Receiver<Vec<u8>>> {
+        let (tx, rx) = mpsc::channel();
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let mut tcp = self.tcp.try_clone()?;
+        let tcp_tx = tx.clone();
+        let tcp_closed = closed.clone();
+        std::thread::spawn(move || {
+            let mut frames = minet::FrameBuffer::new();
+            let mut buf = [0u8; 16384];
+            loop {
+                let len = match tcp.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(len) => len,
+                };
+                frames.push(&buf[..len]);
+                while let Some(frame) = frames.next_frame() {
+                    if tcp_tx.send(frame).is_err() {
+                        return
+                    }
+                }
+            }
+            tcp_closed.store(true, Ordering::Relaxed);
+        });
+
+        let udp = self.udp.try_clone()?;
+        udp.set_read_timeout(Some(UDP_POLL))?;
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 65536];
+            while !closed.load(Ordering::Relaxed) {
+                let len = match udp.recv(&mut buf) {
+                    Ok(len) => len,
+                    Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+                    Err(_) => return,
+                };
+                if tx.send(buf[..len].to_vec()).is_err() {
+                    return
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    /// Sends `packet` over TCP, or as a UDP
+    /// datagram if it isn't `RELIABLE`.
+    pub fn send<T: minet::Packet + 'static>(&mut self, protocol: &Arc<Protocol>, packet: T) -> io::Result<()> {
+        let msg = minet::PacketMessage::with_protocol(protocol, packet, false)
+            .ok_or_else(|| io::Error::other(format!("{} has no id in protocol {}", T::NAME, protocol.name)))?;
+        let mut buf = minet::Writer::new();
+        minet::Data::serialize(&msg, &mut buf);
+        if T::RELIABLE {
+            self.send_tcp(&buf.0)
+        } else {
+            self.udp.send(&buf.0).map(|_| ())
+        }
+    }
+
+    /// Sends a message over TCP.
+    pub fn send_tcp(&mut self, data: &[u8]) -> io::Result<()> {
+        self.tcp.write_all(&minet::frame_tcp(data))
+    }
+}
+
+/// A connection to a fake server on loopback,
+/// and the server's end of its sockets.
+#[cfg(test)]
+fn loopback() -> (Connection, TcpStream, UdpSocket) {
+    use std::net::TcpListener;
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let tcp = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server_tcp, _) = listener.accept().unwrap();
+    let server_udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+    udp.connect(server_udp.local_addr().unwrap()).unwrap();
+    (Connection::new(tcp, udp), server_tcp, server_udp)
+}
+
+#[test]
+fn test_udp_snapshot() {
+    use crate::minet::client::Client;
+    use crate::minet::entity::EntityMapping;
+
+    let (connection, server_tcp, server_udp) = loopback();
+    let incoming = connection.spawn_readers().unwrap();
+
+    let protocol = Protocol::fallback();
+    let mut client = Client::new(protocol.clone(), Default::default(), EntityMapping::for_protocol(&protocol).unwrap());
+    let mut data = minet::Writer::new();
+    // a player
+    data.i32(7);
+    data.u8(12);
+    data.bool(false); // admin
+    data.bool(false); // boosting
+    data.i32(-1); // color
+    data.f32(0.0);
+    data.f32(0.0);
+    data.str("allen");
+    data.i16(-1); // selectedBlock
+    data.i32(0);
+    data.bool(false); // shooting
+    data.u8(1); // team
+    data.bool(false); // typing
+    data.u8(0); // no unit
+    data.i32(0);
+    data.f32(80.0);
+    data.f32(96.0);
+    let snapshot = minet::EntitySnapshotCallPacket { amount: 1, data: data.0 };
+    let mut buf = minet::Writer::new();
+    minet::Data::serialize(&minet::PacketMessage::with_protocol(&protocol, snapshot, false).unwrap(), &mut buf);
+    server_udp.send_to(&buf.0, connection.udp.local_addr().unwrap()).unwrap();
+
+    let datagram = incoming.recv_timeout(Duration::from_secs(5)).unwrap();
+    let msg = minet::PacketMessage::deserialize_with(&datagram, &client.protocol).unwrap();
+    client.handle(&msg).unwrap();
+    assert_eq!(client.entities.player_by_name("allen").map(|p| (p.x, p.y)), Some((80.0, 96.0)));
+
+    // the readers stop once the server hangs up
+    drop(server_tcp);
+    assert_eq!(incoming.recv_timeout(Duration::from_secs(5)), Err(mpsc::RecvTimeoutError::Disconnected));
+}
+
+#[test]
+fn test_send() {
+    let (mut connection, mut server_tcp, server_udp) = loopback();
+    server_tcp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    server_udp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let protocol = Protocol::fallback();
+
+    connection.send(&protocol, minet::SendChatMessageCallPacket { message: "hi".to_string() }).unwrap();
+    let mut buf = [0u8; 1024];
+    let len = server_tcp.read(&mut buf).unwrap();
+    let mut frames = minet::FrameBuffer::new();
+    frames.push(&buf[..len]);
+    let msg = minet::PacketMessage::deserialize_with(&frames.next_frame().unwrap(), &protocol).unwrap();
+    assert_eq!(msg.downcast::<minet::SendChatMessageCallPacket>().unwrap().message, "hi");
+
+    // snapshots are unreliable, so they go over UDP
+    let snapshot = minet::ClientSnapshotCallPacket {
+        snapshot_id: 3, unit_id: 1, dead: false, x: 0.0, y: 0.0, pointer_x: 0.0, pointer_y: 0.0, rotation: 0.0, base_rotation: 0.0,
+        x_velocity: 0.0, y_velocity: 0.0, mining: -1, boosting: false, shooting: false, chatting: false, building: false,
+        plans: vec![], view_x: 0.0, view_y: 0.0, view_width: 0.0, view_height: 0.0,
+    };
+    connection.send(&protocol, snapshot).unwrap();
+    let len = server_udp.recv(&mut buf).unwrap();
+    let msg = minet::PacketMessage::deserialize_with(&buf[..len], &protocol).unwrap();
+    assert_eq!(msg.downcast::<minet::ClientSnapshotCallPacket>().unwrap().snapshot_id, 3);
+}
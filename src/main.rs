@@ -1,16 +1,85 @@
 mod minet;
+mod connection;
+mod tui;
 use std::io::{self, Write, Read};
 use std::net::{UdpSocket, TcpStream};
-use rand::Rng;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
+/// How often to send a `clientSnapshot`
+/// while moving or building.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long to wait for the server to answer `DiscoverHost`.
+const DISCOVER_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Reads a schematic from a file,
+/// or else as base64 text.
+fn load_schematic(arg: &str) -> Result<minet::schematic::Schematic, String> {
+	if std::path::Path::new(arg).exists() {
+		return minet::schematic::Schematic::load(arg);
+	}
+	minet::schematic::Schematic::from_base64(arg)
+}
+
+fn parse_point(arg: &str) -> Option<(i16, i16)> {
+	let (x, y) = arg.split_once(',')?;
+	Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+}
+
+/// Parses `X,Y,WIDTH,HEIGHT`.
+fn parse_area(arg: &str) -> Option<(i16, i16, u16, u16)> {
+	let (x, rest) = arg.split_once(',')?;
+	let (y, rest) = rest.split_once(',')?;
+	let (width, height) = parse_point(rest)?;
+	Some((x.trim().parse().ok()?, y.trim().parse().ok()?, u16::try_from(width).ok()?, u16::try_from(height).ok()?))
+}
+
 fn main() -> io::Result<()> {
-    let mut udp_socket = UdpSocket::bind("0.0.0.0:5001")?;
+	// --dump-world PATH writes the map to PATH once received,
+	// --render PATH draws it as a PNG minimap,
+	// --tui shows a live minimap, chat and status,
+	// --build SCHEMATIC --at X,Y walks to X,Y and builds a .msch file
+	// or base64 schematic there, --move X,Y just walks,
+	// --content PATH uses a content dump exported from the game, which
+	// is required: block and unit ids differ between builds,
+	// --copy X,Y,WIDTH,HEIGHT PATH saves that area as a schematic,
+	// --protocol NAME|PATH speaks that protocol instead of the
+	// one for the build the server reports
+	let mut dump_world = None;
+	let mut render = None;
+	let mut tui = false;
+	let mut schematic = None;
+	let mut at = (0, 0);
+	let mut walk_to = None;
+	let mut content = None;
+	let mut copy = None;
+	let mut protocol = None;
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--dump-world" => dump_world = args.next(),
+			"--render" => render = args.next(),
+			"--tui" => tui = true,
+			"--build" => schematic = args.next().map(|arg| load_schematic(&arg)).transpose().map_err(io::Error::other)?,
+			"--at" => at = args.next().as_deref().and_then(parse_point).ok_or_else(|| io::Error::other("--at takes X,Y"))?,
+			"--move" => walk_to = Some(args.next().as_deref().and_then(parse_point).ok_or_else(|| io::Error::other("--move takes X,Y"))?),
+			"--copy" => {
+				let area = args.next().as_deref().and_then(parse_area).ok_or_else(|| io::Error::other("--copy takes X,Y,WIDTH,HEIGHT PATH"))?;
+				copy = Some((area, args.next().ok_or_else(|| io::Error::other("--copy takes X,Y,WIDTH,HEIGHT PATH"))?));
+			},
+			"--content" => content = Some(args.next().ok_or_else(|| io::Error::other("--content takes a path"))?),
+			"--protocol" => protocol = Some(args.next().ok_or_else(|| io::Error::other("--protocol takes a name or path"))?),
+			_ => eprintln!("unknown argument {}", arg),
+		}
+	}
+
+	let content = content.ok_or_else(|| io::Error::other("--content is required: pass a content dump exported from the game"))?;
+
+    let udp_socket = UdpSocket::bind("0.0.0.0:5001")?;
 	udp_socket.connect("0.0.0.0:6567")?;
     let mut tcp_socket = TcpStream::connect("0.0.0.0:6567")?;
 
-	let mut rng = rand::thread_rng();
-
 	// UDP ping
 	{
 		let mut msg = minet::Writer::new();
@@ -18,10 +87,44 @@ fn main() -> io::Result<()> {
 		udp_socket.send(&msg.0)?;
 	}
 
+	// the server's reply, unless it doesn't answer in time
+	let info = {
+		let mut buf = [0u8; 1024];
+		udp_socket.set_read_timeout(Some(DISCOVER_TIMEOUT))?;
+		let info = match udp_socket.recv(&mut buf) {
+			Ok(len) => <minet::ServerInfo as minet::Data>::deserialize(&buf[..len]),
+			Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => None,
+			Err(e) => return Err(e),
+		};
+		udp_socket.set_read_timeout(None)?;
+		info
+	};
+	match &info {
+		Some(info) => println!("server {:?} is running build {}", info.name, info.version),
+		None => println!("server didn't answer DiscoverHost"),
+	}
+
+	// pick a protocol from the server's reported build
+	let protocol = match protocol {
+		Some(name_or_path) => minet::protocol::Protocol::load(&name_or_path).map_err(io::Error::other)?,
+		None => match info.and_then(|info| minet::protocol::Protocol::for_build(info.version)) {
+			Some(protocol) => protocol,
+			None => {
+				let fallback = minet::protocol::Protocol::fallback();
+				eprintln!("no built-in protocol for the server's build, trying {}; pass --protocol to pick one", fallback.name);
+				fallback
+			},
+		},
+	};
+	println!("using protocol {}", protocol.name);
+	minet::content::ContentRegistry::register(&protocol.name, minet::content::ContentRegistry::load(&content).map_err(io::Error::other)?);
+	let content = minet::content::ContentRegistry::for_protocol(&protocol).map_err(io::Error::other)?;
+	let mapping = minet::entity::EntityMapping::for_protocol(&protocol).map_err(io::Error::other)?;
+
 	// TCP register 
 	let id = {
-		let mut buf = [0u8; 16];
-		tcp_socket.read(&mut buf)?;
+		let mut buf = [0u8; 8];
+		tcp_socket.read_exact(&mut buf)?;
 		((buf[4] as u32) << 24) + ((buf[5] as u32) << 16)+ ((buf[6] as u32) << 8) + (buf[7]) as u32
 	} as i32;
 
@@ -35,8 +138,8 @@ fn main() -> io::Result<()> {
 		
 	let mut buf = [0u8; 16];
 	println!("waiting for tcp reply...");
-	tcp_socket.read(&mut buf)?; // wait for registration reply...
-	println!("tcp reply: {:?}", buf);
+	let len = tcp_socket.read(&mut buf)?; // wait for registration reply...
+	println!("tcp reply: {:?}", &buf[..len]);
 
 	println!("waiting for udp reply...");
 	udp_socket.recv(&mut buf)?;
@@ -46,7 +149,7 @@ fn main() -> io::Result<()> {
 		let mut msg = minet::Writer::new();
 		msg.u8(0x00);
 		msg.u8(0x4a);
-		msg.i32(-1);
+		msg.i32(protocol.build);
 		msg.str("null");
 		msg.str("robot");
 		msg.str("en-US");
@@ -63,12 +166,110 @@ fn main() -> io::Result<()> {
 		msg.i32(0);
 		msg.u8(0);
 
-		tcp_socket.write(&msg.0)?;
+		tcp_socket.write_all(&msg.0)?;
 	}
 
-	loop {
-		tcp_socket.read(&mut buf)?;
+	let mut client = minet::client::Client::new(protocol.clone(), content, mapping);
+	let tile_events = client.world.subscribe();
+	let mut builder = None;
+	let mut movement = minet::movement::Movement::default();
+	let acting = schematic.is_some() || walk_to.is_some();
+	if schematic.is_some() {
+		walk_to = Some(at);
 	}
-		
+	let mut snapshot_id = 0;
+	let mut last_snapshot = Instant::now();
+	let mut connection = connection::Connection::new(tcp_socket, udp_socket);
+	if tui {
+		return tui::run(client, connection);
+	}
+	// snapshots arrive over UDP, everything else over TCP
+	for data in connection.spawn_readers()? {
+		// framework messages start with -2
+		if data.first() == Some(&0xfe) {
+			continue
+		}
+		let msg = match minet::PacketMessage::deserialize_with(&data, &client.protocol) {
+			Some(msg) => msg,
+			None => continue,
+		};
+		if let Err(e) = client.handle(&msg) {
+			eprintln!("{}", e);
+		}
+		movement.correct(&msg);
+		if acting {
+			let Some(data) = &client.world_data else { continue };
+			if let (Some(schematic), None) = (&schematic, &builder) {
+				let team = client.player(data.player_id).map_or(1, |p| p.team);
+				builder = Some(minet::builder::SchematicBuilder::new(schematic, at.0, at.1, team, &client.content).map_err(io::Error::other)?);
+			}
+			// wait for our unit to spawn
+			if let Some((x, y)) = walk_to.filter(|_| client.entities.unit_of(data.player_id).is_some()) {
+				if let Err(e) = movement.move_to(&client, &client.content, x, y) {
+					eprintln!("{}", e);
+				}
+				walk_to = None;
+			}
+			if let Some(builder) = &mut builder {
+				for event in tile_events.try_iter() {
+					builder.apply(&event);
+				}
+			}
+			if last_snapshot.elapsed() >= SNAPSHOT_INTERVAL {
+				let plans = builder.as_mut().map(|b| b.plans(&client.world, &client.state, Instant::now())).unwrap_or_default();
+				if let Some(snapshot) = movement.snapshot(&client, &client.content, snapshot_id, plans, Instant::now()) {
+					connection.send(&client.protocol, snapshot)?;
+					snapshot_id += 1;
+				}
+				last_snapshot = Instant::now();
+			}
+			match &builder {
+				Some(builder) if builder.is_done() => {
+					let (built, total) = builder.progress();
+					println!("built {} of {} blocks", built, total);
+					for p in builder.placements() {
+						if let minet::builder::PlanStatus::Failed(reason) = &p.status {
+							println!("{} at {}, {}: {}", p.name, p.plan.x, p.plan.y, reason);
+						}
+					}
+					return Ok(())
+				},
+				None if walk_to.is_none() && !movement.is_moving() => {
+					println!("stopped at {:.1}, {:.1}", movement.x / 8.0, movement.y / 8.0);
+					return Ok(())
+				},
+				_ => {},
+			}
+			continue
+		}
+		if client.world_data.is_none() || (dump_world.is_none() && render.is_none() && copy.is_none()) {
+			continue
+		}
+		if let Some(path) = &dump_world {
+			match client.save_world(path) {
+				Ok(()) => println!("saved world to {}", path),
+				Err(e) => eprintln!("{}", e),
+			}
+		}
+		if let Some(path) = &render {
+			if let Err(e) = client.world.check_content(&client.content) {
+				eprintln!("sizes and colors will be off: {}", e);
+			}
+			let image = minet::render::render(&client.world, &client.content, Some(&client.entities), 1);
+			match image.save(path) {
+				Ok(()) => println!("rendered world to {}", path),
+				Err(e) => eprintln!("{}", e),
+			}
+		}
+		if let Some(((x, y, width, height), path)) = &copy {
+			let schematic = minet::schematic::Schematic::capture(&client.world, &client.content, *x, *y, *width, *height);
+			match schematic.save(path) {
+				Ok(()) => println!("copied {} blocks to {}\n{}", schematic.tiles.len(), path, schematic.base64()),
+				Err(e) => eprintln!("{}", e),
+			}
+		}
+		return Ok(())
+	}
+
     Ok(())
 }
@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use crate::minet::{BuildPlan, MAX_PLANS};
+use crate::minet::content::ContentRegistry;
+use crate::minet::schematic::Schematic;
+use crate::minet::state::GameState;
+use crate::minet::world::{TileEvent, World};
+
+/// Where a block of the schematic is at.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlanStatus {
+    /// Not sent yet, or held back for the reason given,
+    /// e.g. a banned block or too few items.
+    Waiting(Option<String>),
+    /// Sent, and not yet built.
+    Placing { since: Instant },
+    Built,
+    /// Given up on.
+    Failed(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Placement {
+    pub plan: BuildPlan,
+    pub name: String,
+    pub status: PlanStatus,
+    /// How many times the plan was sent
+    /// without getting built.
+    pub attempts: u32,
+    /// Item ids and amounts, before `buildCostMultiplier`.
+    cost: Vec<(u16, i32)>,
+}
+
+/// Builds a schematic by sending its blocks as `BuildPlan`s
+/// in `ClientSnapshotCallPacket`s, following `TileEvent`s
+/// to see which got built.
+///
+/// Plans are only sent once their block isn't banned and
+/// the team's cores hold enough items. A plan that isn't
+/// built within `timeout` of being sent is sent again, up
+/// to `max_attempts` times, and one whose building is
+/// destroyed goes back to waiting.
+#[derive(Clone, Debug)]
+pub struct SchematicBuilder {
+    pub team: u8,
+    pub timeout: Duration,
+    pub max_attempts: u32,
+    placements: Vec<Placement>,
+}
+
+impl SchematicBuilder {
+    /// Plans `schematic` centered on `(x, y)`, as
+    /// when placed from the build menu. Block names
+    /// are looked up in `content`.
+    pub fn new(schematic: &Schematic, x: i16, y: i16, team: u8, content: &ContentRegistry) -> Result<Self, String> {
+        let (left, bottom) = (x - schematic.width as i16 / 2, y - schematic.height as i16 / 2);
+        let placements = schematic.tiles.iter().map(|tile| {
+            let block = content.blocks.by_name(&tile.block).ok_or_else(|| format!("unknown block {}", tile.block))?;
+            if block.category.is_none() {
+                return Err(format!("{} isn't in the build menu", tile.block));
+            }
+            let cost = block.requirements.iter()
+                .map(|(item, amount)| Some((content.items.by_name(item)?.id, *amount as i32)))
+                .collect::<Option<_>>()
+                .ok_or_else(|| format!("{} needs an unknown item", tile.block))?;
+            Ok(Placement {
+                plan: BuildPlan {
+                    breaking: false,
+                    x: left + tile.x,
+                    y: bottom + tile.y,
+                    block: block.id,
+                    rotation: tile.rotation,
+                    config: tile.config.clone(),
+                },
+                name: tile.block.clone(),
+                status: PlanStatus::Waiting(None),
+                attempts: 0,
+                cost,
+            })
+        }).collect::<Result<_, String>>()?;
+        Ok(Self { team, timeout: Duration::from_secs(30), max_attempts: 3, placements })
+    }
+
+    pub fn placements(&self) -> &[Placement] {
+        &self.placements
+    }
+
+    /// Built and total blocks.
+    pub fn progress(&self) -> (usize, usize) {
+        let built = self.placements.iter().filter(|p| p.status == PlanStatus::Built).count();
+        (built, self.placements.len())
+    }
+
+    /// Whether every block is built or given up on.
+    pub fn is_done(&self) -> bool {
+        self.placements.iter().all(|p| matches!(p.status, PlanStatus::Built | PlanStatus::Failed(_)))
+    }
+
+    /// Marks blocks built, taken or destroyed.
+    pub fn apply(&mut self, event: &TileEvent) {
+        let (x, y, built) = match *event {
+            TileEvent::Constructed { x, y, block, team, .. } | TileEvent::Set { x, y, block, team, .. } => (x, y, Some((block, team))),
+            TileEvent::Deconstructed { x, y, .. } | TileEvent::Removed { x, y, .. } => (x, y, None),
+            _ => return,
+        };
+        let team = self.team;
+        let Some(p) = self.placements.iter_mut().find(|p| (p.plan.x, p.plan.y) == (x, y)) else { return };
+        p.status = match built {
+            Some((block, built_team)) if block == p.plan.block && built_team == team => PlanStatus::Built,
+            Some(_) => PlanStatus::Failed("tile taken by another block".to_string()),
+            None if p.status == PlanStatus::Built => PlanStatus::Waiting(Some("destroyed".to_string())),
+            None => return,
+        };
+    }
+
+    /// The plans to send in the next snapshot: those being
+    /// placed, then waiting ones that are allowed and that
+    /// the team's core items cover.
+    pub fn plans(&mut self, world: &World, state: &GameState, now: Instant) -> Vec<BuildPlan> {
+        let rules = &state.rules;
+        let team_rules = rules.team(self.team);
+        let free = rules.infinite_resources || team_rules.infinite_resources || team_rules.cheat;
+        let mut items = state.core_items.get(&self.team).cloned().unwrap_or_default();
+        let mut plans = Vec::new();
+
+        for p in &mut self.placements {
+            if let Some(b) = world.building(p.plan.x, p.plan.y) {
+                if b.block == p.plan.block && b.team == self.team {
+                    p.status = PlanStatus::Built;
+                }
+            }
+            match p.status {
+                PlanStatus::Placing { since } if now - since >= self.timeout => {
+                    p.attempts += 1;
+                    if p.attempts >= self.max_attempts {
+                        p.status = PlanStatus::Failed(format!("not built after {} attempts", p.attempts));
+                        continue;
+                    }
+                    p.status = PlanStatus::Placing { since: now };
+                },
+                PlanStatus::Placing { .. } => {},
+                PlanStatus::Waiting(_) => {
+                    if rules.is_banned(&p.name) {
+                        p.status = PlanStatus::Waiting(Some("banned".to_string()));
+                        continue;
+                    }
+                    if plans.len() >= MAX_PLANS {
+                        continue;
+                    }
+                    if !free && !take(&mut items, &p.cost, rules.build_cost_multiplier) {
+                        p.status = PlanStatus::Waiting(Some("not enough items".to_string()));
+                        continue;
+                    }
+                    p.status = PlanStatus::Placing { since: now };
+                },
+                PlanStatus::Built | PlanStatus::Failed(_) => continue,
+            }
+            if plans.len() < MAX_PLANS {
+                plans.push(p.plan.clone());
+            }
+        }
+        plans
+    }
+}
+
+/// Takes `cost` out of `items` if they cover it.
+fn take(items: &mut BTreeMap<u16, i32>, cost: &[(u16, i32)], multiplier: f32) -> bool {
+    let cost: Vec<(u16, i32)> = cost.iter().map(|(item, amount)| (*item, (*amount as f32 * multiplier).round() as i32)).collect();
+    if cost.iter().any(|(item, amount)| items.get(item).copied().unwrap_or(0) < *amount) {
+        return false;
+    }
+    for (item, amount) in cost {
+        *items.entry(item).or_default() -= amount;
+    }
+    true
+}
+
+#[test]
+fn test_schematic_builder() {
+    use crate::minet::TypeValue;
+    use crate::minet::schematic::SchematicTile;
+    use crate::minet::world::Building;
+
+    let content = ContentRegistry::from_json(r#"{
+        "blocks": [{ "id": 1, "name": "conveyor", "category": "distribution", "requirements": { "copper": 1 } },
+                   { "id": 2, "name": "duo", "category": "turret", "requirements": { "copper": 35 } },
+                   { "id": 3, "name": "ripple", "size": 3, "category": "turret", "requirements": { "copper": 150 } },
+                   { "id": 4, "name": "stone" }],
+        "items": [{ "id": 0, "name": "copper" }]
+    }"#).unwrap();
+    let tile = |block: &str, x, y| SchematicTile { block: block.to_string(), x, y, config: TypeValue::Null, rotation: 0 };
+    let schematic = Schematic {
+        width: 5, height: 3,
+        tiles: vec![tile("conveyor", 0, 0), tile("conveyor", 1, 0), tile("duo", 0, 1), tile("ripple", 3, 1)],
+        ..Schematic::default()
+    };
+    let mut builder = SchematicBuilder::new(&schematic, 10, 10, 1, &content).unwrap();
+    let stone = Schematic { tiles: vec![tile("stone", 0, 0)], ..schematic.clone() };
+    assert!(SchematicBuilder::new(&stone, 10, 10, 1, &content).is_err());
+    assert_eq!(builder.placements()[0].plan.x, 8);
+    assert_eq!(builder.placements()[3].plan.y, 10);
+
+    let mut world = World::default();
+    let mut state = GameState::default();
+    state.core_items.insert(1, BTreeMap::from([(0, 40)]));
+    state.rules.banned_blocks.insert("ripple".to_string());
+    let start = Instant::now();
+    let plans = builder.plans(&world, &state, start);
+    // 1 + 1 copper for the conveyors leaves 38 for the duo
+    assert_eq!(plans.len(), 3);
+    assert_eq!(builder.placements()[3].status, PlanStatus::Waiting(Some("banned".to_string())));
+
+    builder.apply(&TileEvent::Constructed { x: 8, y: 9, block: 1, rotation: 0, team: 1, config: TypeValue::Null, player: None });
+    world.insert(Building { pos: (9 << 16) | 9, block: 1, team: 1, ..Building::default() });
+    assert_eq!(builder.plans(&world, &state, start).len(), 1);
+    assert_eq!(builder.progress(), (2, 4));
+
+    // the duo is never built
+    let later = start + builder.timeout;
+    assert_eq!(builder.plans(&world, &state, later).len(), 1);
+    assert_eq!(builder.placements()[2].attempts, 1);
+    builder.plans(&world, &state, later + builder.timeout);
+    builder.plans(&world, &state, later + builder.timeout * 2);
+    assert!(matches!(builder.placements()[2].status, PlanStatus::Failed(_)));
+
+    // destroyed conveyors are rebuilt
+    builder.apply(&TileEvent::Removed { x: 8, y: 9, block: Some(1) });
+    state.core_items.insert(1, BTreeMap::new());
+    assert!(builder.plans(&world, &state, later).is_empty());
+    assert_eq!(builder.placements()[0].status, PlanStatus::Waiting(Some("not enough items".to_string())));
+    state.rules.infinite_resources = true;
+    state.rules.banned_blocks.clear();
+    assert_eq!(builder.plans(&world, &state, later).len(), 2);
+    assert!(!builder.is_done());
+}
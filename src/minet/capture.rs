@@ -0,0 +1,184 @@
+use crate::minet;
+use std::{fmt, io::{self, Write}, str::FromStr, time::Instant};
+
+/// The transport a captured message
+/// travelled over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transport::Tcp => write!(f, "TCP"),
+            Transport::Udp => write!(f, "UDP"),
+        }
+    }
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tcp" => Ok(Transport::Tcp),
+            "udp" => Ok(Transport::Udp),
+            _ => Err(format!("unknown transport: {}", s)),
+        }
+    }
+}
+
+/// The side of the connection
+/// that sent a captured message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    ClientToServer,
+    ServerToClient,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::ClientToServer => write!(f, "client -> server"),
+            Direction::ServerToClient => write!(f, "server -> client"),
+        }
+    }
+}
+
+impl FromStr for Direction {
+    type Err = String;
+
+    /// Accepts the `Display` form,
+    /// with or without spaces.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.replace(' ', "").as_str() {
+            "client->server" => Ok(Direction::ClientToServer),
+            "server->client" => Ok(Direction::ServerToClient),
+            _ => Err(format!("unknown direction: {}", s)),
+        }
+    }
+}
+
+/// A single chunk of traffic seen by the proxy.
+///
+/// For TCP, `data` is exactly what one read
+/// returned, length prefixes included.
+/// For UDP, `data` is one datagram.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// Microseconds since the capture started.
+    pub time: u64,
+    pub transport: Transport,
+    pub direction: Direction,
+    pub data: Vec<u8>,
+}
+
+const MAGIC: &[u8] = b"MCAP";
+const VERSION: u8 = 1;
+
+impl Record {
+    pub fn write(&self, buf: &mut minet::Writer) {
+        buf.u64(self.time);
+        buf.u8(match self.transport {
+            Transport::Tcp => 0,
+            Transport::Udp => 1,
+        });
+        buf.u8(match self.direction {
+            Direction::ClientToServer => 0,
+            Direction::ServerToClient => 1,
+        });
+        buf.u32(self.data.len() as u32);
+        buf.bytes(&self.data);
+    }
+
+    pub fn read(reader: &mut minet::Reader) -> Option<Self> {
+        let time = reader.u64()?;
+        let transport = match reader.u8()? {
+            0 => Transport::Tcp,
+            1 => Transport::Udp,
+            _ => return None,
+        };
+        let direction = match reader.u8()? {
+            0 => Direction::ClientToServer,
+            1 => Direction::ServerToClient,
+            _ => return None,
+        };
+        let len = reader.u32()? as usize;
+        let data = reader.bytes(len)?.to_vec();
+
+        Some(Self { time, transport, direction, data })
+    }
+}
+
+/// Writes records to a capture file
+/// as they arrive.
+///
+/// The file format is:
+///
+/// ```text
+/// "MCAP" | version: u8 | (time: u64 | transport: u8 | direction: u8 | len: u32 | data)...
+/// ```
+pub struct CaptureWriter<W: Write> {
+    out: W,
+    start: Instant,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+        Ok(Self { out, start: Instant::now() })
+    }
+
+    /// Records `data` with the time
+    /// elapsed since the capture started.
+    pub fn record(&mut self, transport: Transport, direction: Direction, data: &[u8]) -> io::Result<()> {
+        let record = Record {
+            time: self.start.elapsed().as_micros() as u64,
+            transport, direction,
+            data: data.to_vec(),
+        };
+
+        let mut buf = minet::Writer::new();
+        record.write(&mut buf);
+        self.out.write_all(&buf.0)?;
+        self.out.flush()
+    }
+}
+
+/// Parses the contents of a capture file.
+///
+/// A truncated final record, as left behind
+/// by a proxy that was killed mid-write, is dropped.
+pub fn read_capture(data: &[u8]) -> Option<Vec<Record>> {
+    let mut reader = minet::Reader::new(data);
+    if reader.bytes(MAGIC.len())? != MAGIC || reader.u8()? != VERSION {
+        return None;
+    }
+
+    let mut records = Vec::new();
+    while let Some(record) = Record::read(&mut reader) {
+        records.push(record);
+    }
+    Some(records)
+}
+
+#[test]
+fn test_capture() {
+    let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+    writer.record(Transport::Udp, Direction::ClientToServer, &[0xfe, 0x01]).unwrap();
+    writer.record(Transport::Tcp, Direction::ServerToClient, &[0x00, 0x06, 0xfe, 0x04, 0, 0, 0, 5]).unwrap();
+
+    let mut data = writer.out;
+    let records = read_capture(&data).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].transport, Transport::Udp);
+    assert_eq!(records[1].direction, Direction::ServerToClient);
+    assert_eq!(records[1].data, &[0x00, 0x06, 0xfe, 0x04, 0, 0, 0, 5]);
+    assert!(records[0].time <= records[1].time);
+
+    data.truncate(data.len() - 3);
+    assert_eq!(read_capture(&data).unwrap().len(), 1);
+}
@@ -0,0 +1,182 @@
+use std::sync::{mpsc, Arc};
+use crate::minet::{BlockSnapshotCallPacket, BuildPlan, ClientSnapshotCallPacket, EntitySnapshotCallPacket, PacketMessage, PlayerDisconnectCallPacket, SetRulesCallPacket, StateSnapshotCallPacket, StreamBuilder};
+use crate::minet::content::ContentRegistry;
+use crate::minet::entity::{EntityMapping, EntityTable};
+use crate::minet::players::{Player, PlayerEvent, PlayerList};
+use crate::minet::protocol::Protocol;
+use crate::minet::save::WorldData;
+use crate::minet::state::GameState;
+use crate::minet::world::World;
+
+/// The area around the unit the server sends entities for,
+/// in world units; a 1080p screen at default zoom.
+const VIEW_SIZE: (f32, f32) = (1920.0 / 4.0, 1080.0 / 4.0);
+
+/// How many `entitySnapshot`s an entity may be missing
+/// from before it is forgotten. Big syncs are split over
+/// several snapshots, so this is a few seconds' worth.
+const ENTITY_TTL: u64 = 50;
+
+/// What a client knows about the server it's on,
+/// kept up to date from the packets it receives.
+#[derive(Debug)]
+pub struct Client {
+    /// The server's protocol, which snapshots
+    /// and the world data are decoded with.
+    pub protocol: Arc<Protocol>,
+    /// The content of the server's build.
+    pub content: Arc<ContentRegistry>,
+    mapping: Arc<EntityMapping>,
+    pub entities: EntityTable,
+    pub world: World,
+    pub state: GameState,
+    /// The world data received on join.
+    pub world_data: Option<WorldData>,
+    players: PlayerList,
+    streams: StreamBuilder,
+}
+
+impl Client {
+    /// A client for a server speaking `protocol`,
+    /// given the content and entity mapping of its build.
+    pub fn new(protocol: Arc<Protocol>, content: Arc<ContentRegistry>, mapping: Arc<EntityMapping>) -> Self {
+        Self {
+            protocol, content, mapping,
+            entities: EntityTable::default(),
+            world: World::default(),
+            state: GameState::default(),
+            world_data: None,
+            players: PlayerList::default(),
+            streams: StreamBuilder::default(),
+        }
+    }
+
+    /// Applies a packet from the server.
+    pub fn handle(&mut self, msg: &PacketMessage) -> Result<(), String> {
+        if let Some((kind, data)) = self.streams.push(msg) {
+            if self.protocol.name(kind) == Some("WorldStream") {
+                let data = WorldData::read(&data, &self.mapping)
+                    .map_err(|e| format!("world data: {}", e))?;
+                self.load_world(data);
+            }
+            return Ok(());
+        }
+        if let Some(snapshot) = msg.downcast::<EntitySnapshotCallPacket>() {
+            let applied = self.entities.apply(&self.mapping, snapshot).map_err(|e| format!("entity snapshot: {}", e));
+            self.entities.prune(ENTITY_TTL);
+            self.players.sync(&self.entities);
+            return applied.map(|_| ());
+        }
+        if let Some(snapshot) = msg.downcast::<BlockSnapshotCallPacket>() {
+            return self.world.apply(&self.content, snapshot).map(|_| ()).map_err(|e| format!("block snapshot: {}", e));
+        }
+        if let Some(snapshot) = msg.downcast::<StateSnapshotCallPacket>() {
+            self.state.apply(&self.protocol.layout, snapshot);
+            return Ok(());
+        }
+        if let Some(rules) = msg.downcast::<SetRulesCallPacket>() {
+            return self.state.apply_rules(rules).map_err(|e| format!("rules: {}", e));
+        }
+        if let Some(p) = msg.downcast::<PlayerDisconnectCallPacket>() {
+            // or the next snapshot would see them join again
+            self.entities.remove(p.player);
+        }
+        self.world.apply_tile(&self.entities, msg);
+        self.players.apply(msg);
+        Ok(())
+    }
+
+    /// Replaces the world and rules with those in `data`.
+    pub fn load_world(&mut self, data: WorldData) {
+        self.world.load(data.world(&self.content));
+        self.state.wave = data.wave;
+        self.state.wave_countdown = data.wave_time;
+        self.state.set_rules(data.rules.clone());
+        self.world_data = Some(data);
+    }
+
+    /// Writes the world received on join
+    /// to `path` as an `.msav` map.
+    pub fn save_world(&self, path: &str) -> Result<(), String> {
+        self.world_data.as_ref().ok_or("no world data received yet")?.to_save().save(path)
+    }
+
+    /// The players online, by id.
+    pub fn players(&self) -> impl Iterator<Item = &Player> {
+        self.players.iter()
+    }
+
+    pub fn player(&self, id: i32) -> Option<&Player> {
+        self.players.get(id)
+    }
+
+    #[allow(dead_code)]
+    pub fn player_by_name(&self, name: &str) -> Option<&Player> {
+        self.players.by_name(name)
+    }
+
+    /// A `clientSnapshot` keeping our unit where the server
+    /// last put it, with `plans` as its build queue.
+    /// `None` until the world data says who we are.
+    pub fn snapshot(&self, snapshot_id: i32, plans: Vec<BuildPlan>) -> Option<ClientSnapshotCallPacket> {
+        let player_id = self.world_data.as_ref()?.player_id;
+        let unit = self.entities.unit_of(player_id);
+        let (x, y, rotation) = unit.map_or((0.0, 0.0, 0.0), |u| (u.x, u.y, u.rotation));
+        Some(ClientSnapshotCallPacket {
+            snapshot_id,
+            unit_id: unit.map_or(-1, |u| u.id),
+            dead: unit.is_none(),
+            x, y,
+            pointer_x: x, pointer_y: y,
+            rotation, base_rotation: rotation,
+            x_velocity: 0.0, y_velocity: 0.0,
+            mining: -1,
+            boosting: false, shooting: false, chatting: false,
+            building: !plans.is_empty(),
+            plans,
+            view_x: x, view_y: y,
+            view_width: VIEW_SIZE.0, view_height: VIEW_SIZE.1,
+        })
+    }
+
+    /// Returns a channel that receives every join
+    /// and leave from now on.
+    #[allow(dead_code)]
+    pub fn player_events(&mut self) -> mpsc::Receiver<PlayerEvent> {
+        self.players.subscribe()
+    }
+}
+
+#[test]
+fn test_client_players() {
+    use crate::minet::entity::{Entity, EntityKind};
+    use crate::minet::protocol::Protocol;
+
+    fn msg<T: crate::minet::Packet + 'static>(packet: T) -> PacketMessage {
+        PacketMessage::with_protocol(&Protocol::fallback(), packet, false).unwrap()
+    }
+    let player = |id, name: &str| Entity {
+        id, class: "Player".to_string(), kind: EntityKind::Player, x: 0.0, y: 0.0, rotation: 0.0, health: 0.0, team: 1,
+        unit_type: None, controller: None, name: Some(name.to_string()), unit: None, fields: Default::default(), updated: 0,
+    };
+    let snapshot = || msg(EntitySnapshotCallPacket { amount: 0, data: vec![] });
+    let mut client = Client::new(Protocol::fallback(), Default::default(), Default::default());
+    let events = client.player_events();
+    client.entities.insert(player(1, "anuke"));
+    client.entities.insert(player(2, "griefer"));
+    client.handle(&snapshot()).unwrap();
+    assert_eq!(events.try_iter().count(), 2);
+
+    client.handle(&msg(PlayerDisconnectCallPacket { player: 2 })).unwrap();
+    assert!(matches!(events.try_recv(), Ok(PlayerEvent::Left(p)) if p.id == 2));
+    client.handle(&snapshot()).unwrap();
+    assert!(events.try_recv().is_err());
+    assert!(client.player(2).is_none());
+
+    // anuke is never synced again
+    for _ in 0..ENTITY_TTL {
+        client.handle(&snapshot()).unwrap();
+    }
+    assert!(matches!(events.try_recv(), Ok(PlayerEvent::Left(p)) if p.id == 1));
+    assert_eq!(client.players().count(), 0);
+}
@@ -0,0 +1,325 @@
+use std::{collections::HashMap, sync::{Arc, OnceLock, RwLock}};
+use serde_json::Value;
+use crate::minet::protocol::Protocol;
+use crate::minet::entity::{self, SyncType};
+
+/// `ContentType` ordinals, as sent in
+/// `TypeValue::Content` and map files.
+pub mod kind {
+    pub const ITEM: u8 = 0;
+    pub const BLOCK: u8 = 1;
+    pub const LIQUID: u8 = 4;
+    pub const UNIT: u8 = 6;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub id: u16,
+    pub name: String,
+    /// The width of the block in tiles.
+    pub size: u8,
+    /// The build menu category,
+    /// or `None` for environment blocks.
+    pub category: Option<String>,
+    /// Items needed to build it, by item name.
+    /// Dumps must give these for blocks with a category.
+    pub requirements: Vec<(String, u32)>,
+    /// Which modules its buildings save. Builds since
+    /// v135 say so in the data, older ones don't.
+    pub has_items: bool,
+    pub has_power: bool,
+    pub has_liquids: bool,
+    /// Fields the building writes after the common ones,
+    /// for blocks sent in `blockSnapshot`.
+    pub sync: Vec<(String, SyncType)>,
+    /// The block's minimap color, `mapColor` in Mindustry.
+    pub color: Option<[u8; 3]>,
+    /// Whether ground units are stopped by it.
+    pub solid: bool,
+    /// Whether units of the building's own team
+    /// can pass it even though it is solid.
+    pub team_passable: bool,
+    /// For floors: whether it is water, slag and the like,
+    /// and whether ground units drown in it.
+    pub is_liquid: bool,
+    pub is_deep: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Item {
+    pub id: u16,
+    pub name: String,
+    pub flammability: f32,
+    pub explosiveness: f32,
+    pub radioactivity: f32,
+    pub charge: f32,
+    /// The drill tier needed to mine it.
+    pub hardness: u8,
+    /// How much it adds to build time.
+    pub cost: f32,
+    /// Whether blocks can be built with it.
+    pub buildable: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Liquid {
+    pub id: u16,
+    pub name: String,
+    pub temperature: f32,
+    pub heat_capacity: f32,
+    pub flammability: f32,
+    pub viscosity: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnitType {
+    pub id: u16,
+    pub name: String,
+    /// `mech`, `legs`, `flying` or `naval`.
+    pub kind: String,
+    pub health: Option<f32>,
+    pub flying: bool,
+    /// Top speed in world units per tick. It varies
+    /// too much between types to guess when the dump
+    /// leaves it out, so units without it aren't moved.
+    pub speed: Option<f32>,
+}
+
+/// A list of content of one type,
+/// looked up by id or name.
+#[derive(Clone, Debug)]
+pub struct Table<T> {
+    entries: Vec<T>,
+    ids: HashMap<u16, usize>,
+    names: HashMap<String, usize>,
+}
+
+impl<T> Default for Table<T> {
+    fn default() -> Self {
+        Self { entries: Vec::new(), ids: HashMap::new(), names: HashMap::new() }
+    }
+}
+
+impl<T> Table<T> {
+    fn insert(&mut self, id: u16, name: &str, entry: T) {
+        self.ids.insert(id, self.entries.len());
+        self.names.insert(name.to_string(), self.entries.len());
+        self.entries.push(entry);
+    }
+
+    pub fn get(&self, id: u16) -> Option<&T> {
+        self.ids.get(&id).map(|i| &self.entries[*i])
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&T> {
+        self.names.get(name).map(|i| &self.entries[*i])
+    }
+
+    #[allow(dead_code)]
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.entries.iter()
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Names and properties of the content
+/// in one Mindustry build, so that raw ids
+/// in packets and world data mean something.
+///
+/// Loaded from a JSON content dump:
+///
+/// ```json
+/// { "version": "v135",
+///   "blocks": [{ "id": 1, "name": "router", "size": 1,
+///                "category": "distribution", "requirements": { "copper": 3 },
+///                "has_items": true, "sync": ["reload: f32"], "color": "#6e7080",
+///                "solid": true, "team_passable": false, "is_liquid": false, "is_deep": false }],
+///   "items": [{ "id": 0, "name": "copper", "hardness": 1, "cost": 0.5 }],
+///   "liquids": [{ "id": 0, "name": "water" }],
+///   "units": [{ "id": 0, "name": "dagger", "kind": "mech", "health": 130, "speed": 0.5 }] }
+/// ```
+///
+/// Blocks with a `category` need `requirements`, and units
+/// need a `kind`. Other properties may be left out: those of
+/// blocks, items and liquids then take the defaults of
+/// Mindustry's classes, and a unit's `health` and `speed`
+/// are `None`.
+#[derive(Clone, Debug, Default)]
+pub struct ContentRegistry {
+    pub version: String,
+    pub blocks: Table<Block>,
+    pub items: Table<Item>,
+    pub liquids: Table<Liquid>,
+    pub units: Table<UnitType>,
+}
+
+/// Content dumps registered by protocol name. None are
+/// built in: a dump has to be exported from the game.
+static REGISTRIES: OnceLock<RwLock<HashMap<String, Arc<ContentRegistry>>>> = OnceLock::new();
+
+fn registries() -> &'static RwLock<HashMap<String, Arc<ContentRegistry>>> {
+    REGISTRIES.get_or_init(Default::default)
+}
+
+fn entries<'a>(value: &'a Value, key: &str) -> Result<Vec<(u16, &'a str, &'a Value)>, String> {
+    let list = match &value[key] {
+        Value::Null => return Ok(Vec::new()),
+        list => list.as_array().ok_or_else(|| format!("{} must be a list", key))?,
+    };
+    list.iter().map(|entry| {
+        let id = entry["id"].as_u64().filter(|id| *id <= u16::MAX as u64)
+            .ok_or_else(|| format!("{} entry without a valid id: {}", key, entry))?;
+        let name = entry["name"].as_str().ok_or_else(|| format!("{} {} has no name", key, id))?;
+        Ok((id as u16, name, entry))
+    }).collect()
+}
+
+/// Parses `rrggbb`, with an optional `#`
+/// and alpha, which is ignored.
+fn color(value: &Value) -> Option<[u8; 3]> {
+    let hex = value.as_str()?.trim_start_matches('#');
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    matches!(hex.len(), 6 | 8).then_some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+fn float(value: &Value, default: f32) -> f32 {
+    value.as_f64().map_or(default, |f| f as f32)
+}
+
+impl ContentRegistry {
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let value: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let mut registry = Self {
+            version: value["version"].as_str().unwrap_or_default().to_string(),
+            ..Self::default()
+        };
+
+        for (id, name, entry) in entries(&value, "blocks")? {
+            let category = entry["category"].as_str().map(str::to_string);
+            let requirements = match entry["requirements"].as_object() {
+                Some(map) => map.iter()
+                    .map(|(item, amount)| Some((item.clone(), amount.as_u64()? as u32)))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| format!("block {} has invalid requirements", name))?,
+                None if category.is_some() => return Err(format!("block {} has a category but no requirements", name)),
+                None => Vec::new(),
+            };
+            registry.blocks.insert(id, name, Block {
+                id, name: name.to_string(),
+                size: entry["size"].as_u64().unwrap_or(1) as u8,
+                category,
+                requirements,
+                has_items: entry["has_items"].as_bool().unwrap_or(false),
+                has_power: entry["has_power"].as_bool().unwrap_or(false),
+                has_liquids: entry["has_liquids"].as_bool().unwrap_or(false),
+                sync: match entry["sync"].as_array() {
+                    Some(fields) => entity::parse_fields(name, fields)?,
+                    None => Vec::new(),
+                },
+                color: color(&entry["color"]),
+                solid: entry["solid"].as_bool().unwrap_or(false),
+                team_passable: entry["team_passable"].as_bool().unwrap_or(false),
+                is_liquid: entry["is_liquid"].as_bool().unwrap_or(false),
+                is_deep: entry["is_deep"].as_bool().unwrap_or(false),
+            });
+        }
+        for (id, name, entry) in entries(&value, "items")? {
+            registry.items.insert(id, name, Item {
+                id, name: name.to_string(),
+                flammability: float(&entry["flammability"], 0.0),
+                explosiveness: float(&entry["explosiveness"], 0.0),
+                radioactivity: float(&entry["radioactivity"], 0.0),
+                charge: float(&entry["charge"], 0.0),
+                hardness: entry["hardness"].as_u64().unwrap_or(0) as u8,
+                cost: float(&entry["cost"], 1.0),
+                buildable: entry["buildable"].as_bool().unwrap_or(true),
+            });
+        }
+        for (id, name, entry) in entries(&value, "liquids")? {
+            registry.liquids.insert(id, name, Liquid {
+                id, name: name.to_string(),
+                temperature: float(&entry["temperature"], 0.5),
+                heat_capacity: float(&entry["heat_capacity"], 0.5),
+                flammability: float(&entry["flammability"], 0.0),
+                viscosity: float(&entry["viscosity"], 0.5),
+            });
+        }
+        for (id, name, entry) in entries(&value, "units")? {
+            let kind = entry["kind"].as_str().ok_or_else(|| format!("unit {} has no kind", name))?;
+            registry.units.insert(id, name, UnitType {
+                id, name: name.to_string(),
+                kind: kind.to_string(),
+                health: entry["health"].as_f64().map(|f| f as f32),
+                flying: entry["flying"].as_bool().unwrap_or(kind == "flying"),
+                speed: entry["speed"].as_f64().map(|f| f as f32),
+            });
+        }
+        Ok(registry)
+    }
+
+    /// Loads a content dump from a JSON file.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::from_json(&json)
+    }
+
+    /// The dump registered for `protocol`. Ids differ
+    /// between builds, so no other build's dump stands in.
+    pub fn for_protocol(protocol: &Protocol) -> Result<Arc<ContentRegistry>, String> {
+        registries().read().unwrap().get(&protocol.name).cloned()
+            .ok_or_else(|| format!("no content dump for protocol {}; load one exported from the game", protocol.name))
+    }
+
+    /// Uses `registry` for every protocol named `name`.
+    pub fn register(name: &str, registry: ContentRegistry) {
+        registries().write().unwrap().insert(name.to_string(), Arc::new(registry));
+    }
+
+    /// The name of a piece of content
+    /// given its `ContentType` ordinal.
+    pub fn name(&self, kind: u8, id: u16) -> Option<&str> {
+        match kind {
+            kind::ITEM => self.items.get(id).map(|c| c.name.as_str()),
+            kind::BLOCK => self.blocks.get(id).map(|c| c.name.as_str()),
+            kind::LIQUID => self.liquids.get(id).map(|c| c.name.as_str()),
+            kind::UNIT => self.units.get(id).map(|c| c.name.as_str()),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_content_registry() {
+    let custom = ContentRegistry::from_json(r##"{
+        "version": "test",
+        "blocks": [{ "id": 5, "name": "router", "size": 1, "category": "distribution", "requirements": { "copper": 3 }, "color": "#6e7080ff" },
+                   { "id": 6, "name": "core-shard", "size": 3 }]
+    }"##).unwrap();
+    let router = custom.blocks.by_name("router").unwrap();
+    assert_eq!(router.requirements, vec![("copper".to_string(), 3)]);
+    assert_eq!(router.color, Some([0x6e, 0x70, 0x80]));
+    assert_eq!(custom.blocks.get(6).unwrap().size, 3);
+    assert!(custom.items.is_empty());
+    assert_eq!(custom.name(kind::BLOCK, 5), Some("router"));
+    assert_eq!(custom.name(kind::BLOCK, 9999), None);
+    let protocol = Protocol::new("test", 200, Default::default(), &[]);
+    assert!(ContentRegistry::for_protocol(&protocol).unwrap_err().contains("no content dump for protocol test"));
+    ContentRegistry::register("test", custom);
+    assert_eq!(ContentRegistry::for_protocol(&protocol).unwrap().version, "test");
+
+    assert!(ContentRegistry::from_json(r#"{ "items": [{ "name": "copper" }] }"#).is_err());
+    assert!(ContentRegistry::from_json(r#"{ "blocks": [{ "id": 0, "name": "duo", "category": "turret" }] }"#).is_err());
+    assert!(ContentRegistry::from_json(r#"{ "units": [{ "id": 0, "name": "dagger" }] }"#).is_err());
+    let units = ContentRegistry::from_json(r#"{ "units": [{ "id": 0, "name": "flare", "kind": "flying" }] }"#).unwrap();
+    assert!(units.units.by_name("flare").unwrap().flying);
+    assert_eq!(units.units.by_name("flare").unwrap().speed, None);
+}
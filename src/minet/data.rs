@@ -2,10 +2,13 @@ use async_std::{net::{UdpSocket, TcpStream}, io::WriteExt};
 
 use crate::minet;
 use crate::minet::packets::*;
-use std::{fmt, fmt::Debug, any::{Any, TypeId}, io::{Write, Read, self}};
+use crate::minet::protocol::Protocol;
+use std::{fmt, fmt::Debug, any::Any, io, sync::Arc};
 
 pub trait DataClone: Any + DataCloneBox {
     fn serialize(&self, buf: &mut minet::Writer);   
+    fn json(&self) -> serde_json::Value;
+    fn name(&self) -> &'static str;
 }
 pub trait DataCloneBox {
     fn clone_box(&self) -> Box<dyn DataClone>;
@@ -16,6 +19,15 @@ impl<T> DataClone for T where T: Any + Data {
     fn serialize(&self, buf: &mut minet::Writer) {
         <Self as Data>::serialize(self, buf);
     }
+
+    fn json(&self) -> serde_json::Value {
+        <Self as Data>::json(self)
+    }
+
+    fn name(&self) -> &'static str {
+        let name = std::any::type_name::<T>();
+        name.rsplit("::").next().unwrap_or(name)
+    }
 }
 impl<T> DataCloneBox for T where T: Any + Data {
     fn clone_box(&self) -> Box<dyn DataClone> {
@@ -37,40 +49,194 @@ impl Debug for Box<dyn DataClone> {
     }
 }
 
+/// Implements `Data` field by field;
+/// see `minet_derive` for the attributes.
+pub use minet_derive::Data;
+
 /// Represents any kind of data
 /// that can be marshalled and unmarshalled
 /// into byte slices.
 pub trait Data : Clone + Debug {
     fn serialize(&self, buf: &mut minet::Writer);
     fn deserialize(data: &[u8]) -> Option<Self>;
+
+    /// Returns the decoded fields
+    /// as a JSON object, for tooling.
+    fn json(&self) -> serde_json::Value;
+
+    /// Builds a value from the output of `json`.
+    /// Types that cannot be edited
+    /// this way return `None`.
+    fn from_json(_value: &serde_json::Value) -> Option<Self> {
+        None
+    }
 }
 
 /// Represents data that can be
 /// directly sent as a UDP message
 /// or can be wrapped with a length
 /// to be sent as a TCP message.
+#[allow(dead_code)]
 pub trait Message : Data {}
 
+/// A `Packet` whose concrete type
+/// has been erased.
+pub trait PacketClone: DataClone {
+    fn write(&self, buf: &mut minet::Writer, protocol: &Protocol);
+    fn packet_name(&self) -> &'static str;
+    fn clone_packet(&self) -> Box<dyn PacketClone>;
+    fn as_data(&self) -> &dyn DataClone;
+}
+
+impl<T> PacketClone for T where T: Any + Packet {
+    fn write(&self, buf: &mut minet::Writer, protocol: &Protocol) {
+        <Self as Packet>::write(self, buf, protocol);
+    }
+
+    fn packet_name(&self) -> &'static str {
+        T::NAME
+    }
+
+    fn clone_packet(&self) -> Box<dyn PacketClone> {
+        Box::new(self.clone())
+    }
+
+    fn as_data(&self) -> &dyn DataClone {
+        self
+    }
+}
+impl Clone for Box<dyn PacketClone> {
+    fn clone(&self) -> Self {
+        self.clone_packet()
+    }
+}
+impl Debug for Box<dyn PacketClone> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.debug_fmt(f)
+    }
+}
+
+fn boxed<T: Packet + 'static>(packet: Option<T>) -> Option<Box<dyn PacketClone>> {
+    packet.map(|packet| {
+        let b: Box<dyn PacketClone> = Box::new(packet);
+        b
+    })
+}
+
+/// Decodes a packet by the name
+/// it is registered under.
+fn decode_packet(name: &str, data: &[u8], protocol: &Protocol) -> Option<Box<dyn PacketClone>> {
+    match name {
+        ConnectPacket::NAME => boxed(ConnectPacket::read(data, protocol)),
+        SendMessageCallPacket::NAME => boxed(SendMessageCallPacket::read(data, protocol)),
+        StateSnapshotCallPacket::NAME => boxed(StateSnapshotCallPacket::read(data, protocol)),
+        SetRulesCallPacket::NAME => boxed(SetRulesCallPacket::read(data, protocol)),
+        StreamBegin::NAME => boxed(StreamBegin::read(data, protocol)),
+        StreamChunk::NAME => boxed(StreamChunk::read(data, protocol)),
+        _ => decode_call(name, data, protocol)
+    }
+}
+
+/// Builds a packet by the name it is registered
+/// under from the output of `Data::json`.
+#[allow(dead_code)]
+fn packet_from_json(name: &str, value: &serde_json::Value) -> Option<Box<dyn PacketClone>> {
+    match name {
+        ConnectPacket::NAME => boxed(ConnectPacket::from_json(value)),
+        SendMessageCallPacket::NAME => boxed(SendMessageCallPacket::from_json(value)),
+        StateSnapshotCallPacket::NAME => boxed(StateSnapshotCallPacket::from_json(value)),
+        SetRulesCallPacket::NAME => boxed(SetRulesCallPacket::from_json(value)),
+        StreamBegin::NAME => boxed(StreamBegin::from_json(value)),
+        StreamChunk::NAME => boxed(StreamChunk::from_json(value)),
+        _ => call_from_json(name, value)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PacketMessage {
-    packet: Box<dyn DataClone>,
+    id: u8,
+    packet: Box<dyn PacketClone>,
+    protocol: Arc<Protocol>,
     pub compressed: bool
 }
 
 impl PacketMessage {
-    pub fn new<T: Packet + 'static>(packet: T, compressed: bool) -> Self {
-        Self {
+    /// Wraps `packet` with its id in `protocol`,
+    /// or `None` if the protocol has no such packet.
+    pub fn with_protocol<T: Packet + 'static>(protocol: &Arc<Protocol>, packet: T, compressed: bool) -> Option<Self> {
+        Some(Self {
+            id: protocol.id(T::NAME)?,
             packet: Box::new(packet),
+            protocol: protocol.clone(),
             compressed
-        }
+        })
     }
 
     pub fn id(&self) -> u8 {
-        if self.packet.type_id() == TypeId::of::<ConnectPacket>() {
-            3
+        self.id
+    }
+
+    #[allow(dead_code)]
+    pub fn packet(&self) -> &dyn DataClone {
+        self.packet.as_data()
+    }
+
+    #[allow(dead_code)]
+    pub fn protocol(&self) -> &Arc<Protocol> {
+        &self.protocol
+    }
+
+    /// Returns the packet if it is a `T`.
+    pub fn downcast<T: Packet + 'static>(&self) -> Option<&T> {
+        let any: &dyn Any = self.packet.as_data();
+        any.downcast_ref()
+    }
+
+    /// Builds a packet from its type name
+    /// and the output of `Data::json`.
+    #[allow(dead_code)]
+    pub fn from_json(protocol: &Arc<Protocol>, name: &str, value: &serde_json::Value, compressed: bool) -> Option<Self> {
+        Some(Self {
+            id: protocol.id(name)?,
+            packet: packet_from_json(name, value)?,
+            protocol: protocol.clone(),
+            compressed
+        })
+    }
+
+    /// Replaces the packet with one built from `value`,
+    /// keeping the id and compression.
+    #[allow(dead_code)]
+    pub fn with_json(&self, value: &serde_json::Value) -> Option<Self> {
+        Some(Self {
+            id: self.id,
+            packet: packet_from_json(self.packet.packet_name(), value)?,
+            protocol: self.protocol.clone(),
+            compressed: self.compressed
+        })
+    }
+
+    /// Decodes a message laid out by `protocol`.
+    pub fn deserialize_with(data: &[u8], protocol: &Arc<Protocol>) -> Option<Self> {
+        let mut reader = minet::Reader::new(data);
+        let id = reader.u8()?;
+        let len = reader.u16()? as usize;
+        let compressed = reader.bool()?;
+
+        let original_data = reader.bytes_remaining();
+        let decompressed_data_buf;
+        let decompressed_data = if compressed {
+            decompressed_data_buf = match lz4_flex::block::decompress(original_data, len) {
+                Err(e) =>  { eprintln!("error decompressing: {:?}", e); return None },
+                Ok(v) => v
+            };
+            &decompressed_data_buf
         } else {
-            panic!("invalid packet of type {:?}", self.packet.type_id())
-        }
+            original_data
+        };
+
+        let packet = decode_packet(protocol.name(id)?, decompressed_data, protocol)?;
+        Some(Self { id, packet, protocol: protocol.clone(), compressed })
     }
 }
 
@@ -79,7 +245,7 @@ impl Data for PacketMessage {
         buf.u8(self.id());
         
         let mut packet_data = minet::Writer::new();
-        self.packet.serialize(&mut packet_data);
+        self.packet.write(&mut packet_data, &self.protocol);
         let len = packet_data.0.len();
         buf.u16(len as u16);
 
@@ -92,37 +258,17 @@ impl Data for PacketMessage {
         }
     }
 
+    /// Decodes a message laid out by `Protocol::fallback`;
+    /// use `deserialize_with` to pick the server's protocol.
     fn deserialize(data: &[u8]) -> Option<Self> {
-        let mut reader = minet::Reader::new(data);
-        let id = reader.u8()?;
-        let len = reader.u16()? as usize;
-        let compressed = reader.bool()?;
-
-        let original_data = reader.bytes_remaining();
-        let mut decompressed_data_buf = Vec::new();
-        let decompressed_data = if compressed {
-            decompressed_data_buf = match lz4_flex::block::decompress(original_data, len) {
-                Err(e) =>  { eprintln!("error decompressing: {:?}", e); return None },
-                Ok(v) => v
-            };
-            &decompressed_data_buf
-        } else {
-            original_data
-        };
+        Self::deserialize_with(data, &Protocol::fallback())
+    }
 
-        match id {
-            3 => {
-                ConnectPacket::deserialize(decompressed_data).map(|packet| 
-                    Self {
-                        packet: Box::new(packet),
-                        compressed 
-                    }   
-                )
-            },
-            _ => {
-                None
-            }
-        }
+    fn json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "compressed": self.compressed,
+            "packet": self.packet.json(),
+        })
     }
 }
 
@@ -139,6 +285,70 @@ impl FrameworkMessage {
             inner: Box::new(inner)
         }
     }
+
+    #[allow(dead_code)]
+    pub fn id(&self) -> u8 {
+        let mut buf = minet::Writer::new();
+        self.inner.serialize(&mut buf);
+        buf.0[0]
+    }
+
+    #[allow(dead_code)]
+    pub fn inner(&self) -> &dyn DataClone {
+        self.inner.as_ref()
+    }
+
+    /// Returns the message as `T`
+    /// if that is what it is.
+    pub fn downcast<T: Framework + 'static>(&self) -> Option<&T> {
+        let any: &dyn Any = self.inner.as_ref();
+        any.downcast_ref()
+    }
+}
+
+/// Splits a TCP byte stream into messages.
+///
+/// Each message is prefixed with its length
+/// as a `u16`; reads from the socket may
+/// contain any number of partial messages.
+#[derive(Default)]
+pub struct FrameBuffer {
+    buf: Vec<u8>,
+}
+
+impl FrameBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends bytes read from the socket.
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Removes and returns the next complete
+    /// message, without its length prefix.
+    pub fn next_frame(&mut self) -> Option<Vec<u8>> {
+        if self.buf.len() < 2 {
+            return None
+        }
+        let len = ((self.buf[0] as usize) << 8) | self.buf[1] as usize;
+        if self.buf.len() < 2 + len {
+            return None
+        }
+        let frame = self.buf[2..2 + len].to_vec();
+        self.buf.drain(..2 + len);
+        Some(frame)
+    }
+}
+
+/// Prefixes a message with its length
+/// for sending over TCP.
+pub fn frame_tcp(data: &[u8]) -> Vec<u8> {
+    let mut buf = minet::Writer::new();
+    buf.u16(data.len() as u16);
+    buf.bytes(data);
+    buf.0
 }
 
 impl Data for FrameworkMessage {
@@ -153,9 +363,15 @@ impl Data for FrameworkMessage {
             return None;
         }
         match reader.peek_u8() {
+            Some(0) => {
+                Some(Self { inner: Box::new(Ping::deserialize(reader.bytes_remaining())?) })
+            },
             Some(1) => {
                 Some(Self {inner:Box::new(DiscoverHost)})
             },
+            Some(2) => {
+                Some(Self { inner: Box::new(KeepAlive) })
+            },
             Some(3) => {
                 Some(Self { inner: Box::new(RegisterUDP::deserialize(reader.bytes_remaining())?) })
             },
@@ -165,52 +381,80 @@ impl Data for FrameworkMessage {
             None| Some(_) => None,
         }
     }
+
+    fn json(&self) -> serde_json::Value {
+        self.inner.json()
+    }
 }
 
 impl Message for FrameworkMessage {}
 
+#[allow(dead_code)]
 pub async fn send_udp<T: Message>(socket: UdpSocket, data: T) -> io::Result<usize> {
     let mut buf = minet::Writer::new();
     data.serialize(&mut buf);
     socket.send(&buf.0).await
 }
 
+#[allow(dead_code)]
 pub async fn send_tcp<T: Message>(mut socket: TcpStream, data: T) -> io::Result<usize> {
     let mut buf = minet::Writer::new();
     data.serialize(&mut buf);
 
-    let mut buf_tcp = minet::Writer::new();
-    buf_tcp.u16(buf.0.len() as u16);
-    buf_tcp.bytes(&buf.0);
-
-    socket.write(&buf_tcp.0).await
+    socket.write(&frame_tcp(&buf.0)).await
 }
 
-pub fn parse_udp(data: &[u8]) -> Option<Box<dyn DataClone>> {
-    if data.len() == 0 {
-        return None
+#[allow(dead_code)]
+pub fn parse_udp(data: &[u8], protocol: &Arc<Protocol>) -> Option<Box<dyn DataClone>> {
+    if data.is_empty() {
+        None
     } else if data[0] == 0xfe {
-        return FrameworkMessage::deserialize(data).map(|b| {
+        FrameworkMessage::deserialize(data).map(|b| {
             let b: Box<dyn DataClone> = Box::new(b);
             b
-        });
+        })
     } else {
-        return PacketMessage::deserialize(data).map(|b| {
+        PacketMessage::deserialize_with(data, protocol).map(|b| {
             let b: Box<dyn DataClone> = Box::new(b);
             b
-        });
+        })
     }
 }
 
 #[test]
 fn test_framework_message() {
     assert!(FrameworkMessage::deserialize(&[0xfe, 0x1]).is_some());
+    let ping = FrameworkMessage::deserialize(&[0xfe, 0x0, 0, 0, 0, 7, 1]).unwrap();
+    assert!(ping.downcast::<Ping>().is_some_and(|p| p.id == 7 && p.is_reply));
     assert!(FrameworkMessage::deserialize(&[0xfe, 0x3, 0, 0, 0, 5]).is_some());
     assert!(FrameworkMessage::deserialize(&[0xfe, 0x4, 0, 0, 0, 5]).is_some());
 }
 
+/// A compressed `ConnectPacket` from a v135 client
+/// named "allen", as sent over TCP without its length.
+#[cfg(test)]
+pub const CONNECT_MESSAGE: &[u8] = &[0x03,0x00,0x44,0x01,0xf0,0x35,0x00,0x00,0x00,0x87,0x01,0x00,0x08,0x6f,0x66,0x66,0x69,0x63,0x69,0x61,0x6c,0x01,0x00,0x05,0x61,0x6c,0x6c,0x65,0x6e,0x01,0x00,0x05,0x65,0x6e,0x5f,0x55,0x53,0x01,0x00,0x0c,0x79,0x33,0x2f,0x70,0x33,0x58,0x37,0x77,0x45,0x74,0x6b,0x3d,0x4a,0xef,0x2f,0x79,0x87,0x17,0x4f,0x99,0x00,0x00,0x00,0x00,0xbd,0x7a,0xa1,0xb2,0x00,0xff,0x76,0xa6,0xff,0x00];
+
 #[test]
 fn test_packet_message() {
-    let data = &[0x03,0x00,0x44,0x01,0xf0,0x35,0x00,0x00,0x00,0x87,0x01,0x00,0x08,0x6f,0x66,0x66,0x69,0x63,0x69,0x61,0x6c,0x01,0x00,0x05,0x61,0x6c,0x6c,0x65,0x6e,0x01,0x00,0x05,0x65,0x6e,0x5f,0x55,0x53,0x01,0x00,0x0c,0x79,0x33,0x2f,0x70,0x33,0x58,0x37,0x77,0x45,0x74,0x6b,0x3d,0x4a,0xef,0x2f,0x79,0x87,0x17,0x4f,0x99,0x00,0x00,0x00,0x00,0xbd,0x7a,0xa1,0xb2,0x00,0xff,0x76,0xa6,0xff,0x00];
+    let data = CONNECT_MESSAGE;
     assert!(PacketMessage::deserialize(data).is_some());
+
+    let msg = PacketMessage::deserialize(data).unwrap();
+    assert_eq!(msg.id(), 3);
+    assert_eq!(msg.packet().name(), "ConnectPacket");
+    assert_eq!(Data::json(&msg)["packet"]["player_name"], "allen");
+}
+
+#[test]
+fn test_frame_buffer() {
+    let mut frames = FrameBuffer::new();
+    frames.push(&[0x00, 0x02, 0xfe]);
+    assert_eq!(frames.next_frame(), None);
+    frames.push(&[0x01, 0x00, 0x01, 0xfe, 0x00]);
+    assert_eq!(frames.next_frame(), Some(vec![0xfe, 0x01]));
+    assert_eq!(frames.next_frame(), Some(vec![0xfe]));
+    assert_eq!(frames.next_frame(), None);
+    frames.push(&[0x00]);
+    assert_eq!(frames.next_frame(), Some(vec![]));
 }
@@ -0,0 +1,515 @@
+use std::{collections::HashMap, sync::{Arc, OnceLock, RwLock}};
+use serde_json::{json, Map, Value};
+use crate::minet::{BuildPlan, EntitySnapshotCallPacket, Field, Reader, TypeValue};
+use crate::minet::protocol::Protocol;
+
+/// How a sync field is encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncType {
+    Bool, U8, I16, I32, I64, F32, F64,
+    Str,
+    Team,
+    /// `TypeIO.writeController`.
+    Controller,
+    /// `TypeIO.writeUnit`: a kind byte and an id or position.
+    Unit,
+    /// A packed tile position, -1 for none.
+    Tile,
+    Vec2,
+    /// An item id and amount.
+    Stack,
+    Statuses,
+    Mounts,
+    Abilities,
+    /// Up to 20 `BuildPlan`s, or -1 for none.
+    Plans,
+    Object,
+    /// Payloads embed whole buildings and units,
+    /// which minet can't decode. A snapshot stops
+    /// at the first entity with a payload field.
+    Payloads,
+}
+
+impl SyncType {
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "bool" => SyncType::Bool,
+            "u8" => SyncType::U8,
+            "i16" => SyncType::I16,
+            "i32" => SyncType::I32,
+            "i64" => SyncType::I64,
+            "f32" => SyncType::F32,
+            "f64" => SyncType::F64,
+            "str" => SyncType::Str,
+            "team" => SyncType::Team,
+            "controller" => SyncType::Controller,
+            "unit" => SyncType::Unit,
+            "tile" => SyncType::Tile,
+            "vec2" => SyncType::Vec2,
+            "stack" => SyncType::Stack,
+            "statuses" => SyncType::Statuses,
+            "mounts" => SyncType::Mounts,
+            "abilities" => SyncType::Abilities,
+            "plans" => SyncType::Plans,
+            "object" => SyncType::Object,
+            "payloads" => SyncType::Payloads,
+            _ => return None,
+        })
+    }
+
+    /// Reads one value, as JSON so that
+    /// fields minet doesn't track are kept too.
+    pub fn read(self, reader: &mut Reader) -> Option<Value> {
+        Some(match self {
+            SyncType::Bool => json!(reader.bool()?),
+            SyncType::U8 | SyncType::Team => json!(reader.u8()?),
+            SyncType::I16 => json!(reader.i16()?),
+            SyncType::I32 | SyncType::Tile => json!(reader.i32()?),
+            SyncType::I64 => json!(reader.i64()?),
+            SyncType::F32 => json!(reader.f32()?),
+            SyncType::F64 => json!(reader.f64()?),
+            SyncType::Str => json!(reader.nullable_str()?),
+            SyncType::Controller => Controller::read(reader)?.json(),
+            SyncType::Unit => {
+                let kind = reader.u8()?;
+                let id = reader.i32()?;
+                match kind {
+                    0 => Value::Null,
+                    1 => json!({ "block": id }),
+                    _ => json!({ "unit": id }),
+                }
+            },
+            SyncType::Vec2 => json!([reader.f32()?, reader.f32()?]),
+            SyncType::Stack => json!({ "item": reader.i16()?, "amount": reader.i32()? }),
+            SyncType::Statuses => {
+                let len = reader.i32()?.max(0);
+                Value::Array((0..len).map(|_| Some(json!({ "id": reader.i16()?, "time": reader.f32()? }))).collect::<Option<_>>()?)
+            },
+            SyncType::Mounts => {
+                let len = reader.u8()?;
+                Value::Array((0..len).map(|_| {
+                    let flags = reader.u8()?;
+                    Some(json!({ "shoot": flags & 1 != 0, "rotate": flags & 2 != 0, "aim": [reader.f32()?, reader.f32()?] }))
+                }).collect::<Option<_>>()?)
+            },
+            SyncType::Abilities => {
+                let len = reader.u8()?;
+                Value::Array((0..len).map(|_| reader.f32().map(|f| json!(f))).collect::<Option<_>>()?)
+            },
+            SyncType::Plans => {
+                let len = reader.i32()?;
+                if len < 0 {
+                    return Some(Value::Null);
+                }
+                Value::Array((0..len).map(|_| BuildPlan::read(reader).map(|p| p.json())).collect::<Option<_>>()?)
+            },
+            SyncType::Object => TypeValue::read(reader)?.json(),
+            SyncType::Payloads => return None,
+        })
+    }
+}
+
+/// Parses a list of `"name: type"` sync fields.
+pub fn parse_fields(owner: &str, fields: &[Value]) -> Result<Vec<(String, SyncType)>, String> {
+    fields.iter().map(|field| {
+        let field = field.as_str().ok_or_else(|| format!("{}: fields must be strings", owner))?;
+        let (name, ty) = field.split_once(':').ok_or_else(|| format!("{}: expected name: type, got {}", owner, field))?;
+        let ty = SyncType::parse(ty.trim()).ok_or_else(|| format!("{}: unknown type in {}", owner, field))?;
+        Ok((name.trim().to_string(), ty))
+    }).collect()
+}
+
+/// What is controlling a unit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Controller {
+    /// A player, by id.
+    Player(i32),
+    /// Following another unit, by id.
+    Formation(i32),
+    /// A logic processor, by position.
+    Logic(i32),
+    /// The unit's default AI.
+    Ai,
+}
+
+impl Controller {
+    fn read(reader: &mut Reader) -> Option<Self> {
+        Some(match reader.u8()? {
+            0 => Controller::Player(reader.i32()?),
+            1 => Controller::Formation(reader.i32()?),
+            3 => Controller::Logic(reader.i32()?),
+            _ => Controller::Ai,
+        })
+    }
+
+    fn json(&self) -> Value {
+        match self {
+            Controller::Player(id) => json!({ "type": "player", "id": id }),
+            Controller::Formation(id) => json!({ "type": "formation", "id": id }),
+            Controller::Logic(pos) => json!({ "type": "logic", "pos": pos }),
+            Controller::Ai => json!({ "type": "ai" }),
+        }
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        let id = || value["id"].as_i64().map(|id| id as i32);
+        Some(match value["type"].as_str()? {
+            "player" => Controller::Player(id()?),
+            "formation" => Controller::Formation(id()?),
+            "logic" => Controller::Logic(value["pos"].as_i64()? as i32),
+            _ => Controller::Ai,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntityKind {
+    Unit,
+    Player,
+}
+
+/// The sync layout of one entity class.
+#[derive(Clone, Debug)]
+pub struct EntityClass {
+    #[allow(dead_code)]
+    pub id: u8,
+    pub name: String,
+    pub kind: EntityKind,
+    pub fields: Vec<(String, SyncType)>,
+}
+
+/// The entity classes of one Mindustry build by class id,
+/// as assigned by its generated `EntityMapping`.
+///
+/// Loaded from JSON, with fields in the order
+/// `readSync` reads them:
+///
+/// ```json
+/// { "classes": [{ "id": 12, "name": "Player", "kind": "player",
+///                 "fields": ["admin: bool", "boosting: bool", ...] }] }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct EntityMapping {
+    classes: HashMap<u8, EntityClass>,
+}
+
+/// Built-in mappings by protocol name.
+const BUILTIN_MAPPINGS: &[(&str, &str)] = &[
+    ("v135", include_str!("../../content/v135-entities.json")),
+];
+
+static MAPPINGS: OnceLock<RwLock<HashMap<String, Arc<EntityMapping>>>> = OnceLock::new();
+
+fn mappings() -> &'static RwLock<HashMap<String, Arc<EntityMapping>>> {
+    MAPPINGS.get_or_init(|| {
+        let mappings = BUILTIN_MAPPINGS.iter()
+            .map(|(name, json)| (name.to_string(), Arc::new(EntityMapping::from_json(json).unwrap())))
+            .collect();
+        RwLock::new(mappings)
+    })
+}
+
+/// One entity decoded from a snapshot.
+#[derive(Clone, Debug)]
+pub struct EntitySync {
+    pub id: i32,
+    pub class_id: u8,
+    pub fields: Map<String, Value>,
+}
+
+impl EntityMapping {
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let value: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let mut mapping = Self::default();
+        for class in value["classes"].as_array().ok_or("mapping needs classes")? {
+            let id = class["id"].as_u64().filter(|id| *id <= 0xff).ok_or_else(|| format!("invalid class id: {}", class["id"]))? as u8;
+            let name = class["name"].as_str().ok_or_else(|| format!("class {} has no name", id))?;
+            let kind = match class["kind"].as_str() {
+                Some("player") => EntityKind::Player,
+                _ => EntityKind::Unit,
+            };
+            let fields = parse_fields(name, class["fields"].as_array().ok_or_else(|| format!("{} has no fields", name))?)?;
+            mapping.classes.insert(id, EntityClass { id, name: name.to_string(), kind, fields });
+        }
+        Ok(mapping)
+    }
+
+    #[allow(dead_code)]
+    pub fn load(path: &str) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::from_json(&json)
+    }
+
+    /// The mapping registered for `protocol`. Class ids and
+    /// sync fields differ between builds, so no other
+    /// build's mapping stands in.
+    pub fn for_protocol(protocol: &Protocol) -> Result<Arc<EntityMapping>, String> {
+        mappings().read().unwrap().get(&protocol.name).cloned()
+            .ok_or_else(|| format!("no entity mapping for protocol {}", protocol.name))
+    }
+
+    /// Uses `mapping` for every protocol named `name`.
+    #[allow(dead_code)]
+    pub fn register(name: &str, mapping: EntityMapping) {
+        mappings().write().unwrap().insert(name.to_string(), Arc::new(mapping));
+    }
+
+    pub fn class(&self, id: u8) -> Option<&EntityClass> {
+        self.classes.get(&id)
+    }
+
+    pub fn class_named(&self, name: &str) -> Option<&EntityClass> {
+        self.classes.values().find(|c| c.name == name)
+    }
+
+    /// Decodes the entities in an `entitySnapshot`.
+    ///
+    /// Entities carry no length, so decoding stops
+    /// at the first unknown class. The entities before it
+    /// are returned along with the error.
+    pub fn decode(&self, amount: i16, data: &[u8]) -> (Vec<EntitySync>, Option<String>) {
+        let mut reader = Reader::new(data);
+        let mut entities = Vec::new();
+        for _ in 0..amount.max(0) {
+            let (id, class_id) = match (reader.i32(), reader.u8()) {
+                (Some(id), Some(class_id)) => (id, class_id),
+                _ => return (entities, Some("snapshot ended early".to_string())),
+            };
+            let class = match self.classes.get(&class_id) {
+                Some(class) => class,
+                None => return (entities, Some(format!("unknown entity class {}", class_id))),
+            };
+            let mut fields = Map::new();
+            for (name, ty) in &class.fields {
+                match ty.read(&mut reader) {
+                    Some(value) => { fields.insert(name.clone(), value); },
+                    None => return (entities, Some(format!("can't decode {}.{} of entity {}", class.name, name, id))),
+                }
+            }
+            entities.push(EntitySync { id, class_id, fields });
+        }
+        (entities, None)
+    }
+}
+
+/// The latest known state of a unit or player.
+#[derive(Clone, Debug)]
+pub struct Entity {
+    pub id: i32,
+    #[allow(dead_code)]
+    pub class: String,
+    pub kind: EntityKind,
+    pub x: f32,
+    pub y: f32,
+    pub rotation: f32,
+    #[allow(dead_code)]
+    pub health: f32,
+    pub team: u8,
+    /// The unit type id, for units.
+    pub unit_type: Option<u16>,
+    #[allow(dead_code)]
+    pub controller: Option<Controller>,
+    /// The name, for players.
+    pub name: Option<String>,
+    /// The id of the unit a player controls.
+    pub unit: Option<i32>,
+    /// Every decoded sync field.
+    pub fields: Map<String, Value>,
+    /// The snapshot that last included this entity.
+    pub updated: u64,
+}
+
+/// Units and players as seen in `entitySnapshot`s.
+#[derive(Clone, Debug, Default)]
+pub struct EntityTable {
+    entities: HashMap<i32, Entity>,
+    /// Counts applied snapshots.
+    pub snapshot: u64,
+}
+
+impl EntityTable {
+    /// Applies a snapshot, returning how many entities it updated.
+    /// On error, the entities decoded before it are still applied.
+    pub fn apply(&mut self, mapping: &EntityMapping, packet: &EntitySnapshotCallPacket) -> Result<usize, String> {
+        self.snapshot += 1;
+        let (entities, err) = mapping.decode(packet.amount, &packet.data);
+        let count = entities.len();
+        for sync in entities {
+            if let Some(class) = mapping.class(sync.class_id) {
+                self.update(class, sync);
+            }
+        }
+        match err {
+            Some(e) => Result::Err(e),
+            None => Ok(count),
+        }
+    }
+
+    fn update(&mut self, class: &EntityClass, sync: EntitySync) {
+        let f32_field = |name: &str| sync.fields.get(name).and_then(Value::as_f64).map(|f| f as f32);
+        let entity = Entity {
+            id: sync.id,
+            class: class.name.clone(),
+            kind: class.kind,
+            x: f32_field("x").unwrap_or_default(),
+            y: f32_field("y").unwrap_or_default(),
+            rotation: f32_field("rotation").unwrap_or_default(),
+            health: f32_field("health").unwrap_or_default(),
+            team: sync.fields.get("team").and_then(Value::as_u64).unwrap_or_default() as u8,
+            unit_type: sync.fields.get("type").and_then(Value::as_i64).map(|t| t as u16),
+            controller: sync.fields.get("controller").and_then(Controller::from_json),
+            name: sync.fields.get("name").and_then(Value::as_str).map(str::to_string),
+            unit: sync.fields.get("unit").and_then(|u| u["unit"].as_i64()).map(|id| id as i32),
+            fields: sync.fields,
+            updated: self.snapshot,
+        };
+        self.entities.insert(entity.id, entity);
+    }
+
+    pub fn get(&self, id: i32) -> Option<&Entity> {
+        self.entities.get(&id)
+    }
+
+    #[allow(dead_code)]
+    pub fn insert(&mut self, entity: Entity) {
+        self.entities.insert(entity.id, entity);
+    }
+
+    pub fn remove(&mut self, id: i32) -> Option<Entity> {
+        self.entities.remove(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Entity> {
+        self.entities.values()
+    }
+
+    #[allow(dead_code)]
+    pub fn units(&self) -> impl Iterator<Item = &Entity> {
+        self.iter().filter(|e| e.kind == EntityKind::Unit)
+    }
+
+    pub fn players(&self) -> impl Iterator<Item = &Entity> {
+        self.iter().filter(|e| e.kind == EntityKind::Player)
+    }
+
+    #[allow(dead_code)]
+    pub fn team(&self, team: u8) -> impl Iterator<Item = &Entity> {
+        self.iter().filter(move |e| e.team == team)
+    }
+
+    #[allow(dead_code)]
+    pub fn player_by_name(&self, name: &str) -> Option<&Entity> {
+        self.players().find(|p| p.name.as_deref() == Some(name))
+    }
+
+    /// The unit controlled by the player `player_id`.
+    pub fn unit_of(&self, player_id: i32) -> Option<&Entity> {
+        self.get(self.get(player_id)?.unit?)
+    }
+
+    /// The player controlling the unit `unit_id`.
+    pub fn player_of(&self, unit_id: i32) -> Option<&Entity> {
+        self.players().find(|p| p.unit == Some(unit_id))
+    }
+
+    /// The entity closest to `(x, y)`
+    /// among those matching `filter`.
+    #[allow(dead_code)]
+    pub fn nearest(&self, x: f32, y: f32, filter: impl Fn(&Entity) -> bool) -> Option<&Entity> {
+        let dist = |e: &Entity| (e.x - x).powi(2) + (e.y - y).powi(2);
+        self.iter().filter(|e| filter(e)).min_by(|a, b| dist(a).total_cmp(&dist(b)))
+    }
+
+    /// Forgets entities missing from the last `snapshots` snapshots.
+    /// The server stops syncing entities once they are gone.
+    pub fn prune(&mut self, snapshots: u64) {
+        let snapshot = self.snapshot;
+        self.entities.retain(|_, e| snapshot - e.updated < snapshots);
+    }
+}
+
+#[test]
+fn test_entity_table() {
+    use crate::minet::Writer;
+
+    let mut data = Writer::new();
+    // a player
+    data.i32(7);
+    data.u8(12);
+    data.bool(false); // admin
+    data.bool(false); // boosting
+    data.i32(-1); // color
+    data.f32(0.0);
+    data.f32(0.0);
+    data.str("allen");
+    data.i16(-1); // selectedBlock
+    data.i32(0);
+    data.bool(false); // shooting
+    data.u8(1); // team
+    data.bool(true); // typing
+    data.u8(2); // unit
+    data.i32(40);
+    data.f32(80.0);
+    data.f32(96.0);
+    // their dagger
+    data.i32(40);
+    data.u8(4);
+    data.u8(0); // abilities
+    data.f32(0.0); // ammo
+    data.f32(0.0); // armor
+    data.f32(90.0); // baseRotation
+    data.u8(0); // controller
+    data.i32(7);
+    data.f32(0.0); // elevation
+    data.f64(0.0); // flag
+    data.f32(130.0); // health
+    data.bool(false); // isShooting
+    data.i32(-1); // mineTile
+    data.u8(1); // mounts
+    data.u8(3);
+    data.f32(1.0);
+    data.f32(2.0);
+    data.i32(1); // plans
+    BuildPlan { breaking: true, x: 3, y: 4, block: 0, rotation: 0, config: TypeValue::Null }.write(&mut data);
+    data.f32(45.0); // rotation
+    data.f32(0.0); // shield
+    data.f32(0.0); // shieldAlpha
+    data.bool(true); // spawnedByCore
+    data.i16(0); // stack
+    data.i32(0);
+    data.i32(1); // statuses
+    data.i16(2);
+    data.f32(60.0);
+    data.u8(1); // team
+    data.i16(0); // type
+    data.bool(false); // updateBuilding
+    data.f32(0.0); // vel
+    data.f32(0.0);
+    data.f32(80.0); // x
+    data.f32(96.0); // y
+    // something this mapping doesn't know
+    data.i32(50);
+    data.u8(99);
+
+    let mut table = EntityTable::default();
+    let packet = EntitySnapshotCallPacket { amount: 3, data: data.0 };
+    let mapping = EntityMapping::for_protocol(&Protocol::by_name("v135").unwrap()).unwrap();
+    assert!(EntityMapping::for_protocol(&Protocol::new("v141", 141, Default::default(), &[])).is_err());
+    assert_eq!(table.apply(&mapping, &packet), Result::Err("unknown entity class 99".to_string()));
+
+    let player = table.player_by_name("allen").unwrap();
+    assert_eq!(player.id, 7);
+    let dagger = table.unit_of(7).unwrap();
+    assert_eq!(dagger.controller, Some(Controller::Player(7)));
+    assert_eq!((dagger.x, dagger.rotation, dagger.health, dagger.unit_type), (80.0, 45.0, 130.0, Some(0)));
+    assert_eq!(dagger.fields["plans"][0]["x"], 3);
+    assert_eq!(table.units().count(), 1);
+    assert_eq!(table.team(1).count(), 2);
+    assert_eq!(table.nearest(0.0, 0.0, |e| e.kind == EntityKind::Unit).unwrap().id, 40);
+
+    table.apply(&mapping, &EntitySnapshotCallPacket { amount: 0, data: vec![] }).unwrap();
+    table.prune(2);
+    assert_eq!(table.iter().count(), 2);
+    table.prune(1);
+    assert_eq!(table.iter().count(), 0);
+}
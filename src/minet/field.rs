@@ -0,0 +1,150 @@
+use crate::minet::{Reader, Writer};
+
+/// A value with a fixed encoding,
+/// usable as a `#[derive(Data)]` field.
+///
+/// Strings, lists and optional values
+/// are encoded differently depending on
+/// the packet, so the derive handles them itself.
+pub trait Field: Sized {
+    fn write(&self, buf: &mut Writer);
+    fn read(reader: &mut Reader) -> Option<Self>;
+    fn json(&self) -> serde_json::Value;
+    fn from_json(value: &serde_json::Value) -> Option<Self>;
+}
+
+macro_rules! field {
+    ($($ty:ident => $as_json:ident as $json_ty:ty),* $(,)?) => {$(
+        impl Field for $ty {
+            fn write(&self, buf: &mut Writer) {
+                buf.$ty(*self);
+            }
+
+            fn read(reader: &mut Reader) -> Option<Self> {
+                reader.$ty()
+            }
+
+            fn json(&self) -> serde_json::Value {
+                serde_json::json!(self)
+            }
+
+            fn from_json(value: &serde_json::Value) -> Option<Self> {
+                value.$as_json().map(|v| v as $json_ty as Self)
+            }
+        }
+    )*};
+}
+
+field! {
+    u8 => as_u64 as u64,
+    i8 => as_i64 as i64,
+    u16 => as_u64 as u64,
+    i16 => as_i64 as i64,
+    u32 => as_u64 as u64,
+    i32 => as_i64 as i64,
+    u64 => as_u64 as u64,
+    i64 => as_i64 as i64,
+    f32 => as_f64 as f64,
+    f64 => as_f64 as f64,
+}
+
+impl Field for bool {
+    fn write(&self, buf: &mut Writer) {
+        buf.bool(*self);
+    }
+
+    fn read(reader: &mut Reader) -> Option<Self> {
+        reader.bool()
+    }
+
+    fn json(&self) -> serde_json::Value {
+        serde_json::json!(self)
+    }
+
+    fn from_json(value: &serde_json::Value) -> Option<Self> {
+        value.as_bool()
+    }
+}
+
+/// Sent as its 16 raw bytes.
+impl Field for uuid::Uuid {
+    fn write(&self, buf: &mut Writer) {
+        buf.bytes(self.as_bytes());
+    }
+
+    fn read(reader: &mut Reader) -> Option<Self> {
+        let bytes = reader.bytes(16)?.try_into().ok()?;
+        Some(uuid::Uuid::from_bytes(bytes))
+    }
+
+    fn json(&self) -> serde_json::Value {
+        serde_json::json!(self.to_string())
+    }
+
+    fn from_json(value: &serde_json::Value) -> Option<Self> {
+        value.as_str()?.parse().ok()
+    }
+}
+
+/// Fixed-size arrays have no length prefix.
+impl<T: Field, const N: usize> Field for [T; N] {
+    fn write(&self, buf: &mut Writer) {
+        for item in self {
+            item.write(buf);
+        }
+    }
+
+    fn read(reader: &mut Reader) -> Option<Self> {
+        let items = (0..N).map(|_| T::read(reader)).collect::<Option<Vec<_>>>()?;
+        items.try_into().ok()
+    }
+
+    fn json(&self) -> serde_json::Value {
+        serde_json::Value::Array(self.iter().map(Field::json).collect())
+    }
+
+    fn from_json(value: &serde_json::Value) -> Option<Self> {
+        let items = value.as_array()?;
+        if items.len() != N {
+            return None;
+        }
+        let items = items.iter().map(T::from_json).collect::<Option<Vec<_>>>()?;
+        items.try_into().ok()
+    }
+}
+
+#[test]
+fn test_derive() {
+    use crate::minet::Data;
+
+    #[derive(Clone, Debug, PartialEq, Data)]
+    #[data(tag = 9)]
+    struct Plan {
+        breaking: bool,
+        pos: [i16; 2],
+        #[data(if = "!breaking")]
+        block: Option<u16>,
+        #[data(nullable)]
+        config: Option<String>,
+        #[data(len = "u8")]
+        tags: Vec<String>,
+        #[data(len = "i32", optional)]
+        note: Option<String>,
+    }
+
+    let plan = Plan {
+        breaking: false, pos: [3, -4], block: Some(7),
+        config: None, tags: vec!["a".to_owned()], note: None,
+    };
+    let mut buf = Writer::new();
+    Data::serialize(&plan, &mut buf);
+    assert_eq!(buf.0, [9, 0, 0, 3, 0xff, 0xfc, 0, 7, 0, 1, 1, 0, 1, b'a']);
+    assert_eq!(Plan::deserialize(&buf.0).unwrap(), plan);
+    assert_eq!(Plan::from_json(&Data::json(&plan)).unwrap(), plan);
+
+    let breaking = Plan::deserialize(&[9, 1, 0, 3, 0xff, 0xfc, 1, 0, 1, b'x', 0, 0, 0, 0, 2, b'h', b'i']).unwrap();
+    assert_eq!(breaking.block, None);
+    assert_eq!(breaking.config.as_deref(), Some("x"));
+    assert_eq!(breaking.note.as_deref(), Some("hi"));
+    assert!(Plan::deserialize(&buf.0[1..]).is_none());
+}
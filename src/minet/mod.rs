@@ -2,5 +2,29 @@ mod stream;
 pub use stream::*;
 mod data;
 pub use data::*;
+mod field;
+pub use field::*;
+mod typeio;
+pub use typeio::*;
 mod packets;
-pub use packets::*;
\ No newline at end of file
+pub use packets::*;
+// Captures are only read and written by the proxy and the replay tool.
+#[allow(dead_code)]
+mod capture;
+#[allow(unused_imports)]
+pub use capture::*;
+
+pub mod protocol;
+pub mod content;
+pub mod entity;
+pub mod world;
+pub mod state;
+pub mod rules;
+pub mod save;
+pub mod render;
+pub mod players;
+pub mod client;
+pub mod schematic;
+pub mod builder;
+pub mod pathfind;
+pub mod movement;
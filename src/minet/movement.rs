@@ -0,0 +1,254 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use crate::minet::{BuildPlan, ClientSnapshotCallPacket, PacketMessage, SetPositionCallPacket};
+use crate::minet::client::Client;
+use crate::minet::content::ContentRegistry;
+use crate::minet::entity::Entity;
+use crate::minet::pathfind::{PathGrid, UnitClass};
+
+/// The world is this many units per tile.
+const TILE_SIZE: f32 = 8.0;
+
+const TICKS_PER_SEC: f32 = 60.0;
+
+/// How much of the unit's top speed to use. The server
+/// checks moves against it with some allowance for lag,
+/// so staying under it keeps the unit from being reset.
+const SPEED_MARGIN: f32 = 0.9;
+
+/// The longest step taken at once, so that a stalled
+/// connection doesn't turn into a jump.
+const MAX_STEP: Duration = Duration::from_millis(500);
+
+/// How far the server's position for the unit may be
+/// from ours, on top of the distance covered in
+/// `MAX_STEP`, before ours is taken to be wrong.
+const TOLERANCE: f32 = 3.0 * TILE_SIZE;
+
+/// Steers our unit along a path, giving the positions
+/// to report in each `clientSnapshot`.
+///
+/// The server trusts client positions within the unit's
+/// speed, so the unit moves no faster than its type
+/// allows. Where it is comes from the server at first,
+/// and again whenever the server's idea of it drifts
+/// or a `setPosition` arrives, after which the path
+/// is planned again.
+#[derive(Clone, Debug, Default)]
+pub struct Movement {
+    /// Where the unit is, in world units.
+    pub x: f32,
+    pub y: f32,
+    /// World units per tick.
+    pub velocity: (f32, f32),
+    /// Degrees, counter-clockwise from east.
+    pub rotation: f32,
+    target: Option<(i16, i16)>,
+    waypoints: VecDeque<(f32, f32)>,
+    /// Whether `x` and `y` are known.
+    placed: bool,
+    last: Option<Instant>,
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    (a.0 - b.0).hypot(a.1 - b.1)
+}
+
+/// Our unit, if the world data says who we are
+/// and we are alive.
+fn our_unit(client: &Client) -> Option<&Entity> {
+    client.entities.unit_of(client.world_data.as_ref()?.player_id)
+}
+
+/// The unit's top speed, which the server holds it to,
+/// so it isn't moved when the content dump doesn't say.
+fn speed(content: &ContentRegistry, unit: &Entity) -> Result<f32, String> {
+    let unit_type = unit.unit_type.and_then(|id| content.units.get(id)).ok_or("unknown unit type")?;
+    unit_type.speed.ok_or_else(|| format!("content dump {:?} has no speed for {}", content.version, unit_type.name))
+}
+
+impl Movement {
+    /// Plans a path for our unit to the tile `(x, y)`. Fails if
+    /// we have no unit, its speed is unknown or it can't get there.
+    pub fn move_to(&mut self, client: &Client, content: &ContentRegistry, x: i16, y: i16) -> Result<(), String> {
+        let unit = our_unit(client).ok_or("no unit to move")?;
+        speed(content, unit)?;
+        if !self.placed {
+            (self.x, self.y) = (unit.x, unit.y);
+            self.placed = true;
+        }
+        self.target = Some((x, y));
+        self.plan(client, content, unit)
+    }
+
+    fn plan(&mut self, client: &Client, content: &ContentRegistry, unit: &Entity) -> Result<(), String> {
+        let Some(target) = self.target else { return Ok(()) };
+        let class = unit.unit_type.and_then(|id| content.units.get(id)).map_or(UnitClass::Ground, UnitClass::of);
+        let from = ((self.x / TILE_SIZE).round() as i16, (self.y / TILE_SIZE).round() as i16);
+        let path = PathGrid::new(&client.world, content).map(|grid| grid.find_path(class, unit.team, from, target));
+        let Some(path) = path.inspect_err(|_| self.stop())? else {
+            self.stop();
+            return Err(format!("no path to {}, {}", target.0, target.1));
+        };
+        self.waypoints = path.into_iter().skip(1)
+            .map(|(x, y)| (x as f32 * TILE_SIZE, y as f32 * TILE_SIZE))
+            .collect();
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        self.target = None;
+        self.waypoints.clear();
+        self.velocity = (0.0, 0.0);
+    }
+
+    /// The tile being moved to, until it is reached.
+    #[allow(dead_code)]
+    pub fn target(&self) -> Option<(i16, i16)> {
+        self.target
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.target.is_some()
+    }
+
+    /// Takes the position from a `setPosition`.
+    pub fn correct(&mut self, msg: &PacketMessage) {
+        if let Some(p) = msg.downcast::<SetPositionCallPacket>() {
+            (self.x, self.y) = (p.x, p.y);
+            self.placed = true;
+            // the path no longer starts here
+            self.waypoints.clear();
+        }
+    }
+
+    /// Moves the unit on by the time since the last call and
+    /// returns the `clientSnapshot` to send, as `Client::snapshot`
+    /// does. Meant to be called at the snapshot rate.
+    pub fn snapshot(&mut self, client: &Client, content: &ContentRegistry, snapshot_id: i32, plans: Vec<BuildPlan>, now: Instant) -> Option<ClientSnapshotCallPacket> {
+        let mut snapshot = client.snapshot(snapshot_id, plans)?;
+        let elapsed = self.last.map_or(Duration::ZERO, |last| (now - last).min(MAX_STEP));
+        self.last = Some(now);
+        let Some(unit) = our_unit(client) else {
+            // respawning puts us somewhere else
+            self.placed = false;
+            self.stop();
+            return Some(snapshot);
+        };
+        // e.g. respawned as another type
+        let Ok(speed) = speed(content, unit).map(|speed| speed * SPEED_MARGIN) else {
+            self.stop();
+            return Some(snapshot);
+        };
+
+        let max_drift = TOLERANCE + speed * TICKS_PER_SEC * MAX_STEP.as_secs_f32();
+        if !self.placed || distance((self.x, self.y), (unit.x, unit.y)) > max_drift {
+            (self.x, self.y) = (unit.x, unit.y);
+            self.placed = true;
+            self.waypoints.clear();
+        }
+        if self.target.is_some() && self.waypoints.is_empty() && self.plan(client, content, unit).is_err() {
+            return Some(snapshot);
+        }
+
+        let ticks = elapsed.as_secs_f32() * TICKS_PER_SEC;
+        let (start, mut left) = ((self.x, self.y), speed * ticks);
+        while let Some(&next) = self.waypoints.front() {
+            let d = distance((self.x, self.y), next);
+            if d > left {
+                self.x += (next.0 - self.x) / d * left;
+                self.y += (next.1 - self.y) / d * left;
+                break;
+            }
+            (self.x, self.y) = next;
+            left -= d;
+            self.waypoints.pop_front();
+        }
+        if self.waypoints.is_empty() {
+            self.target = None;
+        }
+        self.velocity = if ticks > 0.0 { ((self.x - start.0) / ticks, (self.y - start.1) / ticks) } else { (0.0, 0.0) };
+        if self.velocity != (0.0, 0.0) {
+            self.rotation = self.velocity.1.atan2(self.velocity.0).to_degrees().rem_euclid(360.0);
+        }
+
+        let pointer = self.waypoints.back().copied().unwrap_or((self.x, self.y));
+        snapshot.x = self.x;
+        snapshot.y = self.y;
+        (snapshot.pointer_x, snapshot.pointer_y) = pointer;
+        (snapshot.x_velocity, snapshot.y_velocity) = self.velocity;
+        snapshot.rotation = self.rotation;
+        snapshot.base_rotation = self.rotation;
+        (snapshot.view_x, snapshot.view_y) = (self.x, self.y);
+        Some(snapshot)
+    }
+}
+
+#[test]
+fn test_movement() {
+    use crate::minet::entity::{EntityKind, EntityTable};
+    use crate::minet::protocol::Protocol;
+    use crate::minet::save::{ContentHeader, WorldData};
+    use crate::minet::world::TileMap;
+
+    let content = ContentRegistry::from_json(r#"{
+        "blocks": [{ "id": 0, "name": "air" }],
+        "units": [{ "id": 0, "name": "dagger", "kind": "mech", "speed": 0.5 }, { "id": 1, "name": "nova", "kind": "legs" }]
+    }"#).unwrap();
+    let player = |id, unit| Entity {
+        id, class: "Player".to_string(), kind: EntityKind::Player, x: 0.0, y: 0.0, rotation: 0.0, health: 0.0, team: 1,
+        unit_type: None, controller: None, name: Some("robot".to_string()), unit, fields: Default::default(), updated: 0,
+    };
+    let unit = |x, y| Entity { kind: EntityKind::Unit, x, y, unit_type: Some(0), name: None, unit: None, ..player(2, None) };
+    let mut entities = EntityTable::default();
+    entities.insert(player(1, Some(2)));
+    entities.insert(unit(8.0, 8.0));
+    let data = WorldData {
+        rules: Default::default(), meta: Default::default(), wave: 1, wave_time: 0.0, tick: 0.0, rand0: 0, rand1: 0,
+        player_id: 1, player: Default::default(), content: ContentHeader::from_registry(&content), map: TileMap::new(20, 10),
+    };
+    let mut client = Client::new(Protocol::fallback(), content.clone().into(), Default::default());
+    client.entities = entities;
+    client.load_world(data);
+
+    let mut movement = Movement::default();
+    assert!(movement.move_to(&client, &content, 30, 1).is_err());
+    movement.move_to(&client, &content, 11, 1).unwrap();
+    let start = Instant::now();
+    let first = movement.snapshot(&client, &content, 0, vec![], start).unwrap();
+    assert_eq!((first.x, first.y, first.unit_id), (8.0, 8.0, 2));
+
+    let snapshot = movement.snapshot(&client, &content, 1, vec![], start + Duration::from_millis(100)).unwrap();
+    let moved = distance((8.0, 8.0), (snapshot.x, snapshot.y));
+    assert!((moved - 0.5 * SPEED_MARGIN * 6.0).abs() < 0.01, "{}", moved);
+    assert!(snapshot.x > 8.0 && snapshot.x_velocity > 0.0);
+    assert_eq!(snapshot.rotation, 0.0);
+    let step = movement.snapshot(&client, &content, 2, vec![], start + Duration::from_secs(5)).unwrap();
+    assert!(distance((snapshot.x, snapshot.y), (step.x, step.y)) <= 0.5 * SPEED_MARGIN * 30.0 + 0.01);
+
+    // the server echoes what we report
+    for i in 3..40 {
+        let snapshot = movement.snapshot(&client, &content, i, vec![], start + Duration::from_secs(5) + MAX_STEP * i as u32).unwrap();
+        client.entities.insert(unit(snapshot.x, snapshot.y));
+    }
+    assert!(!movement.is_moving());
+    assert_eq!((movement.x, movement.y), (88.0, 8.0));
+
+    // the server disagrees by more than lag can explain
+    client.entities.insert(unit(40.0, 40.0));
+    movement.move_to(&client, &content, 3, 3).unwrap();
+    let snapshot = movement.snapshot(&client, &content, 40, vec![], start + Duration::from_secs(30)).unwrap();
+    // so the path starts over from there
+    assert!(snapshot.x < 40.0 && snapshot.x == snapshot.y);
+    assert!((distance((40.0, 40.0), (snapshot.x, snapshot.y)) - 0.5 * SPEED_MARGIN * 30.0).abs() < 0.01);
+    let set = SetPositionCallPacket { x: 16.0, y: 16.0 };
+    movement.correct(&PacketMessage::with_protocol(&Protocol::fallback(), set, false).unwrap());
+    assert_eq!((movement.x, movement.y), (16.0, 16.0));
+
+    // a nova's speed isn't known
+    client.entities.insert(Entity { unit_type: Some(1), ..unit(16.0, 16.0) });
+    assert!(movement.move_to(&client, &content, 5, 5).unwrap_err().contains("no speed for nova"));
+    let snapshot = movement.snapshot(&client, &content, 41, vec![], start + Duration::from_secs(31)).unwrap();
+    assert!(!movement.is_moving());
+    assert_eq!(snapshot.x_velocity, 0.0);
+}
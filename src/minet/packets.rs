@@ -1,12 +1,33 @@
 use crate::minet::data::*;
+use crate::minet::protocol::{Layout, Protocol};
 use crate::minet;
+use crate::minet::Field;
 
 /// Represents data that can be
 /// embedded inside of a `PacketMessage`
 /// to constitute a `Message`.
-pub trait Packet : Data {}
+pub trait Packet : Data {
+    /// The name this packet is registered under
+    /// in a `Protocol`.
+    const NAME: &'static str;
 
-#[derive(Clone, Debug)]
+    /// Whether the packet is sent over TCP
+    /// rather than UDP.
+    const RELIABLE: bool = true;
+
+    /// Decodes the packet as laid out by `protocol`.
+    /// Only packets whose layout differs
+    /// between builds need to override this.
+    fn read(data: &[u8], _protocol: &Protocol) -> Option<Self> {
+        Self::deserialize(data)
+    }
+
+    fn write(&self, buf: &mut minet::Writer, _protocol: &Protocol) {
+        Data::serialize(self, buf)
+    }
+}
+
+#[derive(Clone, Debug, Data)]
 pub struct ConnectPacket {
     pub version_build: u32,
     pub version_type: String,
@@ -16,123 +37,346 @@ pub struct ConnectPacket {
     pub uuid: uuid::Uuid,
     pub mobile: bool,
     pub color: u32,
+    /// The names of the client's mods.
+    #[data(len = "u8", default)]
+    pub mods: Vec<String>,
 }
 
-impl Data for ConnectPacket {
-    fn serialize(&self, buf: &mut minet::Writer) {
-        buf.u32(self.version_build);
-        buf.str(&self.version_type);
-        buf.str(&self.player_name);
-        buf.str(&self.locale);
-        buf.str(&self.usid);
+impl Packet for ConnectPacket {
+    const NAME: &'static str = "ConnectPacket";
+}
 
-        let uuid_bytes = self.uuid.as_bytes();
-        buf.bytes(uuid_bytes);
+/// Starts a stream of data too large for one packet,
+/// such as the world data sent on join.
+#[derive(Clone, Debug, Data)]
+pub struct StreamBegin {
+    pub id: i32,
+    /// The length of the whole stream.
+    pub total: i32,
+    /// The id of the packet the stream makes up.
+    pub kind: u8,
+}
+
+impl Packet for StreamBegin {
+    const NAME: &'static str = "StreamBegin";
+}
 
-        buf.bool(self.mobile);
-        buf.u32(self.color);
-        buf.u8(0); // no mods
+#[derive(Clone, Debug, Data)]
+pub struct StreamChunk {
+    pub id: i32,
+    pub data: Vec<u8>,
+}
+
+impl Packet for StreamChunk {
+    const NAME: &'static str = "StreamChunk";
+}
+
+/// Collects `StreamChunk`s until a stream is complete.
+#[derive(Debug, Default)]
+pub struct StreamBuilder {
+    streams: std::collections::HashMap<i32, (StreamBegin, Vec<u8>)>,
+}
+
+impl StreamBuilder {
+    /// Takes a `StreamBegin` or `StreamChunk`, returning
+    /// the packet id and data of a stream it completes.
+    pub fn push(&mut self, msg: &PacketMessage) -> Option<(u8, Vec<u8>)> {
+        if let Some(begin) = msg.downcast::<StreamBegin>() {
+            self.streams.insert(begin.id, (begin.clone(), Vec::new()));
+            return None;
+        }
+        let chunk = msg.downcast::<StreamChunk>()?;
+        let (begin, data) = self.streams.get_mut(&chunk.id)?;
+        data.extend(&chunk.data);
+        if data.len() < begin.total as usize {
+            return None;
+        }
+        let (begin, data) = self.streams.remove(&chunk.id)?;
+        Some((begin.kind, data))
     }
+}
 
-    fn deserialize(data: &[u8]) -> Option<Self> {
+/// Sent by the server to show a chat message.
+#[derive(Clone, Debug, Data)]
+pub struct SendMessageCallPacket {
+    pub message: String,
+    /// The message without the sender's name.
+    /// Builds without this field repeat `message`.
+    pub unformatted: String,
+    /// The id of the sending player, or -1.
+    pub sender: i32,
+}
+
+impl Packet for SendMessageCallPacket {
+    const NAME: &'static str = "SendMessageCallPacket";
+
+    fn read(data: &[u8], protocol: &Protocol) -> Option<Self> {
+        if protocol.layout.chat_unformatted {
+            return Self::deserialize(data);
+        }
         let mut reader = minet::Reader::new(data);
-        let version_build = reader.u32()?;
-        let version_type = reader.str()?.to_string();
-        let player_name = reader.str()?.to_string();
-        let locale = reader.str()?.to_string();
-        let usid = reader.str()?.to_string();
-        let uuid_bytes = reader.bytes(16)?;
-        let uuid_bytes = <&[u8] as TryInto<[u8; 16]>>::try_into(uuid_bytes).ok()?;
-        let mobile = reader.bool()?;
-        let color = reader.u32()?;
-        // ignore mods
+        let message = reader.str()?.to_string();
+        let sender = reader.i32()?;
+        Some(Self { unformatted: message.clone(), message, sender })
+    }
 
+    fn write(&self, buf: &mut minet::Writer, protocol: &Protocol) {
+        if protocol.layout.chat_unformatted {
+            return Data::serialize(self, buf);
+        }
+        buf.str(&self.message);
+        buf.i32(self.sender);
+    }
+}
+
+/// Sent by the server every few seconds
+/// with the state of the game.
+#[derive(Clone, Debug, Data)]
+pub struct StateSnapshotCallPacket {
+    /// Ticks until the next wave.
+    pub wave_time: f32,
+    pub wave: i32,
+    pub enemies: i32,
+    pub paused: bool,
+    pub game_over: bool,
+    pub time_data: i32,
+    /// Missing before v135, along with the seed.
+    pub tps: u8,
+    pub rand0: i64,
+    pub rand1: i64,
+    /// Each team's core items;
+    /// see `minet::state::read_core_data`.
+    pub core_data: Vec<u8>,
+}
+
+impl Packet for StateSnapshotCallPacket {
+    const NAME: &'static str = "StateSnapshotCallPacket";
+    const RELIABLE: bool = false;
+
+    fn read(data: &[u8], protocol: &Protocol) -> Option<Self> {
+        if protocol.layout.state_tps {
+            return Self::deserialize(data);
+        }
+        let mut reader = minet::Reader::new(data);
         Some(Self {
-            version_build, version_type: version_type.to_string(),
-            player_name: player_name.to_string(), locale: locale.to_string(),
-            usid: usid.to_string(),
-            uuid: uuid::Uuid::from_bytes(uuid_bytes),
-            mobile, color
+            wave_time: reader.f32()?,
+            wave: reader.i32()?,
+            enemies: reader.i32()?,
+            paused: reader.bool()?,
+            game_over: reader.bool()?,
+            time_data: reader.i32()?,
+            tps: 60, rand0: 0, rand1: 0,
+            core_data: {
+                let len = reader.u16()? as usize;
+                reader.bytes(len)?.to_vec()
+            },
         })
     }
+
+    fn write(&self, buf: &mut minet::Writer, protocol: &Protocol) {
+        if protocol.layout.state_tps {
+            return Data::serialize(self, buf);
+        }
+        buf.f32(self.wave_time);
+        buf.i32(self.wave);
+        buf.i32(self.enemies);
+        buf.bool(self.paused);
+        buf.bool(self.game_over);
+        buf.i32(self.time_data);
+        buf.u16(self.core_data.len() as u16);
+        buf.bytes(&self.core_data);
+    }
 }
-impl Packet for ConnectPacket {}
 
-/// Represents data that can be
-/// embedded inside of a `FrameworkMessage`
-/// to constitute a `Message`.
-pub trait Framework : Data {}
+/// Sent by the server when the rules change.
+#[derive(Clone, Debug, Data)]
+pub struct SetRulesCallPacket {
+    /// `Rules` as JSON; see `minet::rules::Rules`.
+    #[data(len = "i32")]
+    pub rules: String,
+}
 
-#[derive(Clone, Debug)]
-pub struct RegisterUDP {
-    pub id: u32
+impl Packet for SetRulesCallPacket {
+    const NAME: &'static str = "SetRulesCallPacket";
 }
 
-impl Data for RegisterUDP {
-    fn serialize(&self, buf: &mut minet::Writer) {
-        buf.u8(3);
-        buf.u32(self.id);
+include!(concat!(env!("OUT_DIR"), "/calls.rs"));
+
+/// A block to build or break,
+/// as written by `TypeIO.writePlan`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BuildPlan {
+    pub breaking: bool,
+    pub x: i16,
+    pub y: i16,
+    /// Unused when breaking.
+    pub block: u16,
+    pub rotation: u8,
+    pub config: minet::TypeValue,
+}
+
+impl BuildPlan {
+    /// Reads a plan whose config was
+    /// written by a build with `layout`.
+    pub fn read_with(reader: &mut minet::Reader, layout: &Layout) -> Option<Self> {
+        let breaking = reader.bool()?;
+        let pos = reader.i32()?;
+        let (x, y) = ((pos >> 16) as i16, pos as i16);
+        if breaking {
+            return Some(Self { breaking, x, y, block: 0, rotation: 0, config: minet::TypeValue::Null });
+        }
+        let block = reader.u16()?;
+        let rotation = reader.u8()?;
+        reader.u8()?;
+        let config = minet::TypeValue::read_with(reader, layout)?;
+        Some(Self { breaking, x, y, block, rotation, config })
     }
+}
 
-    fn deserialize(data: &[u8]) -> Option<Self> {
-        let mut reader = minet::Reader::new(data);
-        if reader.u8() != Some(3) {
-            return None
+impl Field for BuildPlan {
+    fn write(&self, buf: &mut minet::Writer) {
+        buf.bool(self.breaking);
+        buf.i32(((self.x as i32) << 16) | (self.y as u16 as i32));
+        if !self.breaking {
+            buf.u16(self.block);
+            buf.u8(self.rotation);
+            buf.u8(1); // always has a config
+            self.config.write(buf);
         }
+    }
 
-        let id = reader.u32()?;
+    fn read(reader: &mut minet::Reader) -> Option<Self> {
+        Self::read_with(reader, &Layout::default())
+    }
 
-        Some(Self { id })
+    fn json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "breaking": self.breaking,
+            "x": self.x,
+            "y": self.y,
+            "block": self.block,
+            "rotation": self.rotation,
+            "config": self.config.json(),
+        })
+    }
+
+    fn from_json(value: &serde_json::Value) -> Option<Self> {
+        Some(Self {
+            breaking: value["breaking"].as_bool()?,
+            x: i16::from_json(&value["x"])?,
+            y: i16::from_json(&value["y"])?,
+            block: u16::from_json(&value["block"]).unwrap_or(0),
+            rotation: u8::from_json(&value["rotation"]).unwrap_or(0),
+            config: minet::TypeValue::from_json(&value["config"]).unwrap_or(minet::TypeValue::Null),
+        })
     }
 }
-impl Framework for RegisterUDP {}
 
-#[derive(Clone, Debug)]
-pub struct RegisterTCP {
-    pub id: u32
+/// Plans a client may send in one snapshot.
+pub const MAX_PLANS: usize = 20;
+
+/// Writes the plans of a `clientSnapshot`: an `i16`
+/// count, then up to `MAX_PLANS` plans.
+pub fn write_plans(buf: &mut minet::Writer, plans: &[BuildPlan]) {
+    let plans = &plans[..plans.len().min(MAX_PLANS)];
+    buf.i16(plans.len() as i16);
+    plans.iter().for_each(|p| p.write(buf));
 }
 
-impl Data for RegisterTCP {
-    fn serialize(&self, buf: &mut minet::Writer) {
-        buf.u8(4);
-        buf.u32(self.id);
-    }
+/// Reads plans written by `write_plans`.
+/// A count of -1 means none.
+pub fn read_plans(reader: &mut minet::Reader, layout: &Layout) -> Option<Vec<BuildPlan>> {
+    let len = reader.i16()?;
+    (0..len.max(0)).map(|_| BuildPlan::read_with(reader, layout)).collect()
+}
 
-    fn deserialize(data: &[u8]) -> Option<Self> {
-        let mut reader = minet::Reader::new(data);
-        if reader.u8() != Some(4) {
-            return None
-        }
+/// The server's reply to `DiscoverHost`,
+/// sent as a bare UDP datagram.
+///
+/// Strings here are prefixed with
+/// a `u8` length instead of a `u16`.
+#[derive(Clone, Debug, Data)]
+pub struct ServerInfo {
+    #[data(len = "u8")]
+    pub name: String,
+    #[data(len = "u8")]
+    pub map: String,
+    pub players: i32,
+    pub wave: i32,
+    pub version: i32,
+    #[data(len = "u8")]
+    pub version_type: String,
+    pub gamemode: u8,
+    pub player_limit: i32,
+    #[data(len = "u8")]
+    pub description: String,
+    #[data(len = "u8", optional)]
+    pub mode_name: Option<String>,
+}
 
-        let id = reader.u32()?;
+/// Represents data that can be
+/// embedded inside of a `FrameworkMessage`
+/// to constitute a `Message`.
+pub trait Framework : Data {}
 
-        Some(Self { id })
-    }
+/// Sent either way to measure latency. The other
+/// side sends it back with `is_reply` set.
+#[derive(Clone, Debug, Data)]
+#[data(tag = 0)]
+pub struct Ping {
+    pub id: i32,
+    pub is_reply: bool,
 }
-impl Framework for RegisterTCP {}
+impl Framework for Ping {}
 
-#[derive(Clone, Debug)]
-pub struct DiscoverHost;
+#[derive(Clone, Debug, Data)]
+#[data(tag = 2)]
+pub struct KeepAlive;
+impl Framework for KeepAlive {}
 
-impl Data for DiscoverHost {
-    fn serialize(&self, buf: &mut minet::Writer) {
-        buf.u8(1);
-    }
+#[derive(Clone, Debug, Data)]
+#[data(tag = 3)]
+pub struct RegisterUDP {
+    pub id: u32
+}
+impl Framework for RegisterUDP {}
 
-    fn deserialize(data: &[u8]) -> Option<Self> {
-        let mut reader = minet::Reader::new(data);
-        if reader.u8() != Some(1) {
-            return None
-        }
+#[derive(Clone, Debug, Data)]
+#[data(tag = 4)]
+pub struct RegisterTCP {
+    pub id: u32
+}
+impl Framework for RegisterTCP {}
+
+#[derive(Clone, Debug, Data)]
+#[data(tag = 1)]
+pub struct DiscoverHost;
 
-        Some(Self)
+#[test]
+fn test_stream_builder() {
+    fn msg<T: Packet + 'static>(packet: T) -> PacketMessage {
+        PacketMessage::with_protocol(&Protocol::fallback(), packet, false).unwrap()
     }
+    let mut streams = StreamBuilder::default();
+    assert_eq!(streams.push(&msg(StreamBegin { id: 1, total: 5, kind: 2 })), None);
+    assert_eq!(streams.push(&msg(StreamChunk { id: 1, data: vec![1, 2, 3] })), None);
+    assert_eq!(streams.push(&msg(StreamChunk { id: 2, data: vec![9] })), None);
+    assert_eq!(streams.push(&msg(StreamChunk { id: 1, data: vec![4, 5] })), Some((2, vec![1, 2, 3, 4, 5])));
 }
 
 #[test]
 fn test_connect_packet() {
     let data = &[0, 0, 0, 135, 1, 0, 8, 111, 102, 102, 105, 99, 105, 97, 108, 1, 0, 5, 97, 108, 108, 101, 110, 1, 0, 5, 101, 110, 95, 85, 83, 1, 0, 12, 121, 51, 47, 112, 51, 88, 55, 119, 69, 116, 107, 61, 74, 239, 47, 121, 135, 23, 79, 153, 0, 0, 0, 0, 189, 122, 161, 178, 0, 255, 118, 166, 255, 0];
-    assert!(ConnectPacket::deserialize(data).is_some());
+    let packet = ConnectPacket::deserialize(data).unwrap();
+    assert_eq!(packet.player_name, "allen");
+    assert!(packet.mods.is_empty());
+
+    let mut reencoded = minet::Writer::new();
+    Data::serialize(&packet, &mut reencoded);
+    assert_eq!(&reencoded.0, data);
+    let json = Data::json(&packet);
+    assert_eq!(json["uuid"], packet.uuid.to_string());
+    assert_eq!(ConnectPacket::from_json(&json).unwrap().uuid, packet.uuid);
 
     let mut encoded = minet::Writer::new();
     Data::serialize(&ConnectPacket { 
@@ -143,7 +387,72 @@ fn test_connect_packet() {
         usid: "AAAAAAAA".to_owned(),
         uuid: uuid::Uuid::new_v4(),
         mobile: false,
-        color: 0x00ff00ff
+        color: 0x00ff00ff,
+        mods: vec![],
     }, &mut encoded);
     assert!(ConnectPacket::deserialize(&encoded.0).is_some());
-}
\ No newline at end of file
+}
+#[test]
+fn test_protocol_layout() {
+    use std::sync::Arc;
+    use crate::minet::protocol::Layout;
+    // a build whose chat carries no unformatted text
+    let layout = Layout { chat_unformatted: false, ..Layout::default() };
+    let older = Arc::new(Protocol::new("old", 126, layout, &[("SendMessageCallPacket", 0x47), ("ClientSnapshotCallPacket", 0x0c)]));
+    let v135 = Protocol::fallback();
+    let packet = SendMessageCallPacket {
+        message: "[red]allen[]: hi".to_owned(),
+        unformatted: "hi".to_owned(),
+        sender: 7,
+    };
+
+    let mut old = minet::Writer::new();
+    Data::serialize(&PacketMessage::with_protocol(&older, packet.clone(), false).unwrap(), &mut old);
+    let mut new = minet::Writer::new();
+    Data::serialize(&PacketMessage::with_protocol(&v135, packet, false).unwrap(), &mut new);
+    assert_ne!(old.0[0], new.0[0]);
+    assert_eq!(new.0.len() - old.0.len(), 5);
+
+    let decoded = PacketMessage::deserialize_with(&old.0, &older).unwrap();
+    assert!(Arc::ptr_eq(decoded.protocol(), &older));
+    assert_eq!(decoded.downcast::<SendMessageCallPacket>().unwrap().sender, 7);
+    assert!(PacketMessage::deserialize_with(&old.0, &v135).is_none());
+
+    let plan = BuildPlan { breaking: true, x: 1, y: 2, block: 0, rotation: 0, config: minet::TypeValue::Null };
+    let snapshot = ClientSnapshotCallPacket {
+        snapshot_id: 0, unit_id: 1, dead: false, x: 0.0, y: 0.0, pointer_x: 0.0, pointer_y: 0.0, rotation: 0.0, base_rotation: 0.0,
+        x_velocity: 0.0, y_velocity: 0.0, mining: -1, boosting: false, shooting: false, chatting: false, building: true,
+        plans: vec![plan; MAX_PLANS + 5], view_x: 0.0, view_y: 0.0, view_width: 0.0, view_height: 0.0,
+    };
+    let mut old = minet::Writer::new();
+    Data::serialize(&PacketMessage::with_protocol(&older, snapshot.clone(), false).unwrap(), &mut old);
+    let mut new = minet::Writer::new();
+    Data::serialize(&PacketMessage::with_protocol(&v135, snapshot, false).unwrap(), &mut new);
+    assert_eq!(new.0.len(), old.0.len());
+    let decoded = PacketMessage::deserialize_with(&new.0, &v135).unwrap();
+    assert_eq!(decoded.downcast::<ClientSnapshotCallPacket>().unwrap().plans.len(), MAX_PLANS);
+
+    // a command config, which only builds since v136 send
+    let commands = Arc::new(Protocol::new("v136", 136, Layout { unit_command_ids: true, ..Layout::default() }, &[("TileConfigCallPacket", 0x61)]));
+    let config = TileConfigCallPacket { player: -1, building: 0, value: minet::TypeValue::UnitCommand(2) };
+    let mut buf = minet::Writer::new();
+    Data::serialize(&PacketMessage::with_protocol(&commands, config, false).unwrap(), &mut buf);
+    let decoded = PacketMessage::deserialize_with(&buf.0, &commands).unwrap();
+    assert_eq!(decoded.downcast::<TileConfigCallPacket>().unwrap().value, minet::TypeValue::UnitCommand(2));
+    buf.0[0] = v135.id("TileConfigCallPacket").unwrap();
+    assert!(PacketMessage::deserialize_with(&buf.0, &v135).is_none());
+
+    let info = ServerInfo {
+        name: "server".to_owned(), map: "Ancient Caldera".to_owned(),
+        players: 3, wave: 12, version: 135, version_type: "official".to_owned(),
+        gamemode: 0, player_limit: 0, description: "".to_owned(), mode_name: None,
+    };
+    let mut encoded = minet::Writer::new();
+    Data::serialize(&info, &mut encoded);
+    let decoded = ServerInfo::deserialize(&encoded.0).unwrap();
+    assert_eq!(decoded.version, 135);
+    assert_eq!(decoded.mode_name, None);
+
+    assert!(RegisterUDP::deserialize(&[4, 0, 0, 0, 1]).is_none());
+    assert_eq!(RegisterTCP::deserialize(&[4, 0, 0, 0, 1]).unwrap().id, 1);
+}
@@ -0,0 +1,336 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use crate::minet::content::{kind, ContentRegistry, UnitType};
+use crate::minet::world::World;
+
+/// Costs of a straight and a diagonal step,
+/// before the cost of the tile stepped on.
+const STRAIGHT: u32 = 10;
+const DIAGONAL: u32 = 14;
+
+const NEIGHBORS: [(i16, i16); 8] = [(1, 0), (0, 1), (-1, 0), (0, -1), (1, 1), (-1, 1), (-1, -1), (1, -1)];
+
+/// How a unit gets around, which decides
+/// the tiles it can cross.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnitClass {
+    /// Mechs and tanks: stopped by walls,
+    /// solid buildings and deep water.
+    Ground,
+    /// Crosses buildings and water,
+    /// but not static walls.
+    Legs,
+    /// Only on water.
+    Naval,
+    Flying,
+}
+
+impl UnitClass {
+    pub fn of(unit: &UnitType) -> Self {
+        match unit.kind.as_str() {
+            _ if unit.flying => UnitClass::Flying,
+            "legs" => UnitClass::Legs,
+            "naval" => UnitClass::Naval,
+            _ => UnitClass::Ground,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Cell {
+    /// A static wall, such as a cliff.
+    wall: bool,
+    liquid: bool,
+    deep: bool,
+    /// The team of a solid building on it,
+    /// and whether that team may pass.
+    building: Option<(u8, bool)>,
+}
+
+/// What `World` says about each tile that
+/// matters to movement, made once per query
+/// or whenever the world changes.
+#[derive(Clone, Debug, Default)]
+pub struct PathGrid {
+    pub width: u16,
+    pub height: u16,
+    cells: Vec<Cell>,
+}
+
+impl PathGrid {
+    /// Floors and walls are looked up by name in `content`,
+    /// which must know every block in the world.
+    pub fn new(world: &World, content: &ContentRegistry) -> Result<Self, String> {
+        world.check_content(content)?;
+        let (width, height) = (world.tiles.width, world.tiles.height);
+        let block = |id: u16| world.header.name(kind::BLOCK, id).and_then(|name| content.blocks.by_name(name));
+        let mut cells: Vec<Cell> = world.tiles.tiles().iter().map(|t| {
+            let floor = block(t.floor);
+            Cell {
+                wall: !t.building && block(t.block).is_some_and(|b| b.solid),
+                liquid: floor.is_some_and(|f| f.is_liquid),
+                deep: floor.is_some_and(|f| f.is_deep),
+                building: None,
+            }
+        }).collect();
+        // map buildings since taken down are only gone from `buildings`
+        for b in world.buildings() {
+            let Some(block) = content.blocks.get(b.block).filter(|block| block.solid) else { continue };
+            let size = block.size.max(1) as i16;
+            let (left, bottom) = (b.x() - (size - 1) / 2, b.y() - (size - 1) / 2);
+            for x in left..left + size {
+                for y in bottom..bottom + size {
+                    if (0..width as i16).contains(&x) && (0..height as i16).contains(&y) {
+                        cells[y as usize * width as usize + x as usize].building = Some((b.team, block.team_passable));
+                    }
+                }
+            }
+        }
+        Ok(Self { width, height, cells })
+    }
+
+    fn index(&self, x: i16, y: i16) -> Option<usize> {
+        let inside = (0..self.width as i16).contains(&x) && (0..self.height as i16).contains(&y);
+        inside.then(|| y as usize * self.width as usize + x as usize)
+    }
+
+    /// What it takes a unit of `class` on `team` to
+    /// cross the tile, or `None` if it can't.
+    pub fn cost(&self, class: UnitClass, team: u8, x: i16, y: i16) -> Option<u32> {
+        let cell = self.cells[self.index(x, y)?];
+        let blocked = cell.building.is_some_and(|(owner, passable)| !(passable && owner == team));
+        match class {
+            UnitClass::Flying => Some(1),
+            _ if cell.wall => None,
+            UnitClass::Legs => Some(1),
+            _ if blocked => None,
+            UnitClass::Ground if cell.deep => None,
+            UnitClass::Ground => Some(if cell.liquid { 3 } else { 1 }),
+            UnitClass::Naval if !cell.liquid => None,
+            UnitClass::Naval => Some(if cell.deep { 1 } else { 2 }),
+        }
+    }
+
+    pub fn passable(&self, class: UnitClass, team: u8, x: i16, y: i16) -> bool {
+        self.cost(class, team, x, y).is_some()
+    }
+
+    /// The tiles next to `(x, y)` that can be stepped to, with the
+    /// cost of the step before the tile's. Diagonal steps can't
+    /// cut corners.
+    fn steps(&self, class: UnitClass, team: u8, x: i16, y: i16) -> impl Iterator<Item = ((i16, i16), u32)> + '_ {
+        NEIGHBORS.iter().filter_map(move |&(dx, dy)| {
+            let (nx, ny) = (x + dx, y + dy);
+            if !self.passable(class, team, nx, ny) {
+                return None;
+            }
+            if dx != 0 && dy != 0 {
+                if !self.passable(class, team, x + dx, y) || !self.passable(class, team, x, y + dy) {
+                    return None;
+                }
+                return Some(((nx, ny), DIAGONAL));
+            }
+            Some(((nx, ny), STRAIGHT))
+        })
+    }
+
+    /// The cheapest path from `from` to `to` by A*,
+    /// both ends included, or `None` if there is none.
+    pub fn find_path(&self, class: UnitClass, team: u8, from: (i16, i16), to: (i16, i16)) -> Option<Vec<(i16, i16)>> {
+        let (start, goal) = (self.index(from.0, from.1)?, self.index(to.0, to.1)?);
+        if !self.passable(class, team, to.0, to.1) {
+            return None;
+        }
+        // octile distance, a lower bound as no tile costs under 1
+        let estimate = |(x, y): (i16, i16)| {
+            let (dx, dy) = ((x - to.0).unsigned_abs() as u32, (y - to.1).unsigned_abs() as u32);
+            STRAIGHT * dx.max(dy) + (DIAGONAL - STRAIGHT) * dx.min(dy)
+        };
+        let mut costs = vec![u32::MAX; self.cells.len()];
+        let mut came_from = vec![usize::MAX; self.cells.len()];
+        let mut open = BinaryHeap::from([Reverse((estimate(from), 0, from))]);
+        costs[start] = 0;
+
+        while let Some(Reverse((_, cost, (x, y)))) = open.pop() {
+            let i = y as usize * self.width as usize + x as usize;
+            if i == goal {
+                let mut path = vec![to];
+                let mut i = goal;
+                while i != start {
+                    i = came_from[i];
+                    path.push(((i % self.width as usize) as i16, (i / self.width as usize) as i16));
+                }
+                path.reverse();
+                return Some(path);
+            }
+            if cost > costs[i] {
+                continue;
+            }
+            for (next, step) in self.steps(class, team, x, y) {
+                let step = step * self.cost(class, team, next.0, next.1).unwrap();
+                let j = next.1 as usize * self.width as usize + next.0 as usize;
+                if cost + step < costs[j] {
+                    costs[j] = cost + step;
+                    came_from[j] = i;
+                    open.push(Reverse((cost + step + estimate(next), cost + step, next)));
+                }
+            }
+        }
+        None
+    }
+
+    /// The cost from every tile to the nearest of `targets`,
+    /// for sending many units, or one unit again and again,
+    /// to the same place.
+    #[allow(dead_code)]
+    pub fn flow_field(&self, class: UnitClass, team: u8, targets: &[(i16, i16)]) -> FlowField {
+        let mut field = FlowField {
+            width: self.width,
+            height: self.height,
+            costs: vec![u32::MAX; self.cells.len()],
+            next: vec![usize::MAX; self.cells.len()],
+        };
+        let mut open = BinaryHeap::new();
+        for &(x, y) in targets {
+            if let Some(i) = self.index(x, y).filter(|_| self.passable(class, team, x, y)) {
+                field.costs[i] = 0;
+                open.push(Reverse((0, (x, y))));
+            }
+        }
+
+        while let Some(Reverse((cost, (x, y)))) = open.pop() {
+            let i = y as usize * self.width as usize + x as usize;
+            if cost > field.costs[i] {
+                continue;
+            }
+            // a unit on `next` would step onto `(x, y)`
+            let tile = self.cost(class, team, x, y).unwrap();
+            for (next, step) in self.steps(class, team, x, y) {
+                let step = step * tile;
+                let j = next.1 as usize * self.width as usize + next.0 as usize;
+                if cost + step < field.costs[j] {
+                    field.costs[j] = cost + step;
+                    field.next[j] = i;
+                    open.push(Reverse((cost + step, next)));
+                }
+            }
+        }
+        field
+    }
+}
+
+/// The result of `PathGrid::flow_field`.
+#[derive(Clone, Debug)]
+#[allow(dead_code)]
+pub struct FlowField {
+    pub width: u16,
+    pub height: u16,
+    costs: Vec<u32>,
+    next: Vec<usize>,
+}
+
+impl FlowField {
+    #[allow(dead_code)]
+    fn index(&self, x: i16, y: i16) -> Option<usize> {
+        let inside = (0..self.width as i16).contains(&x) && (0..self.height as i16).contains(&y);
+        inside.then(|| y as usize * self.width as usize + x as usize)
+    }
+
+    /// The cost from `(x, y)` to the nearest target,
+    /// or `None` if none can be reached.
+    #[allow(dead_code)]
+    pub fn cost(&self, x: i16, y: i16) -> Option<u32> {
+        Some(self.costs[self.index(x, y)?]).filter(|cost| *cost != u32::MAX)
+    }
+
+    /// The tile to step to from `(x, y)`, or `None`
+    /// on a target or where no target can be reached.
+    #[allow(dead_code)]
+    pub fn next(&self, x: i16, y: i16) -> Option<(i16, i16)> {
+        let next = self.next[self.index(x, y)?];
+        (next != usize::MAX).then(|| ((next % self.width as usize) as i16, (next / self.width as usize) as i16))
+    }
+
+    /// The steps from `from` to the nearest target,
+    /// both ends included.
+    #[allow(dead_code)]
+    pub fn path(&self, from: (i16, i16)) -> Option<Vec<(i16, i16)>> {
+        self.cost(from.0, from.1)?;
+        let mut path = vec![from];
+        while let Some(next) = self.next(path[path.len() - 1].0, path[path.len() - 1].1) {
+            path.push(next);
+        }
+        Some(path)
+    }
+}
+
+#[test]
+fn test_pathfind() {
+    use crate::minet::save::ContentHeader;
+    use crate::minet::world::{Building, TileMap};
+
+    let content = ContentRegistry::from_json(r#"{
+        "blocks": [{ "id": 0, "name": "air" }, { "id": 1, "name": "stone" },
+                   { "id": 2, "name": "cliff", "solid": true },
+                   { "id": 3, "name": "shallow-water", "is_liquid": true },
+                   { "id": 4, "name": "deep-water", "is_liquid": true, "is_deep": true },
+                   { "id": 5, "name": "copper-wall", "solid": true },
+                   { "id": 6, "name": "door", "solid": true, "team_passable": true }],
+        "units": [{ "id": 0, "name": "dagger", "kind": "mech" }, { "id": 1, "name": "flare", "kind": "flying" },
+                  { "id": 2, "name": "atrax", "kind": "legs" }, { "id": 3, "name": "risso", "kind": "naval" }]
+    }"#).unwrap();
+    assert_eq!(UnitClass::of(content.units.by_name("flare").unwrap()), UnitClass::Flying);
+    assert_eq!(UnitClass::of(content.units.by_name("atrax").unwrap()), UnitClass::Legs);
+
+    // a cliff along x = 5 with a gap at the top, a lake
+    // in the bottom right and a wall in the gap's way
+    let mut map = TileMap::new(12, 10);
+    for x in 0..12 {
+        for y in 0..10 {
+            let tile = map.get_mut(x, y).unwrap();
+            tile.floor = match (x, y) {
+                (8.., ..3) => 4,
+                (7.., ..4) => 3,
+                _ => 1,
+            };
+            if x == 5 && y < 8 {
+                tile.block = 2;
+            }
+        }
+    }
+    let mut world = World::from_map(map, &ContentHeader::from_registry(&content), &content);
+    world.insert(Building { pos: (6 << 16) | 8, block: 5, team: 1, ..Building::default() });
+    world.insert(Building { pos: (6 << 16) | 9, block: 6, team: 1, ..Building::default() });
+    let grid = PathGrid::new(&world, &content).unwrap();
+    assert!(PathGrid::new(&world, &ContentRegistry::default()).unwrap_err().contains("no air, cliff, copper-wall, 4 more"));
+
+    assert_eq!(grid.cost(UnitClass::Ground, 1, 8, 3), Some(3));
+    assert!(!grid.passable(UnitClass::Ground, 1, 9, 1));
+    assert!(grid.passable(UnitClass::Legs, 1, 6, 8));
+    assert!(!grid.passable(UnitClass::Legs, 1, 5, 0));
+
+    // through the gap and the team's own door
+    let path = grid.find_path(UnitClass::Ground, 1, (2, 2), (9, 6)).unwrap();
+    assert_eq!((path[0], path[path.len() - 1]), ((2, 2), (9, 6)));
+    assert!(path.contains(&(6, 9)));
+    assert!(path.windows(2).all(|w| (w[0].0 - w[1].0).abs() <= 1 && (w[0].1 - w[1].1).abs() <= 1));
+    // other teams can't use the door
+    assert_eq!(grid.find_path(UnitClass::Ground, 2, (2, 2), (9, 6)), None);
+    assert_eq!(grid.find_path(UnitClass::Flying, 2, (2, 2), (9, 6)).unwrap().len(), 8);
+    assert_eq!(grid.find_path(UnitClass::Naval, 1, (11, 0), (7, 3)).unwrap().len(), 5);
+    assert_eq!(grid.find_path(UnitClass::Naval, 1, (11, 0), (2, 2)), None);
+
+    let field = grid.flow_field(UnitClass::Ground, 1, &[(9, 6)]);
+    assert_eq!(field.cost(9, 6), Some(0));
+    assert_eq!(field.next(9, 6), None);
+    let flowed = field.path((2, 2)).unwrap();
+    assert_eq!(flowed[flowed.len() - 1], (9, 6));
+    // as cheap as the A* path
+    let cost = |path: &[(i16, i16)]| path.windows(2).map(|w| {
+        let step = if w[0].0 != w[1].0 && w[0].1 != w[1].1 { DIAGONAL } else { STRAIGHT };
+        step * grid.cost(UnitClass::Ground, 1, w[1].0, w[1].1).unwrap()
+    }).sum::<u32>();
+    assert_eq!(field.cost(2, 2), Some(cost(&path)));
+    assert_eq!(cost(&flowed), cost(&path));
+    assert_eq!(field.cost(9, 1), None);
+}
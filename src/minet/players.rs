@@ -0,0 +1,160 @@
+use std::{collections::BTreeMap, sync::mpsc};
+use serde_json::Value;
+use crate::minet::{PacketMessage, PlayerDisconnectCallPacket, TraceInfoCallPacket};
+use crate::minet::entity::EntityTable;
+
+/// A connected player.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Player {
+    pub id: i32,
+    pub name: String,
+    pub team: u8,
+    pub admin: bool,
+    /// The id of the unit the player controls.
+    pub unit: Option<i32>,
+    /// Only known after a `traceInfo`,
+    /// which servers only send to admins.
+    pub uuid: Option<String>,
+    pub ip: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlayerEvent {
+    Joined(Player),
+    Left(Player),
+}
+
+/// Who is online, going by player entities
+/// and `playerDisconnect`.
+#[derive(Clone, Debug, Default)]
+pub struct PlayerList {
+    players: BTreeMap<i32, Player>,
+    subscribers: Vec<mpsc::Sender<PlayerEvent>>,
+}
+
+impl PlayerList {
+    /// Returns a channel that receives every
+    /// `PlayerEvent` from now on.
+    #[allow(dead_code)]
+    pub fn subscribe(&mut self) -> mpsc::Receiver<PlayerEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    fn emit(&mut self, event: PlayerEvent) {
+        self.subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+
+    /// Updates the list from the player entities in `entities`.
+    /// New players have joined; players no longer
+    /// in it have left without a `playerDisconnect`.
+    pub fn sync(&mut self, entities: &EntityTable) {
+        let mut left: Vec<i32> = self.players.keys().copied().collect();
+        for entity in entities.players() {
+            left.retain(|id| *id != entity.id);
+            let known = self.players.get(&entity.id);
+            let player = Player {
+                id: entity.id,
+                name: entity.name.clone().unwrap_or_default(),
+                team: entity.team,
+                admin: entity.fields.get("admin").and_then(Value::as_bool).unwrap_or(false),
+                unit: entity.unit,
+                uuid: known.and_then(|p| p.uuid.clone()),
+                ip: known.and_then(|p| p.ip.clone()),
+            };
+            if known.is_none() {
+                self.emit(PlayerEvent::Joined(player.clone()));
+            }
+            self.players.insert(entity.id, player);
+        }
+        for id in left {
+            self.remove(id);
+        }
+    }
+
+    /// Applies `playerDisconnect` and `traceInfo`,
+    /// ignoring other packets.
+    pub fn apply(&mut self, msg: &PacketMessage) {
+        if let Some(p) = msg.downcast::<PlayerDisconnectCallPacket>() {
+            self.remove(p.player);
+        } else if let Some(p) = msg.downcast::<TraceInfoCallPacket>() {
+            if let Some(player) = self.players.get_mut(&p.player) {
+                player.uuid = Some(p.uuid.clone());
+                player.ip = Some(p.ip.clone());
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: i32) -> Option<Player> {
+        let player = self.players.remove(&id)?;
+        self.emit(PlayerEvent::Left(player.clone()));
+        Some(player)
+    }
+
+    pub fn get(&self, id: i32) -> Option<&Player> {
+        self.players.get(&id)
+    }
+
+    #[allow(dead_code)]
+    pub fn by_name(&self, name: &str) -> Option<&Player> {
+        self.iter().find(|p| p.name == name)
+    }
+
+    /// Players by id.
+    pub fn iter(&self) -> impl Iterator<Item = &Player> {
+        self.players.values()
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.players.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.players.is_empty()
+    }
+}
+
+#[test]
+fn test_player_list() {
+    use crate::minet::entity::{Entity, EntityKind};
+    use crate::minet::protocol::Protocol;
+
+    fn msg<T: crate::minet::Packet + 'static>(packet: T) -> PacketMessage {
+        PacketMessage::with_protocol(&Protocol::fallback(), packet, false).unwrap()
+    }
+    let player = |id: i32, name: &str| Entity {
+        id, class: "Player".to_string(), kind: EntityKind::Player, x: 0.0, y: 0.0, rotation: 0.0, health: 0.0, team: 1,
+        unit_type: None, controller: None, name: Some(name.to_string()), unit: Some(id + 100),
+        fields: serde_json::json!({ "admin": id == 1 }).as_object().unwrap().clone(), updated: 0,
+    };
+
+    let mut entities = EntityTable::default();
+    entities.insert(player(1, "anuke"));
+    entities.insert(player(2, "griefer"));
+    let mut players = PlayerList::default();
+    let events = players.subscribe();
+    players.sync(&entities);
+    players.sync(&entities);
+    assert_eq!(players.len(), 2);
+    assert!(players.get(1).unwrap().admin);
+    assert_eq!(players.by_name("griefer").unwrap().unit, Some(102));
+    assert_eq!(events.try_iter().count(), 2);
+
+    players.apply(&msg(TraceInfoCallPacket {
+        player: 2, ip: "10.0.0.2".to_string(), uuid: "AAAAAAAAAAAAAAAAAAAAAA==".to_string(),
+        modded: false, mobile: false, times_joined: 3, times_kicked: 1,
+    }));
+    players.sync(&entities);
+    assert_eq!(players.get(2).unwrap().uuid.as_deref(), Some("AAAAAAAAAAAAAAAAAAAAAA=="));
+
+    players.apply(&msg(PlayerDisconnectCallPacket { player: 2 }));
+    assert!(matches!(events.try_recv(), Ok(PlayerEvent::Left(p)) if p.name == "griefer"));
+    entities.remove(2);
+    entities.remove(1);
+    players.sync(&entities);
+    assert!(matches!(events.try_recv(), Ok(PlayerEvent::Left(p)) if p.id == 1));
+    assert!(players.is_empty());
+}
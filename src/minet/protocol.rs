@@ -0,0 +1,172 @@
+use std::{collections::HashMap, sync::{Arc, OnceLock}};
+use crate::minet::packets::call_ids;
+
+/// Layout differences between builds
+/// for packets that exist in all of them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Layout {
+    /// Whether `SendMessageCallPacket` carries
+    /// the message without the sender's name.
+    pub chat_unformatted: bool,
+    /// Whether `StateSnapshotCallPacket` carries
+    /// the server's tps and random seed.
+    pub state_tps: bool,
+    /// Whether item modules use `u16` counts and ids
+    /// rather than bytes.
+    pub short_item_ids: bool,
+    /// Whether `TypeValue` tag 15 is a `u16` `UnitCommand`
+    /// id, which builds since v136 send. v135 has no tag 15.
+    pub unit_command_ids: bool,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self { chat_unformatted: true, state_tps: true, short_item_ids: true, unit_command_ids: false }
+    }
+}
+
+/// The packet ids and field layouts
+/// used by one Mindustry build.
+///
+/// Packet ids past the first four are assigned
+/// by `Call.registerPackets`, so they shift
+/// whenever a remote method is added or removed.
+/// The built-in table comes from schema/calls.txt.
+#[derive(Clone, Debug)]
+pub struct Protocol {
+    /// A short name such as `v135`.
+    pub name: String,
+    /// The build number sent in `ConnectPacket`.
+    pub build: i32,
+    pub layout: Layout,
+    ids: HashMap<String, u8>,
+    names: HashMap<u8, String>,
+}
+
+/// Packets registered in `Net`'s static block,
+/// which keep their ids across builds.
+const FRAMEWORK_PACKETS: &[(&str, u8)] = &[
+    ("StreamBegin", 0),
+    ("StreamChunk", 1),
+    ("WorldStream", 2),
+    ("ConnectPacket", 3),
+];
+
+static BUILTIN: OnceLock<Vec<Arc<Protocol>>> = OnceLock::new();
+
+impl Protocol {
+    pub fn new(name: &str, build: i32, layout: Layout, packets: &[(&str, u8)]) -> Self {
+        let mut protocol = Self {
+            name: name.to_string(),
+            build, layout,
+            ids: HashMap::new(),
+            names: HashMap::new(),
+        };
+        for (name, id) in FRAMEWORK_PACKETS.iter().chain(packets) {
+            protocol.ids.insert(name.to_string(), *id);
+            protocol.names.insert(*id, name.to_string());
+        }
+        protocol
+    }
+
+    /// Returns the id of the packet type `name`.
+    pub fn id(&self, name: &str) -> Option<u8> {
+        self.ids.get(name).copied()
+    }
+
+    /// Returns the name of the packet type with id `id`.
+    pub fn name(&self, id: u8) -> Option<&str> {
+        self.names.get(&id).map(String::as_str)
+    }
+
+    /// The profiles minet ships with: only v135,
+    /// whose ids SPEC.md derives from `Call.java`.
+    pub fn builtin() -> &'static [Arc<Protocol>] {
+        BUILTIN.get_or_init(|| vec![
+            Arc::new(Protocol::new("v135", 135, Layout::default(), &call_ids("v135"))),
+        ])
+    }
+
+    pub fn by_name(name: &str) -> Option<Arc<Protocol>> {
+        Self::builtin().iter().find(|p| p.name == name).cloned()
+    }
+
+    /// The profile to use when the build
+    /// isn't known: v135.
+    pub fn fallback() -> Arc<Protocol> {
+        Self::by_name("v135").unwrap()
+    }
+
+    /// The built-in profile for exactly `build`. Ids shift
+    /// between builds, so no other profile stands in.
+    pub fn for_build(build: i32) -> Option<Arc<Protocol>> {
+        Self::builtin().iter().find(|p| p.build == build).cloned()
+    }
+
+    /// Parses a profile for a build minet doesn't know about.
+    ///
+    /// ```json
+    /// { "name": "v141", "build": 141,
+    ///   "layout": { "chat_unformatted": true, "state_tps": true, "short_item_ids": true, "unit_command_ids": true },
+    ///   "packets": { "SendChatMessageCallPacket": 78 } }
+    /// ```
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let value: serde_json::Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let name = value["name"].as_str().ok_or("profile needs a name")?;
+        let build = value["build"].as_i64().ok_or("profile needs a build")? as i32;
+        let mut layout = Layout::default();
+        let flags = [
+            ("chat_unformatted", &mut layout.chat_unformatted),
+            ("state_tps", &mut layout.state_tps),
+            ("short_item_ids", &mut layout.short_item_ids),
+            ("unit_command_ids", &mut layout.unit_command_ids),
+        ];
+        for (key, flag) in flags {
+            if let Some(b) = value["layout"][key].as_bool() {
+                *flag = b;
+            }
+        }
+        let packets = value["packets"].as_object().ok_or("profile needs packets")?
+            .iter()
+            .map(|(name, id)| match id.as_u64() {
+                Some(id) if id <= 0xff => Ok((name.as_str(), id as u8)),
+                _ => Err(format!("invalid id for {}: {}", name, id)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::new(name, build, layout, &packets))
+    }
+
+    /// Loads a profile by built-in name,
+    /// or else from a JSON file.
+    pub fn load(name_or_path: &str) -> Result<Arc<Protocol>, String> {
+        if let Some(protocol) = Self::by_name(name_or_path) {
+            return Ok(protocol);
+        }
+        let json = std::fs::read_to_string(name_or_path).map_err(|e| format!("{}: {}", name_or_path, e))?;
+        Self::from_json(&json).map(Arc::new)
+    }
+}
+
+#[test]
+fn test_protocol() {
+    assert_eq!(Protocol::for_build(135).unwrap().name, "v135");
+    assert!(Protocol::for_build(139).is_none());
+    assert!(Protocol::for_build(-1).is_none());
+
+    let v135 = Protocol::by_name("v135").unwrap();
+    assert_eq!(v135.id("ConnectPacket"), Some(3));
+    assert_eq!(v135.name(0x4b), Some("SendChatMessageCallPacket"));
+    assert_eq!(v135.id("NoSuchPacket"), None);
+
+    let custom = Protocol::from_json(r#"{
+        "name": "v141", "build": 141,
+        "layout": { "chat_unformatted": false, "unit_command_ids": true },
+        "packets": { "SendChatMessageCallPacket": 78 }
+    }"#).unwrap();
+    assert_eq!(custom.id("SendChatMessageCallPacket"), Some(78));
+    assert_eq!(custom.id("StreamChunk"), Some(1));
+    assert!(!custom.layout.chat_unformatted);
+    assert!(custom.layout.state_tps);
+    assert!(custom.layout.unit_command_ids);
+    assert!(Protocol::from_json(r#"{ "name": "x", "build": 1, "packets": { "A": 300 } }"#).is_err());
+}
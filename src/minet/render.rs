@@ -0,0 +1,170 @@
+use crate::minet::content::{kind, ContentRegistry};
+use crate::minet::entity::{EntityKind, EntityTable};
+use crate::minet::world::World;
+
+/// Colors of the first six teams, as in `Team`.
+const TEAM_COLORS: [[u8; 3]; 6] = [
+    [0x4d, 0x4e, 0x58], // derelict
+    [0xff, 0xd3, 0x7f], // sharded
+    [0xf2, 0x55, 0x55], // crux
+    [0xa2, 0x7c, 0xe5], // malis
+    [0x54, 0xd6, 0x7d], // green
+    [0x6c, 0x87, 0xfd], // blue
+];
+
+/// The world is this many units per tile.
+const TILE_SIZE: f32 = 8.0;
+
+pub fn team_color(team: u8) -> [u8; 3] {
+    TEAM_COLORS.get(team as usize).copied().unwrap_or_else(|| name_color(&team.to_string()))
+}
+
+/// A muted color made up from `name`,
+/// for content without one of its own.
+fn name_color(name: &str) -> [u8; 3] {
+    let hash = name.bytes().fold(0x811c9dc5u32, |hash, b| (hash ^ b as u32).wrapping_mul(0x01000193));
+    [64 + (hash & 0x7f) as u8, 64 + (hash >> 8 & 0x7f) as u8, 64 + (hash >> 16 & 0x7f) as u8]
+}
+
+fn lighten(color: [u8; 3]) -> [u8; 3] {
+    color.map(|c| c / 2 + 128)
+}
+
+/// An RGB image, row by row from the top.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height, pixels: vec![0; width as usize * height as usize * 3] }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> [u8; 3] {
+        let i = (y as usize * self.width as usize + x as usize) * 3;
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2]]
+    }
+
+    /// Fills a rectangle, cut to the image.
+    pub fn fill(&mut self, x: i64, y: i64, width: i64, height: i64, color: [u8; 3]) {
+        for py in y.max(0)..(y + height).min(self.height as i64) {
+            for px in x.max(0)..(x + width).min(self.width as i64) {
+                let i = (py as usize * self.width as usize + px as usize) * 3;
+                self.pixels[i..i + 3].copy_from_slice(&color);
+            }
+        }
+    }
+
+    pub fn png(&self) -> Result<Vec<u8>, String> {
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+        writer.write_image_data(&self.pixels).map_err(|e| e.to_string())?;
+        writer.finish().map_err(|e| e.to_string())?;
+        Ok(out)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.png()?).map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+/// Draws `world` as a minimap, `scale` pixels
+/// per tile, with north up.
+///
+/// Floors, ores and walls take their block's color from
+/// `content`, buildings their team's. Units and players
+/// in `entities` are drawn on top, players in white.
+pub fn render(world: &World, content: &ContentRegistry, entities: Option<&EntityTable>, scale: u32) -> Image {
+    let scale = scale.max(1);
+    // without a map, fit what is known
+    let (mut width, mut height) = (world.tiles.width as u32, world.tiles.height as u32);
+    if width == 0 || height == 0 {
+        for b in world.buildings() {
+            width = width.max(b.x().max(0) as u32 + 2);
+            height = height.max(b.y().max(0) as u32 + 2);
+        }
+    }
+    let mut image = Image::new(width * scale, height * scale);
+    let mut tile = |x: i64, y: i64, size: i64, color: [u8; 3]| {
+        let (scale, top) = (scale as i64, height as i64 - 1 - y);
+        image.fill(x * scale, (top - size + 1) * scale, size * scale, size * scale, color)
+    };
+
+    let block_color = |id: u16| {
+        let name = world.header.name(kind::BLOCK, id)?;
+        Some(content.blocks.by_name(name).and_then(|b| b.color).unwrap_or_else(|| name_color(name)))
+    };
+    let is_air = |id: u16| world.header.name(kind::BLOCK, id).is_none_or(|name| name == "air");
+    for (i, t) in world.tiles.tiles().iter().enumerate() {
+        let (x, y) = ((i % width as usize) as i64, (i / width as usize) as i64);
+        let color = if !t.building && !is_air(t.block) {
+            block_color(t.block)
+        } else if !is_air(t.overlay) {
+            block_color(t.overlay)
+        } else {
+            block_color(t.floor)
+        };
+        tile(x, y, 1, color.unwrap_or_default());
+    }
+
+    for b in world.buildings() {
+        let size = content.blocks.get(b.block).map_or(1, |block| block.size.max(1)) as i64;
+        let offset = (size - 1) / 2;
+        tile(b.x() as i64 - offset, b.y() as i64 - offset, size, team_color(b.team));
+    }
+
+    for e in entities.iter().flat_map(|entities| entities.iter()) {
+        let (x, y) = ((e.x / TILE_SIZE).round() as i64, (e.y / TILE_SIZE).round() as i64);
+        let color = match e.kind {
+            EntityKind::Player => [0xff; 3],
+            EntityKind::Unit => lighten(team_color(e.team)),
+        };
+        tile(x, y, 1, color);
+    }
+    image
+}
+
+#[test]
+fn test_render() {
+    use crate::minet::save::ContentHeader;
+    use crate::minet::world::{Building, TileMap};
+
+    let content = ContentRegistry::from_json(r#"{
+        "blocks": [{ "id": 0, "name": "air" }, { "id": 1, "name": "stone", "color": "323232" },
+                   { "id": 2, "name": "ore-copper", "color": "d99d73" }, { "id": 3, "name": "stone-wall", "color": "6e7080" },
+                   { "id": 4, "name": "core-shard", "size": 3 }, { "id": 5, "name": "sand-floor" }]
+    }"#).unwrap();
+    let mut map = TileMap::new(8, 6);
+    for x in 0..8 {
+        for y in 0..6 {
+            map.get_mut(x, y).unwrap().floor = 1;
+        }
+    }
+    map.get_mut(0, 0).unwrap().overlay = 2;
+    map.get_mut(7, 5).unwrap().block = 3;
+    map.get_mut(7, 0).unwrap().floor = 5;
+    let mut world = World::from_map(map, &ContentHeader::from_registry(&content), &content);
+    world.insert(Building { pos: (3 << 16) | 2, block: 4, team: 1, ..Building::default() });
+
+    let image = render(&world, &content, None, 2);
+    assert_eq!((image.width, image.height), (16, 12));
+    // the bottom left tile is at the bottom left of the image
+    assert_eq!(image.get(0, 11), [0xd9, 0x9d, 0x73]);
+    assert_eq!(image.get(15, 0), [0x6e, 0x70, 0x80]);
+    assert_eq!(image.get(12, 8), [0x32, 0x32, 0x32]);
+    assert_eq!(image.get(14, 11), name_color("sand-floor"));
+    // a 3x3 core around (3, 2)
+    assert_eq!(image.get(4, 9), team_color(1));
+    assert_eq!(image.get(9, 4), team_color(1));
+    assert_eq!(image.get(10, 5), [0x32, 0x32, 0x32]);
+
+    let png = image.png().unwrap();
+    let decoder = png::Decoder::new(std::io::Cursor::new(png)).read_info().unwrap();
+    assert_eq!((decoder.info().width, decoder.info().height), (16, 12));
+}
@@ -0,0 +1,234 @@
+use std::collections::{BTreeMap, BTreeSet};
+use serde_json::{json, Map, Value};
+
+fn take_bool(map: &mut Map<String, Value>, key: &str, default: bool) -> bool {
+    map.remove(key).and_then(|v| v.as_bool()).unwrap_or(default)
+}
+
+fn take_f32(map: &mut Map<String, Value>, key: &str, default: f32) -> f32 {
+    map.remove(key).and_then(|v| v.as_f64()).map_or(default, |f| f as f32)
+}
+
+fn take_names(map: &mut Map<String, Value>, key: &str) -> BTreeSet<String> {
+    map.remove(key).and_then(|v| v.as_array().map(|names| {
+        names.iter().filter_map(Value::as_str).map(str::to_string).collect()
+    })).unwrap_or_default()
+}
+
+/// Rules that apply to one team on top of the global ones.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TeamRules {
+    pub cheat: bool,
+    pub infinite_resources: bool,
+    pub infinite_ammo: bool,
+    pub rts_ai: bool,
+    pub build_speed_multiplier: f32,
+    pub unit_build_speed_multiplier: f32,
+    pub unit_damage_multiplier: f32,
+    pub block_health_multiplier: f32,
+    pub block_damage_multiplier: f32,
+    /// Keys minet doesn't know, kept as sent.
+    pub extra: Map<String, Value>,
+}
+
+impl Default for TeamRules {
+    fn default() -> Self {
+        Self::from_map(Map::new())
+    }
+}
+
+impl TeamRules {
+    fn from_map(mut map: Map<String, Value>) -> Self {
+        Self {
+            cheat: take_bool(&mut map, "cheat", false),
+            infinite_resources: take_bool(&mut map, "infiniteResources", false),
+            infinite_ammo: take_bool(&mut map, "infiniteAmmo", false),
+            rts_ai: take_bool(&mut map, "rtsAi", false),
+            build_speed_multiplier: take_f32(&mut map, "buildSpeedMultiplier", 1.0),
+            unit_build_speed_multiplier: take_f32(&mut map, "unitBuildSpeedMultiplier", 1.0),
+            unit_damage_multiplier: take_f32(&mut map, "unitDamageMultiplier", 1.0),
+            block_health_multiplier: take_f32(&mut map, "blockHealthMultiplier", 1.0),
+            block_damage_multiplier: take_f32(&mut map, "blockDamageMultiplier", 1.0),
+            extra: map,
+        }
+    }
+
+    pub fn json(&self) -> Value {
+        let mut map = self.extra.clone();
+        map.extend([
+            ("cheat", json!(self.cheat)),
+            ("infiniteResources", json!(self.infinite_resources)),
+            ("infiniteAmmo", json!(self.infinite_ammo)),
+            ("rtsAi", json!(self.rts_ai)),
+            ("buildSpeedMultiplier", json!(self.build_speed_multiplier)),
+            ("unitBuildSpeedMultiplier", json!(self.unit_build_speed_multiplier)),
+            ("unitDamageMultiplier", json!(self.unit_damage_multiplier)),
+            ("blockHealthMultiplier", json!(self.block_health_multiplier)),
+            ("blockDamageMultiplier", json!(self.block_damage_multiplier)),
+        ].map(|(key, value)| (key.to_string(), value)));
+        Value::Object(map)
+    }
+}
+
+/// The rules of a game, as sent in `setRules`
+/// and the world data.
+///
+/// Keys are those of Mindustry's `Rules` class. Missing ones
+/// take Mindustry's defaults; unknown ones end up in `extra`
+/// so that `json` gives back what was sent.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rules {
+    pub pvp: bool,
+    pub attack_mode: bool,
+    pub waves: bool,
+    pub wave_timer: bool,
+    /// Ticks between waves.
+    pub wave_spacing: f32,
+    pub infinite_resources: bool,
+    /// Extra units each team may have,
+    /// on top of what its cores allow.
+    pub unit_cap: i32,
+    pub build_speed_multiplier: f32,
+    pub build_cost_multiplier: f32,
+    pub deconstruct_refund_multiplier: f32,
+    pub unit_build_speed_multiplier: f32,
+    pub unit_damage_multiplier: f32,
+    pub block_health_multiplier: f32,
+    pub block_damage_multiplier: f32,
+    /// Block names that can't be built.
+    pub banned_blocks: BTreeSet<String>,
+    /// Unit type names that can't be made.
+    pub banned_units: BTreeSet<String>,
+    /// Per-team rules, by team id.
+    pub teams: BTreeMap<u8, TeamRules>,
+    /// Keys minet doesn't know, kept as sent.
+    pub extra: Map<String, Value>,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self::from_map(Map::new())
+    }
+}
+
+impl Rules {
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        match serde_json::from_str(json).map_err(|e| e.to_string())? {
+            Value::Object(map) => Ok(Self::from_map(map)),
+            _ => Err("rules must be an object".to_string()),
+        }
+    }
+
+    fn from_map(mut map: Map<String, Value>) -> Self {
+        let teams = match map.remove("teams") {
+            Some(Value::Object(teams)) => teams.into_iter()
+                .filter_map(|(team, rules)| Some((team.parse().ok()?, TeamRules::from_map(rules.as_object()?.clone()))))
+                .collect(),
+            _ => BTreeMap::new(),
+        };
+        Self {
+            pvp: take_bool(&mut map, "pvp", false),
+            attack_mode: take_bool(&mut map, "attackMode", false),
+            waves: take_bool(&mut map, "waves", false),
+            wave_timer: take_bool(&mut map, "waveTimer", true),
+            wave_spacing: take_f32(&mut map, "waveSpacing", 2.0 * 60.0 * 60.0),
+            infinite_resources: take_bool(&mut map, "infiniteResources", false),
+            unit_cap: map.remove("unitCap").and_then(|v| v.as_i64()).unwrap_or(0) as i32,
+            build_speed_multiplier: take_f32(&mut map, "buildSpeedMultiplier", 1.0),
+            build_cost_multiplier: take_f32(&mut map, "buildCostMultiplier", 1.0),
+            deconstruct_refund_multiplier: take_f32(&mut map, "deconstructRefundMultiplier", 0.5),
+            unit_build_speed_multiplier: take_f32(&mut map, "unitBuildSpeedMultiplier", 1.0),
+            unit_damage_multiplier: take_f32(&mut map, "unitDamageMultiplier", 1.0),
+            block_health_multiplier: take_f32(&mut map, "blockHealthMultiplier", 1.0),
+            block_damage_multiplier: take_f32(&mut map, "blockDamageMultiplier", 1.0),
+            banned_blocks: take_names(&mut map, "bannedBlocks"),
+            banned_units: take_names(&mut map, "bannedUnits"),
+            teams,
+            extra: map,
+        }
+    }
+
+    pub fn json(&self) -> Value {
+        let mut map = self.extra.clone();
+        let teams: Map<String, Value> = self.teams.iter().map(|(team, rules)| (team.to_string(), rules.json())).collect();
+        map.extend([
+            ("pvp", json!(self.pvp)),
+            ("attackMode", json!(self.attack_mode)),
+            ("waves", json!(self.waves)),
+            ("waveTimer", json!(self.wave_timer)),
+            ("waveSpacing", json!(self.wave_spacing)),
+            ("infiniteResources", json!(self.infinite_resources)),
+            ("unitCap", json!(self.unit_cap)),
+            ("buildSpeedMultiplier", json!(self.build_speed_multiplier)),
+            ("buildCostMultiplier", json!(self.build_cost_multiplier)),
+            ("deconstructRefundMultiplier", json!(self.deconstruct_refund_multiplier)),
+            ("unitBuildSpeedMultiplier", json!(self.unit_build_speed_multiplier)),
+            ("unitDamageMultiplier", json!(self.unit_damage_multiplier)),
+            ("blockHealthMultiplier", json!(self.block_health_multiplier)),
+            ("blockDamageMultiplier", json!(self.block_damage_multiplier)),
+            ("bannedBlocks", json!(self.banned_blocks)),
+            ("bannedUnits", json!(self.banned_units)),
+            ("teams", Value::Object(teams)),
+        ].map(|(key, value)| (key.to_string(), value)));
+        Value::Object(map)
+    }
+
+    /// The rules for `team`, or the defaults
+    /// if it has none of its own.
+    pub fn team(&self, team: u8) -> TeamRules {
+        self.teams.get(&team).cloned().unwrap_or_default()
+    }
+
+    pub fn is_banned(&self, block: &str) -> bool {
+        self.banned_blocks.contains(block)
+    }
+
+    #[allow(dead_code)]
+    pub fn is_unit_banned(&self, unit: &str) -> bool {
+        self.banned_units.contains(unit)
+    }
+
+    /// How fast `team` builds,
+    /// global and team multipliers combined.
+    #[allow(dead_code)]
+    pub fn build_speed(&self, team: u8) -> f32 {
+        self.build_speed_multiplier * self.team(team).build_speed_multiplier
+    }
+
+    /// The JSON keys whose values differ from `other`'s.
+    pub fn changes(&self, other: &Rules) -> Vec<String> {
+        let (a, b) = (self.json(), other.json());
+        let (a, b) = (a.as_object().unwrap(), b.as_object().unwrap());
+        let keys: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+        keys.into_iter().filter(|key| a.get(*key) != b.get(*key)).cloned().collect()
+    }
+}
+
+#[test]
+fn test_rules() {
+    let json = r#"{
+        "pvp": true, "waveSpacing": 3600, "buildSpeedMultiplier": 2,
+        "bannedBlocks": ["ripple", "thorium-reactor"], "bannedUnits": ["quad"],
+        "teams": { "2": { "cheat": true, "buildSpeedMultiplier": 0.5, "someNewFlag": 1 } },
+        "lighting": true, "ambientLight": { "r": 0.01, "g": 0.01, "b": 0.04, "a": 0.99 }
+    }"#;
+    let rules = Rules::from_json(json).unwrap();
+    assert!(rules.pvp && rules.wave_timer);
+    assert_eq!(rules.wave_spacing, 3600.0);
+    assert!(rules.is_banned("ripple") && !rules.is_banned("duo"));
+    assert!(rules.is_unit_banned("quad"));
+    assert_eq!(rules.build_speed(2), 1.0);
+    assert_eq!(rules.build_speed(1), 2.0);
+    assert!(rules.team(2).cheat && !rules.team(1).cheat);
+    assert_eq!(rules.extra["lighting"], true);
+
+    let round_trip = Rules::from_json(&rules.json().to_string()).unwrap();
+    assert_eq!(round_trip, rules);
+    assert_eq!(round_trip.team(2).extra["someNewFlag"], 1);
+
+    let mut changed = rules.clone();
+    changed.pvp = false;
+    changed.banned_blocks.remove("ripple");
+    assert_eq!(changed.changes(&rules), ["bannedBlocks", "pvp"]);
+    assert!(Rules::from_json("[]").is_err());
+}
@@ -0,0 +1,339 @@
+use std::{collections::BTreeMap, io::{Read, Write}};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use serde_json::{Map, Value};
+use crate::minet::{Len, Reader, Writer};
+use crate::minet::content::ContentRegistry;
+use crate::minet::entity::EntityMapping;
+use crate::minet::rules::Rules;
+use crate::minet::world::{TileMap, World};
+
+const MAGIC: &[u8] = b"MSAV";
+
+/// The oldest save version minet reads.
+/// Older ones lay out the map differently.
+#[allow(dead_code)]
+pub const MIN_VERSION: i32 = 7;
+
+/// The save version written by `Save::new`.
+pub const VERSION: i32 = 7;
+
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    ZlibDecoder::new(data).read_to_end(&mut out).map_err(|e| e.to_string())?;
+    Ok(out)
+}
+
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Reads a `StringMap` as written by `SaveVersion.writeStringMap`.
+pub fn read_string_map(reader: &mut Reader) -> Option<BTreeMap<String, String>> {
+    (0..reader.i16()?.max(0))
+        .map(|_| Some((reader.prefixed_str(Len::U16)?, reader.prefixed_str(Len::U16)?)))
+        .collect()
+}
+
+pub fn write_string_map(buf: &mut Writer, map: &BTreeMap<String, String>) {
+    buf.i16(map.len() as i16);
+    for (key, value) in map {
+        buf.prefixed_str(key, Len::U16);
+        buf.prefixed_str(value, Len::U16);
+    }
+}
+
+/// The names of the content a map refers to by id,
+/// by `ContentType` ordinal. Ids in a map are indices
+/// into these lists, not the ids of the running build.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ContentHeader(pub BTreeMap<u8, Vec<String>>);
+
+impl ContentHeader {
+    /// A header mapping every id in `content` to its name.
+    #[allow(dead_code)]
+    pub fn from_registry(content: &ContentRegistry) -> Self {
+        use crate::minet::content::kind;
+        fn names<'a>(entries: impl Iterator<Item = (u16, &'a String)>) -> Vec<String> {
+            let mut names = Vec::new();
+            for (id, name) in entries {
+                if names.len() <= id as usize {
+                    names.resize(id as usize + 1, String::new());
+                }
+                names[id as usize] = name.clone();
+            }
+            names
+        }
+        Self(BTreeMap::from([
+            (kind::ITEM, names(content.items.iter().map(|c| (c.id, &c.name)))),
+            (kind::BLOCK, names(content.blocks.iter().map(|c| (c.id, &c.name)))),
+            (kind::LIQUID, names(content.liquids.iter().map(|c| (c.id, &c.name)))),
+            (kind::UNIT, names(content.units.iter().map(|c| (c.id, &c.name)))),
+        ]))
+    }
+
+    pub fn read(reader: &mut Reader) -> Option<Self> {
+        (0..reader.u8()?).map(|_| {
+            let kind = reader.u8()?;
+            let names = (0..reader.i16()?.max(0)).map(|_| reader.prefixed_str(Len::U16)).collect::<Option<_>>()?;
+            Some((kind, names))
+        }).collect::<Option<_>>().map(Self)
+    }
+
+    pub fn write(&self, buf: &mut Writer) {
+        buf.u8(self.0.len() as u8);
+        for (kind, names) in &self.0 {
+            buf.u8(*kind);
+            buf.i16(names.len() as i16);
+            for name in names {
+                buf.prefixed_str(name, Len::U16);
+            }
+        }
+    }
+
+    pub fn name(&self, kind: u8, id: u16) -> Option<&str> {
+        self.0.get(&kind)?.get(id as usize).map(String::as_str).filter(|name| !name.is_empty())
+    }
+}
+
+/// A `.msav` map or save file.
+///
+/// The meta, content header and map are decoded. The regions
+/// after the map (entities, markers, custom chunks) are kept
+/// as they were, so that a save reads back the same.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Save {
+    pub version: i32,
+    /// Map tags and save info, including `rules` as JSON.
+    pub meta: BTreeMap<String, String>,
+    pub content: ContentHeader,
+    pub map: TileMap,
+    pub regions: Vec<Vec<u8>>,
+}
+
+impl Save {
+    /// A save of `map`, without entities.
+    pub fn new(meta: BTreeMap<String, String>, content: ContentHeader, map: TileMap) -> Self {
+        let mut entities = Writer::new();
+        entities.i16(0); // custom entity classes
+        entities.i32(0); // teams with block plans
+        entities.i32(0); // entities
+        let mut custom = Writer::new();
+        custom.i32(0);
+        Self { version: VERSION, meta, content, map, regions: vec![entities.0, custom.0] }
+    }
+
+    /// Reads a compressed save.
+    #[allow(dead_code)]
+    pub fn read(data: &[u8]) -> Result<Self, String> {
+        let data = inflate(data)?;
+        let mut reader = Reader::new(&data);
+        if reader.bytes(MAGIC.len()) != Some(MAGIC) {
+            return Err("not a map file".to_string());
+        }
+        let version = reader.i32().ok_or("file ended early")?;
+        if version < MIN_VERSION {
+            return Err(format!("unsupported save version {}", version));
+        }
+
+        let mut regions = Vec::new();
+        while let Some(len) = reader.length(Len::I32) {
+            regions.push(reader.bytes(len).ok_or("region ended early")?.to_vec());
+        }
+        if regions.len() < 3 {
+            return Err("missing regions".to_string());
+        }
+        let rest = regions.split_off(3);
+        let meta = read_string_map(&mut Reader::new(&regions[0])).ok_or("invalid meta")?;
+        let content = ContentHeader::read(&mut Reader::new(&regions[1])).ok_or("invalid content header")?;
+        let map = TileMap::read(&mut Reader::new(&regions[2])).ok_or("invalid map")?;
+        Ok(Self { version, meta, content, map, regions: rest })
+    }
+
+    /// Writes the save, compressed.
+    pub fn write(&self) -> Vec<u8> {
+        let mut meta = Writer::new();
+        write_string_map(&mut meta, &self.meta);
+        let mut content = Writer::new();
+        self.content.write(&mut content);
+        let mut map = Writer::new();
+        self.map.write(&mut map);
+
+        let mut buf = Writer::new();
+        buf.bytes(MAGIC);
+        buf.i32(self.version);
+        for region in [&meta.0, &content.0, &map.0].into_iter().chain(&self.regions) {
+            buf.i32(region.len() as i32);
+            buf.bytes(region);
+        }
+        deflate(&buf.0)
+    }
+
+    #[allow(dead_code)]
+    pub fn load(path: &str) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::read(&data).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.write()).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// The rules saved in the meta,
+    /// or the defaults if there are none.
+    #[allow(dead_code)]
+    pub fn rules(&self) -> Result<Rules, String> {
+        self.meta.get("rules").map_or(Ok(Rules::default()), |json| Rules::from_json(json))
+    }
+
+    #[allow(dead_code)]
+    pub fn world(&self, content: &ContentRegistry) -> World {
+        World::from_map(self.map.clone(), &self.content, content)
+    }
+}
+
+/// The world data sent in a `WorldStream` when joining,
+/// as written by `NetworkIO.writeWorld`.
+///
+/// Block plans, markers and custom chunks after
+/// the map are not decoded.
+#[derive(Clone, Debug)]
+pub struct WorldData {
+    pub rules: Rules,
+    /// Map tags.
+    pub meta: BTreeMap<String, String>,
+    pub wave: i32,
+    /// Ticks until the next wave.
+    pub wave_time: f32,
+    #[allow(dead_code)]
+    pub tick: f64,
+    #[allow(dead_code)]
+    pub rand0: i64,
+    #[allow(dead_code)]
+    pub rand1: i64,
+    /// The id the server gave our player.
+    pub player_id: i32,
+    /// Our player's saved fields.
+    #[allow(dead_code)]
+    pub player: Map<String, Value>,
+    pub content: ContentHeader,
+    pub map: TileMap,
+}
+
+impl WorldData {
+    /// Decodes the compressed world data. The player
+    /// is read using the `Player` class of `mapping`.
+    pub fn read(data: &[u8], mapping: &EntityMapping) -> Result<Self, String> {
+        let data = inflate(data)?;
+        let mut reader = Reader::new(&data);
+        let rules = Rules::from_json(&reader.prefixed_str(Len::U16).ok_or("missing rules")?)?;
+        let meta = read_string_map(&mut reader).ok_or("invalid map tags")?;
+        let err = || "world data ended early".to_string();
+        let (wave, wave_time, tick) = (reader.i32().ok_or_else(err)?, reader.f32().ok_or_else(err)?, reader.f64().ok_or_else(err)?);
+        let (rand0, rand1) = (reader.i64().ok_or_else(err)?, reader.i64().ok_or_else(err)?);
+        let player_id = reader.i32().ok_or_else(err)?;
+
+        let class = mapping.class_named("Player").ok_or("the entity mapping has no Player class")?;
+        reader.i16().ok_or_else(err)?; // revision
+        let mut player = Map::new();
+        for (name, ty) in &class.fields {
+            player.insert(name.clone(), ty.read(&mut reader).ok_or_else(|| format!("can't decode Player.{}", name))?);
+        }
+
+        let content = ContentHeader::read(&mut reader).ok_or("invalid content header")?;
+        let map = TileMap::read(&mut reader).ok_or("invalid map")?;
+        Ok(Self { rules, meta, wave, wave_time, tick, rand0, rand1, player_id, player, content, map })
+    }
+
+    /// A save of this world that Mindustry can load,
+    /// without units or block plans.
+    pub fn to_save(&self) -> Save {
+        let mut meta = self.meta.clone();
+        let saved = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+        meta.insert("version".to_string(), VERSION.to_string());
+        meta.insert("saved".to_string(), saved.as_millis().to_string());
+        if let Some(name) = self.meta.get("name") {
+            meta.insert("mapname".to_string(), name.clone());
+        }
+        meta.insert("rules".to_string(), self.rules.json().to_string());
+        meta.insert("wave".to_string(), self.wave.to_string());
+        meta.insert("width".to_string(), self.map.width.to_string());
+        meta.insert("height".to_string(), self.map.height.to_string());
+        Save::new(meta, self.content.clone(), self.map.clone())
+    }
+
+    pub fn world(&self, content: &ContentRegistry) -> World {
+        World::from_map(self.map.clone(), &self.content, content)
+    }
+}
+
+#[test]
+fn test_save() {
+    use crate::minet::content::kind;
+
+    let content = ContentRegistry::from_json(r#"{
+        "blocks": [{ "id": 0, "name": "air" }, { "id": 1, "name": "stone" }, { "id": 2, "name": "core-shard", "size": 3 },
+                   { "id": 3, "name": "ore-copper" }, { "id": 4, "name": "boulder" }]
+    }"#).unwrap();
+    let header = ContentHeader::from_registry(&content);
+    assert_eq!(header.name(kind::BLOCK, 2), Some("core-shard"));
+
+    let mut map = TileMap::new(40, 30);
+    for i in (0..40 * 30).step_by(7) {
+        map.get_mut(i % 40, i / 40).unwrap().overlay = 3;
+    }
+    for x in 4..7 {
+        for y in 4..7 {
+            let tile = map.get_mut(x, y).unwrap();
+            tile.block = 2;
+            tile.building = true;
+        }
+    }
+    map.get_mut(20, 20).unwrap().block = 4;
+    map.get_mut(20, 20).unwrap().data = Some((1, 0, 0, -1));
+    let mut core = Writer::new();
+    core.u8(0); // revision
+    core.f32(1100.0);
+    core.u8(0x80);
+    core.u8(1);
+    core.u8(3);
+    core.u8(1);
+    core.u8(0); // no modules
+    core.u8(255);
+    core.u8(0);
+    map.buildings.insert(5 * 40 + 5, core.0);
+
+    let meta = BTreeMap::from([("name".to_string(), "test".to_string()), ("rules".to_string(), r#"{"pvp":true}"#.to_string())]);
+    let save = Save::new(meta.clone(), header.clone(), map.clone());
+    let read = Save::read(&save.write()).unwrap();
+    assert_eq!(read, save);
+    assert!(read.rules().unwrap().pvp);
+    let world = read.world(&content);
+    assert_eq!(world.building(5, 5).unwrap().health, 1100.0);
+    assert_eq!(world.tiles.get(20, 20).unwrap().data, Some((1, 0, 0, -1)));
+    assert!(Save::read(&deflate(b"MSAV\0\0\0\x05")).is_err());
+
+    // the same map, as sent on join
+    let mapping = EntityMapping::from_json(r#"{ "classes": [{ "id": 12, "name": "Player", "kind": "player", "fields": ["name: str", "x: f32"] }] }"#).unwrap();
+    let mut stream = Writer::new();
+    stream.prefixed_str(r#"{"waves":true}"#, Len::U16);
+    write_string_map(&mut stream, &meta);
+    stream.i32(3);
+    stream.f32(600.0);
+    stream.f64(1e6);
+    stream.i64(1);
+    stream.i64(2);
+    stream.i32(77);
+    stream.i16(0);
+    stream.str("robot");
+    stream.f32(8.0);
+    header.write(&mut stream);
+    map.write(&mut stream);
+    let data = WorldData::read(&deflate(&stream.0), &mapping).unwrap();
+    assert!(data.rules.waves);
+    assert_eq!((data.wave, data.player_id), (3, 77));
+    assert_eq!(data.player["name"], "robot");
+    assert_eq!(data.map, map);
+    assert_eq!(Save::read(&data.to_save().write()).unwrap().meta["wave"], "3");
+}
@@ -0,0 +1,268 @@
+use std::collections::BTreeMap;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use crate::minet::{Field, Len, Reader, TypeValue, Writer};
+use crate::minet::content::ContentRegistry;
+use crate::minet::save::{deflate, inflate};
+use crate::minet::world::{Building, World};
+
+const MAGIC: &[u8] = b"msch";
+
+/// Blocks configured with the position of the one they
+/// link to, which schematics keep relative to the block.
+const LINKED_BLOCKS: &[&str] = &[
+    "bridge-conveyor", "phase-conveyor", "bridge-conduit", "phase-conduit", "duct-bridge",
+    "reinforced-bridge-conduit", "mass-driver", "payload-mass-driver", "large-payload-mass-driver",
+];
+
+/// Power nodes, which are configured with a position to
+/// link or unlink, and keep their links as a list.
+const POWER_NODES: &[&str] = &["power-node", "power-node-large", "surge-tower", "beam-link"];
+
+/// The schematic version minet reads and writes.
+/// Version 0 stored configs as plain `i32`s.
+pub const VERSION: u8 = 1;
+
+/// A block in a schematic.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SchematicTile {
+    /// The block's name, so that a schematic
+    /// doesn't depend on a build's ids.
+    pub block: String,
+    /// Position from the bottom left corner.
+    /// Blocks bigger than 1x1 are at their center.
+    pub x: i16,
+    pub y: i16,
+    pub config: TypeValue,
+    pub rotation: u8,
+}
+
+/// A `.msch` schematic, as written by `Schematics.write`.
+///
+/// Schematics are shared as files and as the
+/// same bytes in base64, as by "copy to clipboard".
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Schematic {
+    pub width: u16,
+    pub height: u16,
+    /// `name`, `description` and `labels`, among others.
+    pub tags: BTreeMap<String, String>,
+    pub tiles: Vec<SchematicTile>,
+}
+
+impl Schematic {
+    /// Copies the buildings whose center is in the
+    /// `width` by `height` tiles from `(x, y)`, as
+    /// Mindustry's copy tool does. The schematic is cut
+    /// to the area they cover, so it can come out
+    /// smaller than asked for, or empty.
+    ///
+    /// Configs are those seen in `constructFinish` and
+    /// `tileConfig`; buildings the bot hasn't seen
+    /// built or configured get none. Links are made
+    /// relative, as `Building.config()` does, so that
+    /// they still line up wherever the schematic goes.
+    pub fn capture(world: &World, content: &ContentRegistry, x: i16, y: i16, width: u16, height: u16) -> Self {
+        let (xs, ys) = (x..x.saturating_add(width as i16), y..y.saturating_add(height as i16));
+        let mut found: Vec<_> = world.buildings()
+            .filter(|b| xs.contains(&b.x()) && ys.contains(&b.y()))
+            .filter_map(|b| Some((b, content.blocks.get(b.block)?)))
+            // unfinished buildings are `build1` to `build16`
+            .filter(|(_, block)| block.name.strip_prefix("build").is_none_or(|size| size.parse::<u8>().is_err()))
+            .collect();
+        if found.is_empty() {
+            return Self::default();
+        }
+        found.sort_by_key(|(b, _)| (b.y(), b.x()));
+
+        // blocks are placed by their center, which is
+        // left of and below the middle for even sizes
+        let low = |b: &Building, size: u8| (b.x() - (size.max(1) as i16 - 1) / 2, b.y() - (size.max(1) as i16 - 1) / 2);
+        let high = |b: &Building, size: u8| (b.x() + size.max(1) as i16 / 2, b.y() + size.max(1) as i16 / 2);
+        let min_x = found.iter().map(|(b, block)| low(b, block.size).0).min().unwrap();
+        let min_y = found.iter().map(|(b, block)| low(b, block.size).1).min().unwrap();
+        let max_x = found.iter().map(|(b, block)| high(b, block.size).0).max().unwrap();
+        let max_y = found.iter().map(|(b, block)| high(b, block.size).1).max().unwrap();
+        Self {
+            width: (max_x - min_x + 1) as u16,
+            height: (max_y - min_y + 1) as u16,
+            tags: BTreeMap::new(),
+            tiles: found.into_iter().map(|(b, block)| SchematicTile {
+                block: block.name.clone(),
+                x: b.x() - min_x,
+                y: b.y() - min_y,
+                config: relative_config(&block.name, &b.config, b.x(), b.y()),
+                rotation: b.rotation,
+            }).collect(),
+        }
+    }
+
+    pub fn read(data: &[u8]) -> Result<Self, String> {
+        if data.get(..MAGIC.len()) != Some(MAGIC) {
+            return Err("not a schematic".to_string());
+        }
+        let version = *data.get(MAGIC.len()).ok_or("file ended early")?;
+        if version != VERSION {
+            return Err(format!("unsupported schematic version {}", version));
+        }
+        let data = inflate(&data[MAGIC.len() + 1..])?;
+        Self::read_body(&mut Reader::new(&data)).ok_or_else(|| "invalid schematic".to_string())
+    }
+
+    fn read_body(reader: &mut Reader) -> Option<Self> {
+        let (width, height) = (reader.u16()?, reader.u16()?);
+        let tags = (0..reader.u8()?)
+            .map(|_| Some((reader.prefixed_str(Len::U16)?, reader.prefixed_str(Len::U16)?)))
+            .collect::<Option<_>>()?;
+        let palette: Vec<String> = (0..reader.u8()?).map(|_| reader.prefixed_str(Len::U16)).collect::<Option<_>>()?;
+        let tiles = (0..reader.i32()?.max(0)).map(|_| {
+            let block = palette.get(reader.u8()? as usize)?.clone();
+            let pos = reader.i32()?;
+            Some(SchematicTile { block, x: (pos >> 16) as i16, y: pos as i16, config: TypeValue::read(reader)?, rotation: reader.u8()? })
+        }).collect::<Option<_>>()?;
+        Some(Self { width, height, tags, tiles })
+    }
+
+    pub fn write(&self) -> Vec<u8> {
+        let mut palette: Vec<&str> = Vec::new();
+        for tile in &self.tiles {
+            if !palette.contains(&tile.block.as_str()) {
+                palette.push(&tile.block);
+            }
+        }
+
+        let mut buf = Writer::new();
+        buf.u16(self.width);
+        buf.u16(self.height);
+        buf.u8(self.tags.len() as u8);
+        for (key, value) in &self.tags {
+            buf.prefixed_str(key, Len::U16);
+            buf.prefixed_str(value, Len::U16);
+        }
+        buf.u8(palette.len() as u8);
+        palette.iter().for_each(|name| buf.prefixed_str(name, Len::U16));
+        buf.i32(self.tiles.len() as i32);
+        for tile in &self.tiles {
+            buf.u8(palette.iter().position(|name| *name == tile.block).unwrap() as u8);
+            buf.i32(((tile.x as i32) << 16) | (tile.y as u16 as i32));
+            tile.config.write(&mut buf);
+            buf.u8(tile.rotation);
+        }
+
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        out.extend(deflate(&buf.0));
+        out
+    }
+
+    /// Reads a schematic copied as text.
+    pub fn from_base64(text: &str) -> Result<Self, String> {
+        let data = BASE64.decode(text.trim()).map_err(|e| e.to_string())?;
+        Self::read(&data)
+    }
+
+    pub fn base64(&self) -> String {
+        BASE64.encode(self.write())
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::read(&data).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.write()).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    #[allow(dead_code)]
+    pub fn name(&self) -> Option<&str> {
+        self.tags.get("name").map(String::as_str)
+    }
+
+    #[allow(dead_code)]
+    pub fn description(&self) -> Option<&str> {
+        self.tags.get("description").map(String::as_str)
+    }
+}
+
+/// The config of the `block` at `(x, y)` with the
+/// absolute positions sent in packets made relative.
+fn relative_config(block: &str, config: &TypeValue, x: i16, y: i16) -> TypeValue {
+    let offset = |pos: i32| ((pos >> 16) as i16 - x, pos as i16 - y);
+    match *config {
+        TypeValue::Building(pos) => {
+            let (dx, dy) = offset(pos);
+            TypeValue::Point2(dx as i32, dy as i32)
+        },
+        // -1 unlinks
+        TypeValue::Int(-1) if LINKED_BLOCKS.contains(&block) || POWER_NODES.contains(&block) => TypeValue::Null,
+        TypeValue::Int(pos) if LINKED_BLOCKS.contains(&block) => {
+            let (dx, dy) = offset(pos);
+            TypeValue::Point2(dx as i32, dy as i32)
+        },
+        TypeValue::Int(pos) if POWER_NODES.contains(&block) => TypeValue::Point2Array(vec![offset(pos)]),
+        _ => config.clone(),
+    }
+}
+
+#[test]
+fn test_schematic() {
+    let schematic = Schematic {
+        width: 4,
+        height: 3,
+        tags: BTreeMap::from([("name".to_string(), "drills".to_string()), ("labels".to_string(), "[]".to_string())]),
+        tiles: vec![
+            SchematicTile { block: "mechanical-drill".to_string(), x: 0, y: 1, config: TypeValue::Null, rotation: 0 },
+            SchematicTile { block: "conveyor".to_string(), x: 2, y: 0, config: TypeValue::Null, rotation: 1 },
+            SchematicTile { block: "sorter".to_string(), x: 3, y: 0, config: TypeValue::Content { kind: 0, id: 1 }, rotation: 0 },
+            SchematicTile { block: "conveyor".to_string(), x: 3, y: 2, config: TypeValue::Null, rotation: 3 },
+        ],
+    };
+    let data = schematic.write();
+    assert_eq!(&data[..5], b"msch\x01");
+    assert_eq!(Schematic::read(&data).unwrap(), schematic);
+
+    let text = schematic.base64();
+    assert!(text.starts_with("bXNjaAF"));
+    let read = Schematic::from_base64(&format!("{}\n", text)).unwrap();
+    assert_eq!(read.name(), Some("drills"));
+    assert_eq!(read, schematic);
+
+    assert!(Schematic::read(b"msch\x00").is_err());
+    assert!(Schematic::from_base64("not base64!").is_err());
+}
+
+#[test]
+fn test_schematic_capture() {
+    let content = ContentRegistry::from_json(r#"{
+        "blocks": [{ "id": 1, "name": "conveyor" }, { "id": 2, "name": "sorter" },
+                   { "id": 3, "name": "ripple", "size": 3 }, { "id": 4, "name": "build3", "size": 3 },
+                   { "id": 5, "name": "bridge-conveyor" }, { "id": 6, "name": "power-node" }]
+    }"#).unwrap();
+    let mut world = World::default();
+    let building = |x: i32, y: i32, block, rotation, config| Building { pos: (x << 16) | y, block, rotation, config, ..Building::default() };
+    world.insert(building(10, 10, 1, 1, TypeValue::Null));
+    world.insert(building(11, 10, 2, 0, TypeValue::Content { kind: 0, id: 1 }));
+    world.insert(building(13, 12, 3, 0, TypeValue::Null));
+    world.insert(building(20, 20, 4, 0, TypeValue::Null));
+    world.insert(building(30, 30, 1, 0, TypeValue::Null));
+    // linked to each other by absolute position
+    world.insert(building(40, 40, 5, 0, TypeValue::Int((43 << 16) | 40)));
+    world.insert(building(43, 40, 5, 0, TypeValue::Int(-1)));
+    world.insert(building(41, 42, 6, 0, TypeValue::Int((40 << 16) | 40)));
+
+    let schematic = Schematic::capture(&world, &content, 9, 9, 20, 20);
+    // the ripple reaches one tile past its center
+    assert_eq!((schematic.width, schematic.height), (5, 4));
+    assert_eq!(schematic.tiles, [
+        SchematicTile { block: "conveyor".to_string(), x: 0, y: 0, config: TypeValue::Null, rotation: 1 },
+        SchematicTile { block: "sorter".to_string(), x: 1, y: 0, config: TypeValue::Content { kind: 0, id: 1 }, rotation: 0 },
+        SchematicTile { block: "ripple".to_string(), x: 3, y: 2, config: TypeValue::Null, rotation: 0 },
+    ]);
+    assert_eq!(Schematic::from_base64(&schematic.base64()).unwrap(), schematic);
+    assert!(Schematic::capture(&world, &content, 0, 0, 5, 5).tiles.is_empty());
+
+    let linked = Schematic::capture(&world, &content, 40, 40, 4, 3);
+    let configs: Vec<_> = linked.tiles.iter().map(|t| t.config.clone()).collect();
+    assert_eq!(configs, [TypeValue::Point2(3, 0), TypeValue::Null, TypeValue::Point2Array(vec![(-1, -2)])]);
+}
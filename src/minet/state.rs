@@ -0,0 +1,174 @@
+use std::{collections::BTreeMap, sync::mpsc, time::Instant};
+use crate::minet::{Reader, SetRulesCallPacket, StateSnapshotCallPacket};
+use crate::minet::protocol::Layout;
+use crate::minet::rules::Rules;
+
+/// Something that changed between two `stateSnapshot`s.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StateEvent {
+    WaveChanged { from: i32, to: i32 },
+    Paused(bool),
+    GameOver,
+    /// The `Rules` keys that changed.
+    RulesChanged(Vec<String>),
+}
+
+/// The state of the game, as last sent
+/// in a `stateSnapshot`.
+#[derive(Debug, Default)]
+pub struct GameState {
+    pub wave: i32,
+    /// Ticks until the next wave.
+    pub wave_countdown: f32,
+    pub enemies: i32,
+    pub paused: bool,
+    pub game_over: bool,
+    pub tps: u8,
+    /// Core items by team, then by item id.
+    pub core_items: BTreeMap<u8, BTreeMap<u16, i32>>,
+    /// When the last snapshot arrived,
+    /// or `None` before the first.
+    pub updated: Option<Instant>,
+    pub rules: Rules,
+    /// When the rules were last set,
+    /// or `None` if they are the defaults.
+    pub rules_updated: Option<Instant>,
+    subscribers: Vec<mpsc::Sender<StateEvent>>,
+}
+
+/// Reads the `core_data` of a `stateSnapshot`.
+pub fn read_core_data(data: &[u8], layout: &Layout) -> Option<BTreeMap<u8, BTreeMap<u16, i32>>> {
+    let mut reader = Reader::new(data);
+    let short = |reader: &mut Reader| if layout.short_item_ids { reader.u16() } else { reader.u8().map(|b| b as u16) };
+    let mut teams = BTreeMap::new();
+    for _ in 0..reader.u8()? {
+        let team = reader.u8()?;
+        let mut items = BTreeMap::new();
+        for _ in 0..short(&mut reader)? {
+            let item = short(&mut reader)?;
+            items.insert(item, reader.i32()?);
+        }
+        teams.insert(team, items);
+    }
+    Some(teams)
+}
+
+impl GameState {
+    /// Returns a channel that receives every
+    /// `StateEvent` from now on.
+    #[allow(dead_code)]
+    pub fn subscribe(&mut self) -> mpsc::Receiver<StateEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    /// Applies a snapshot laid out as `layout` says.
+    pub fn apply(&mut self, layout: &Layout, packet: &StateSnapshotCallPacket) {
+        let mut events = Vec::new();
+        // the first snapshot sets the scene rather than changing it
+        if self.updated.is_some() {
+            if packet.wave != self.wave {
+                events.push(StateEvent::WaveChanged { from: self.wave, to: packet.wave });
+            }
+            if packet.paused != self.paused {
+                events.push(StateEvent::Paused(packet.paused));
+            }
+        }
+        if packet.game_over && !self.game_over {
+            events.push(StateEvent::GameOver);
+        }
+
+        self.wave = packet.wave;
+        self.wave_countdown = packet.wave_time;
+        self.enemies = packet.enemies;
+        self.paused = packet.paused;
+        self.game_over = packet.game_over;
+        self.tps = packet.tps;
+        if let Some(core_items) = read_core_data(&packet.core_data, layout) {
+            self.core_items = core_items;
+        }
+        self.updated = Some(Instant::now());
+
+        for event in events {
+            self.emit(event);
+        }
+    }
+
+    fn emit(&mut self, event: StateEvent) {
+        self.subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+
+    /// Replaces the rules with those in `setRules`.
+    pub fn apply_rules(&mut self, packet: &SetRulesCallPacket) -> Result<(), String> {
+        self.set_rules(Rules::from_json(&packet.rules)?);
+        Ok(())
+    }
+
+    /// Replaces the rules, e.g. with those from the world data.
+    pub fn set_rules(&mut self, rules: Rules) {
+        let changes = rules.changes(&self.rules);
+        let known = self.rules_updated.is_some();
+        self.rules = rules;
+        self.rules_updated = Some(Instant::now());
+        if known && !changes.is_empty() {
+            self.emit(StateEvent::RulesChanged(changes));
+        }
+    }
+
+    /// Seconds until the next wave.
+    pub fn wave_countdown_secs(&self) -> f32 {
+        self.wave_countdown / 60.0
+    }
+}
+
+#[test]
+fn test_game_state() {
+    use crate::minet::{Data, Packet, Writer};
+    use crate::minet::protocol::Protocol;
+
+    let mut core_data = Writer::new();
+    core_data.u8(1);
+    core_data.u8(1);
+    core_data.u16(1);
+    core_data.u16(0);
+    core_data.i32(1500);
+    let mut packet = StateSnapshotCallPacket {
+        wave_time: 1800.0, wave: 4, enemies: 0, paused: false, game_over: false,
+        time_data: 0, tps: 60, rand0: 0, rand1: 0, core_data: core_data.0,
+    };
+
+    let mut state = GameState::default();
+    let events = state.subscribe();
+    state.apply(&Layout::default(), &packet);
+    assert_eq!(state.wave_countdown_secs(), 30.0);
+    assert_eq!(state.core_items[&1][&0], 1500);
+    assert!(events.try_recv().is_err());
+
+    packet.wave = 5;
+    packet.game_over = true;
+    state.apply(&Layout::default(), &packet);
+    assert_eq!(events.try_recv(), Ok(StateEvent::WaveChanged { from: 4, to: 5 }));
+    assert_eq!(events.try_recv(), Ok(StateEvent::GameOver));
+    state.apply(&Layout::default(), &packet);
+    assert!(events.try_recv().is_err());
+
+    // a build with no tps or seed, and byte item ids
+    let layout = Layout { chat_unformatted: false, state_tps: false, short_item_ids: false, ..Layout::default() };
+    let older = Protocol::new("old", 126, layout, &[("StateSnapshotCallPacket", 0x1d)]);
+    let mut old = Writer::new();
+    StateSnapshotCallPacket { core_data: vec![1, 2, 1, 3, 0, 0, 0, 9], ..packet.clone() }.write(&mut old, &older);
+    let mut new = Writer::new();
+    Data::serialize(&packet, &mut new);
+    // tps, rand0 and rand1
+    assert_eq!(new.0.len() - packet.core_data.len(), old.0.len() - 8 + 17);
+    let decoded = StateSnapshotCallPacket::read(&old.0, &older).unwrap();
+    assert_eq!(read_core_data(&decoded.core_data, &older.layout).unwrap()[&2][&3], 9);
+
+    state.apply_rules(&SetRulesCallPacket { rules: r#"{"pvp": true}"#.to_string() }).unwrap();
+    assert!(state.rules.pvp);
+    assert!(events.try_recv().is_err());
+    state.apply_rules(&SetRulesCallPacket { rules: r#"{"pvp": true, "bannedBlocks": ["duo"]}"#.to_string() }).unwrap();
+    assert_eq!(events.try_recv(), Ok(StateEvent::RulesChanged(vec!["bannedBlocks".to_string()])));
+    assert!(state.apply_rules(&SetRulesCallPacket { rules: "{".to_string() }).is_err());
+}
@@ -1,4 +1,22 @@
 
+/// The width of a length prefix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Len {
+    U8,
+    U16,
+    I32,
+}
+
+impl Len {
+    fn max(self) -> usize {
+        match self {
+            Len::U8 => u8::MAX as usize,
+            Len::U16 => u16::MAX as usize,
+            Len::I32 => i32::MAX as usize,
+        }
+    }
+}
+
 /// Represents a buffer
 /// to which data can be written to.
 /// The data format is consistent
@@ -9,7 +27,7 @@ pub struct Writer(pub Vec<u8>);
 
 impl Writer {
     pub fn new() -> Self {
-        return Writer(Vec::new());
+        Writer(Vec::new())
     }
 
     pub fn u64(&mut self, i: u64) {
@@ -74,6 +92,18 @@ impl Writer {
         self.0.push(b);
     }
 
+    pub fn i8(&mut self, b: i8) {
+        self.0.push(b as u8);
+    }
+
+    pub fn f32(&mut self, f: f32) {
+        self.u32(f.to_bits());
+    }
+
+    pub fn f64(&mut self, f: f64) {
+        self.u64(f.to_bits());
+    }
+
     pub fn bool(&mut self, b: bool) {
         self.u8(if b { 1 } else { 0 });
     }
@@ -86,6 +116,31 @@ impl Writer {
         self.0.extend(s.as_bytes());
     }
 
+    /// Writes a string that may be null,
+    /// the way `TypeIO.writeString` does.
+    pub fn nullable_str(&mut self, s: Option<&str>) {
+        match s {
+            Some(s) => self.str(s),
+            None => self.bool(false),
+        }
+    }
+
+    pub fn length(&mut self, len: usize, width: Len) {
+        match width {
+            Len::U8 => self.u8(len as u8),
+            Len::U16 => self.u16(len as u16),
+            Len::I32 => self.i32(len as i32),
+        }
+    }
+
+    /// Writes a string with a `width` length prefix
+    /// and no exists flag, cut short if it doesn't fit.
+    pub fn prefixed_str(&mut self, s: &str, width: Len) {
+        let bytes = &s.as_bytes()[..s.len().min(width.max())];
+        self.length(bytes.len(), width);
+        self.bytes(bytes);
+    }
+
     /// Appends a series of bytes
     /// to this.
     pub fn bytes(&mut self, s: &[u8]) {
@@ -118,7 +173,7 @@ impl<'a> Reader<'a> {
                 ((self.data[self.pos+4] as u64) << 24) |
                 ((self.data[self.pos+5] as u64) << 16) |
                 ((self.data[self.pos+6] as u64) << 8) |
-                ((self.data[self.pos+7] as u64));
+                (self.data[self.pos+7] as u64);
             self.pos += 8;
             Some(value)
         }
@@ -136,7 +191,7 @@ impl<'a> Reader<'a> {
                 ((self.data[self.pos+4] as i64) << 24) |
                 ((self.data[self.pos+5] as i64) << 16) |
                 ((self.data[self.pos+6] as i64) << 8) |
-                ((self.data[self.pos+7] as i64));
+                (self.data[self.pos+7] as i64);
             self.pos += 8;
             Some(value)
         }
@@ -150,7 +205,7 @@ impl<'a> Reader<'a> {
                 ((self.data[self.pos] as u32) << 24) |
                 ((self.data[self.pos+1] as u32) << 16) |
                 ((self.data[self.pos+2] as u32) << 8) |
-                ((self.data[self.pos+3] as u32));
+                (self.data[self.pos+3] as u32);
             self.pos += 4;
             Some(value)
         }
@@ -164,7 +219,7 @@ impl<'a> Reader<'a> {
                 ((self.data[self.pos] as i32) << 24) |
                 ((self.data[self.pos+1] as i32) << 16) |
                 ((self.data[self.pos+2] as i32) << 8) |
-                ((self.data[self.pos+3] as i32));
+                (self.data[self.pos+3] as i32);
             self.pos += 4;
             Some(value)
         }
@@ -176,7 +231,7 @@ impl<'a> Reader<'a> {
         } else {
             let value = 
                 ((self.data[self.pos] as u16) << 8) |
-                ((self.data[self.pos+1] as u16));
+                (self.data[self.pos+1] as u16);
             self.pos += 2;
             Some(value)
         }
@@ -188,7 +243,7 @@ impl<'a> Reader<'a> {
         } else {
             let value = 
                 ((self.data[self.pos] as i16) << 8) |
-                ((self.data[self.pos+1] as i16));
+                (self.data[self.pos+1] as i16);
             self.pos += 2;
             Some(value)
         }
@@ -218,7 +273,15 @@ impl<'a> Reader<'a> {
     }
 
     pub fn bool(&mut self) -> Option<bool> {
-        self.u8().map(|v| if v == 0 { false } else { true })
+        self.u8().map(|v| v != 0)
+    }
+
+    pub fn f32(&mut self) -> Option<f32> {
+        self.u32().map(f32::from_bits)
+    }
+
+    pub fn f64(&mut self) -> Option<f64> {
+        self.u64().map(f64::from_bits)
     }
 
     pub fn str(&mut self) -> Option<&str> {
@@ -235,6 +298,30 @@ impl<'a> Reader<'a> {
         })
     }
 
+    /// Reads a string written by `Writer::nullable_str`.
+    pub fn nullable_str(&mut self) -> Option<Option<&str>> {
+        if !self.bool()? {
+            return Some(None)
+        }
+        let len = self.u16()?;
+        let bytes = self.bytes(len as usize)?;
+        Some(Some(std::str::from_utf8(bytes).unwrap_or("")))
+    }
+
+    pub fn length(&mut self, width: Len) -> Option<usize> {
+        match width {
+            Len::U8 => self.u8().map(|n| n as usize),
+            Len::U16 => self.u16().map(|n| n as usize),
+            Len::I32 => self.i32().and_then(|n| usize::try_from(n).ok()),
+        }
+    }
+
+    /// Reads a string written by `Writer::prefixed_str`.
+    pub fn prefixed_str(&mut self, width: Len) -> Option<String> {
+        let len = self.length(width)?;
+        Some(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+
     pub fn bytes(&mut self, n: usize) -> Option<&[u8]> {
         if self.data.len() - self.pos < n {
             return None
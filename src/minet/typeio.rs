@@ -0,0 +1,382 @@
+use crate::minet::{Field, Reader, Writer};
+use crate::minet::protocol::Layout;
+use serde_json::{json, Value};
+
+/// A dynamically typed value, as written by
+/// `TypeIO.writeObject`. Used for block configs
+/// and a few `Call` parameters.
+///
+/// Each value starts with a `u8` type tag.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum TypeValue {
+    #[default]
+    Null,
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    String(Option<String>),
+    /// A block, item, unit type etc.
+    /// `kind` is the `ContentType` ordinal.
+    Content { kind: u8, id: i16 },
+    IntSeq(Vec<i32>),
+    Point2(i32, i32),
+    /// Sent packed into one `i32` each,
+    /// so coordinates are cut to 16 bits.
+    Point2Array(Vec<(i16, i16)>),
+    TechNode { kind: u8, id: i16 },
+    Bool(bool),
+    Double(f64),
+    /// A building, by its packed tile position.
+    Building(i32),
+    /// A `LAccess` ordinal.
+    LAccess(i16),
+    Bytes(Vec<u8>),
+    /// A `UnitCommand`, by id. Only read
+    /// with `Layout::unit_command_ids`.
+    UnitCommand(u16),
+    Bools(Vec<bool>),
+    /// A unit, by id.
+    Unit(i32),
+    Vec2Array(Vec<(f32, f32)>),
+    Vec2(f32, f32),
+    Team(u8),
+}
+
+impl TypeValue {
+    pub fn tag(&self) -> u8 {
+        match self {
+            TypeValue::Null => 0,
+            TypeValue::Int(_) => 1,
+            TypeValue::Long(_) => 2,
+            TypeValue::Float(_) => 3,
+            TypeValue::String(_) => 4,
+            TypeValue::Content { .. } => 5,
+            TypeValue::IntSeq(_) => 6,
+            TypeValue::Point2(..) => 7,
+            TypeValue::Point2Array(_) => 8,
+            TypeValue::TechNode { .. } => 9,
+            TypeValue::Bool(_) => 10,
+            TypeValue::Double(_) => 11,
+            TypeValue::Building(_) => 12,
+            TypeValue::LAccess(_) => 13,
+            TypeValue::Bytes(_) => 14,
+            TypeValue::UnitCommand(_) => 15,
+            TypeValue::Bools(_) => 16,
+            TypeValue::Unit(_) => 17,
+            TypeValue::Vec2Array(_) => 18,
+            TypeValue::Vec2(..) => 19,
+            TypeValue::Team(_) => 20,
+        }
+    }
+
+    /// The name used for the `type` key in JSON.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            TypeValue::Null => "null",
+            TypeValue::Int(_) => "int",
+            TypeValue::Long(_) => "long",
+            TypeValue::Float(_) => "float",
+            TypeValue::String(_) => "string",
+            TypeValue::Content { .. } => "content",
+            TypeValue::IntSeq(_) => "int_seq",
+            TypeValue::Point2(..) => "point2",
+            TypeValue::Point2Array(_) => "point2_array",
+            TypeValue::TechNode { .. } => "tech_node",
+            TypeValue::Bool(_) => "bool",
+            TypeValue::Double(_) => "double",
+            TypeValue::Building(_) => "building",
+            TypeValue::LAccess(_) => "laccess",
+            TypeValue::Bytes(_) => "bytes",
+            TypeValue::UnitCommand(_) => "unit_command",
+            TypeValue::Bools(_) => "bools",
+            TypeValue::Unit(_) => "unit",
+            TypeValue::Vec2Array(_) => "vec2_array",
+            TypeValue::Vec2(..) => "vec2",
+            TypeValue::Team(_) => "team",
+        }
+    }
+
+    /// Reads a value written by a build with `layout`.
+    pub fn read_with(reader: &mut Reader, layout: &Layout) -> Option<Self> {
+        Some(match reader.u8()? {
+            0 => TypeValue::Null,
+            1 => TypeValue::Int(reader.i32()?),
+            2 => TypeValue::Long(reader.i64()?),
+            3 => TypeValue::Float(reader.f32()?),
+            4 => TypeValue::String(reader.nullable_str()?.map(str::to_string)),
+            5 => TypeValue::Content { kind: reader.u8()?, id: reader.i16()? },
+            6 => {
+                let len = reader.i16()?.max(0);
+                TypeValue::IntSeq((0..len).map(|_| reader.i32()).collect::<Option<_>>()?)
+            },
+            7 => TypeValue::Point2(reader.i32()?, reader.i32()?),
+            8 => {
+                let len = reader.i8()?.max(0);
+                TypeValue::Point2Array((0..len).map(|_| reader.i32().map(unpack_point)).collect::<Option<_>>()?)
+            },
+            9 => TypeValue::TechNode { kind: reader.u8()?, id: reader.i16()? },
+            10 => TypeValue::Bool(reader.bool()?),
+            11 => TypeValue::Double(reader.f64()?),
+            12 => TypeValue::Building(reader.i32()?),
+            13 => TypeValue::LAccess(reader.i16()?),
+            14 => {
+                let len = usize::try_from(reader.i32()?).ok()?;
+                TypeValue::Bytes(reader.bytes(len)?.to_vec())
+            },
+            15 if layout.unit_command_ids => TypeValue::UnitCommand(reader.u16()?),
+            16 => {
+                let len = reader.i32()?.max(0);
+                TypeValue::Bools((0..len).map(|_| reader.bool()).collect::<Option<_>>()?)
+            },
+            17 => TypeValue::Unit(reader.i32()?),
+            18 => {
+                let len = reader.i16()?.max(0);
+                TypeValue::Vec2Array((0..len).map(|_| Some((reader.f32()?, reader.f32()?))).collect::<Option<_>>()?)
+            },
+            19 => TypeValue::Vec2(reader.f32()?, reader.f32()?),
+            20 => TypeValue::Team(reader.u8()?),
+            _ => return None,
+        })
+    }
+}
+
+fn pack_point(x: i16, y: i16) -> i32 {
+    ((x as i32) << 16) | (y as u16 as i32)
+}
+
+fn unpack_point(packed: i32) -> (i16, i16) {
+    ((packed >> 16) as i16, packed as i16)
+}
+
+impl Field for TypeValue {
+    fn write(&self, buf: &mut Writer) {
+        buf.u8(self.tag());
+        match self {
+            TypeValue::Null => {},
+            TypeValue::Int(i) | TypeValue::Building(i) | TypeValue::Unit(i) => buf.i32(*i),
+            TypeValue::Long(l) => buf.i64(*l),
+            TypeValue::Float(f) => buf.f32(*f),
+            TypeValue::String(s) => buf.nullable_str(s.as_deref()),
+            TypeValue::Content { kind, id } | TypeValue::TechNode { kind, id } => {
+                buf.u8(*kind);
+                buf.i16(*id);
+            },
+            TypeValue::IntSeq(ints) => {
+                buf.i16(ints.len() as i16);
+                ints.iter().for_each(|i| buf.i32(*i));
+            },
+            TypeValue::Point2(x, y) => {
+                buf.i32(*x);
+                buf.i32(*y);
+            },
+            TypeValue::Point2Array(points) => {
+                buf.i8(points.len() as i8);
+                points.iter().for_each(|(x, y)| buf.i32(pack_point(*x, *y)));
+            },
+            TypeValue::Bool(b) => buf.bool(*b),
+            TypeValue::Double(d) => buf.f64(*d),
+            TypeValue::LAccess(l) => buf.i16(*l),
+            TypeValue::UnitCommand(id) => buf.u16(*id),
+            TypeValue::Bytes(bytes) => {
+                buf.i32(bytes.len() as i32);
+                buf.bytes(bytes);
+            },
+            TypeValue::Bools(bools) => {
+                buf.i32(bools.len() as i32);
+                bools.iter().for_each(|b| buf.bool(*b));
+            },
+            TypeValue::Vec2Array(vecs) => {
+                buf.i16(vecs.len() as i16);
+                vecs.iter().for_each(|(x, y)| {
+                    buf.f32(*x);
+                    buf.f32(*y);
+                });
+            },
+            TypeValue::Vec2(x, y) => {
+                buf.f32(*x);
+                buf.f32(*y);
+            },
+            TypeValue::Team(team) => buf.u8(*team),
+        }
+    }
+
+    /// Reads a value as v135 lays it out.
+    fn read(reader: &mut Reader) -> Option<Self> {
+        Self::read_with(reader, &Layout::default())
+    }
+
+    /// `{"type": "point2", "value": [3, 4]}`,
+    /// with `value` left out for `null`.
+    fn json(&self) -> Value {
+        let value = match self {
+            TypeValue::Null => return json!({ "type": "null" }),
+            TypeValue::Int(i) | TypeValue::Building(i) | TypeValue::Unit(i) => json!(i),
+            TypeValue::Long(l) => json!(l),
+            TypeValue::Float(f) => json!(f),
+            TypeValue::String(s) => json!(s),
+            TypeValue::Content { kind, id } | TypeValue::TechNode { kind, id } => json!({ "kind": kind, "id": id }),
+            TypeValue::IntSeq(ints) => json!(ints),
+            TypeValue::Point2(x, y) => json!([x, y]),
+            TypeValue::Point2Array(points) => json!(points.iter().map(|(x, y)| [x, y]).collect::<Vec<_>>()),
+            TypeValue::Bool(b) => json!(b),
+            TypeValue::Double(d) => json!(d),
+            TypeValue::LAccess(l) => json!(l),
+            TypeValue::Bytes(bytes) => json!(bytes),
+            TypeValue::UnitCommand(id) => json!(id),
+            TypeValue::Bools(bools) => json!(bools),
+            TypeValue::Vec2Array(vecs) => json!(vecs.iter().map(|(x, y)| [x, y]).collect::<Vec<_>>()),
+            TypeValue::Vec2(x, y) => json!([x, y]),
+            TypeValue::Team(team) => json!(team),
+        };
+        json!({ "type": self.type_name(), "value": value })
+    }
+
+    fn from_json(json: &Value) -> Option<Self> {
+        let value = &json["value"];
+        let pair = |value: &Value| Some((value.get(0)?.clone(), value.get(1)?.clone()));
+        let list = |value: &Value| value.as_array().cloned();
+        Some(match json["type"].as_str()? {
+            "null" => TypeValue::Null,
+            "int" => TypeValue::Int(i32::from_json(value)?),
+            "long" => TypeValue::Long(i64::from_json(value)?),
+            "float" => TypeValue::Float(f32::from_json(value)?),
+            "string" => TypeValue::String(if value.is_null() { None } else { Some(value.as_str()?.to_string()) }),
+            "content" => TypeValue::Content { kind: u8::from_json(&value["kind"])?, id: i16::from_json(&value["id"])? },
+            "int_seq" => TypeValue::IntSeq(list(value)?.iter().map(i32::from_json).collect::<Option<_>>()?),
+            "point2" => {
+                let (x, y) = pair(value)?;
+                TypeValue::Point2(i32::from_json(&x)?, i32::from_json(&y)?)
+            },
+            "point2_array" => TypeValue::Point2Array(list(value)?.iter().map(|point| {
+                let (x, y) = pair(point)?;
+                Some((i16::from_json(&x)?, i16::from_json(&y)?))
+            }).collect::<Option<_>>()?),
+            "tech_node" => TypeValue::TechNode { kind: u8::from_json(&value["kind"])?, id: i16::from_json(&value["id"])? },
+            "bool" => TypeValue::Bool(value.as_bool()?),
+            "double" => TypeValue::Double(value.as_f64()?),
+            "building" => TypeValue::Building(i32::from_json(value)?),
+            "laccess" => TypeValue::LAccess(i16::from_json(value)?),
+            "bytes" => TypeValue::Bytes(list(value)?.iter().map(u8::from_json).collect::<Option<_>>()?),
+            "unit_command" => TypeValue::UnitCommand(u16::from_json(value)?),
+            "bools" => TypeValue::Bools(list(value)?.iter().map(Value::as_bool).collect::<Option<_>>()?),
+            "unit" => TypeValue::Unit(i32::from_json(value)?),
+            "vec2_array" => TypeValue::Vec2Array(list(value)?.iter().map(|vec| {
+                let (x, y) = pair(vec)?;
+                Some((f32::from_json(&x)?, f32::from_json(&y)?))
+            }).collect::<Option<_>>()?),
+            "vec2" => {
+                let (x, y) = pair(value)?;
+                TypeValue::Vec2(f32::from_json(&x)?, f32::from_json(&y)?)
+            },
+            "team" => TypeValue::Team(u8::from_json(value)?),
+            _ => return None,
+        })
+    }
+}
+
+/// A unit reference, as written by `TypeIO.writeUnit`:
+/// a `u8` kind, then an `i32`, which is `0` for `None`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnitRef {
+    None,
+    /// A block unit, such as a turret's,
+    /// by its building's packed tile position.
+    Block(i32),
+    /// A unit, by id.
+    Standard(i32),
+}
+
+impl Field for UnitRef {
+    fn write(&self, buf: &mut Writer) {
+        match self {
+            UnitRef::None => { buf.u8(0); buf.i32(0) },
+            UnitRef::Block(pos) => { buf.u8(1); buf.i32(*pos) },
+            UnitRef::Standard(id) => { buf.u8(2); buf.i32(*id) },
+        }
+    }
+
+    fn read(reader: &mut Reader) -> Option<Self> {
+        let (kind, id) = (reader.u8()?, reader.i32()?);
+        Some(match kind {
+            0 => UnitRef::None,
+            1 => UnitRef::Block(id),
+            2 => UnitRef::Standard(id),
+            _ => return None,
+        })
+    }
+
+    fn json(&self) -> Value {
+        match self {
+            UnitRef::None => Value::Null,
+            UnitRef::Block(pos) => json!({ "building": pos }),
+            UnitRef::Standard(id) => json!({ "unit": id }),
+        }
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        if value.is_null() {
+            return Some(UnitRef::None);
+        }
+        match (&value["building"], &value["unit"]) {
+            (pos, Value::Null) => Some(UnitRef::Block(i32::from_json(pos)?)),
+            (Value::Null, id) => Some(UnitRef::Standard(i32::from_json(id)?)),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_type_value() {
+    let values = [
+        TypeValue::Null,
+        TypeValue::Int(-5),
+        TypeValue::Long(1 << 40),
+        TypeValue::Float(1.5),
+        TypeValue::String(Some("router".to_owned())),
+        TypeValue::String(None),
+        TypeValue::Content { kind: 1, id: 3 },
+        TypeValue::IntSeq(vec![1, -2, 3]),
+        TypeValue::Point2(-1, 70000),
+        TypeValue::Point2Array(vec![(1, -1), (-300, 2)]),
+        TypeValue::TechNode { kind: 1, id: 0 },
+        TypeValue::Bool(true),
+        TypeValue::Double(0.25),
+        TypeValue::Building(0x0010_0020),
+        TypeValue::LAccess(4),
+        TypeValue::Bytes(vec![0, 255, 7]),
+        TypeValue::Bools(vec![true, false]),
+        TypeValue::Unit(42),
+        TypeValue::Vec2Array(vec![(0.5, -0.5)]),
+        TypeValue::Vec2(8.0, 16.0),
+        TypeValue::Team(2),
+    ];
+    for value in values {
+        let mut buf = Writer::new();
+        value.write(&mut buf);
+        assert_eq!(buf.0[0], value.tag());
+        assert_eq!(TypeValue::read(&mut Reader::new(&buf.0)).as_ref(), Some(&value));
+        assert_eq!(TypeValue::from_json(&value.json()).as_ref(), Some(&value));
+    }
+
+    // an item source set to copper
+    assert_eq!(TypeValue::read(&mut Reader::new(&[5, 0, 0, 0])), Some(TypeValue::Content { kind: 0, id: 0 }));
+    let mut buf = Writer::new();
+    TypeValue::Point2Array(vec![(1, -1)]).write(&mut buf);
+    assert_eq!(buf.0, [8, 1, 0, 1, 0xff, 0xff]);
+    // a command config, which v135 can't carry
+    let command = [15, 0, 2];
+    assert_eq!(TypeValue::read(&mut Reader::new(&command)), None);
+    let layout = Layout { unit_command_ids: true, ..Layout::default() };
+    assert_eq!(TypeValue::read_with(&mut Reader::new(&command), &layout), Some(TypeValue::UnitCommand(2)));
+    let mut buf = Writer::new();
+    TypeValue::UnitCommand(2).write(&mut buf);
+    assert_eq!(buf.0, command);
+    assert_eq!(TypeValue::from_json(&TypeValue::UnitCommand(2).json()), Some(TypeValue::UnitCommand(2)));
+
+    for unit in [UnitRef::None, UnitRef::Block(0x0003_0004), UnitRef::Standard(7)] {
+        let mut buf = Writer::new();
+        unit.write(&mut buf);
+        assert_eq!(UnitRef::read(&mut Reader::new(&buf.0)), Some(unit));
+        assert_eq!(UnitRef::from_json(&unit.json()), Some(unit));
+    }
+}
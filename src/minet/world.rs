@@ -0,0 +1,556 @@
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, sync::mpsc};
+use serde_json::Map;
+use crate::minet::{BlockSnapshotCallPacket, PacketMessage, Reader, TypeValue, UnitRef, Writer};
+use crate::minet::{ConstructFinishCallPacket, DeconstructFinishCallPacket, RemoveTileCallPacket};
+use crate::minet::{RotateBlockCallPacket, SetTileCallPacket, TileConfigCallPacket};
+use crate::minet::content::{self, Block, ContentRegistry};
+use crate::minet::entity::EntityTable;
+use crate::minet::save::ContentHeader;
+
+/// A building's state, as last sent
+/// in a `blockSnapshot` (or the world data).
+#[derive(Clone, Debug, Default)]
+pub struct Building {
+    /// The packed tile position, `x << 16 | y`.
+    pub pos: i32,
+    pub block: u16,
+    pub team: u8,
+    pub rotation: u8,
+    pub health: f32,
+    pub enabled: bool,
+    /// Amounts by item id.
+    pub items: BTreeMap<u16, i32>,
+    /// Amounts by liquid id.
+    pub liquids: BTreeMap<u16, f32>,
+    /// How satisfied its power graph is,
+    /// if it has a power module.
+    pub power: Option<f32>,
+    pub efficiency: Option<f32>,
+    /// The block's own sync fields.
+    pub fields: Map<String, serde_json::Value>,
+    /// The config it was built or last configured with.
+    /// Unknown for buildings that came with the map.
+    pub config: TypeValue,
+}
+
+impl Building {
+    pub fn x(&self) -> i16 {
+        (self.pos >> 16) as i16
+    }
+
+    pub fn y(&self) -> i16 {
+        self.pos as i16
+    }
+
+    /// Reads what `Building.writeAll` wrote.
+    ///
+    /// Since v135 the data says which modules follow.
+    /// Before that, `block` has to, and without it
+    /// the building is assumed to have none.
+    pub fn read(reader: &mut Reader, pos: i32, block_id: u16, block: Option<&Block>) -> Option<Self> {
+        let mut building = Building { pos, block: block_id, enabled: true, ..Self::default() };
+        building.health = reader.f32()?;
+        let rotation = reader.u8()?;
+        building.team = reader.u8()?;
+        building.rotation = rotation & 0x7f;
+
+        let legacy = rotation & 0x80 == 0;
+        let mut version = 0;
+        let mut modules = block.map_or(0, |b| b.has_items as u8 | (b.has_power as u8) << 1 | (b.has_liquids as u8) << 2);
+        if !legacy {
+            version = reader.u8()?;
+            if version >= 1 {
+                building.enabled = reader.u8()? == 1;
+            }
+            if version >= 2 {
+                modules = reader.u8()?;
+            }
+        }
+
+        // counts and ids were bytes before v135
+        let short = |reader: &mut Reader| if legacy { reader.u8().map(|b| b as u16) } else { reader.u16() };
+        if modules & 1 != 0 {
+            for _ in 0..short(reader)? {
+                let item = short(reader)?;
+                building.items.insert(item, reader.i32()?);
+            }
+        }
+        if modules & 2 != 0 {
+            let links = reader.i16()?.max(0);
+            for _ in 0..links {
+                reader.i32()?;
+            }
+            building.power = Some(reader.f32()?);
+        }
+        if modules & 4 != 0 {
+            for _ in 0..short(reader)? {
+                let liquid = short(reader)?;
+                building.liquids.insert(liquid, reader.f32()?);
+            }
+        }
+        if version <= 2 {
+            reader.bool()?; // consume module
+        }
+        if version >= 3 {
+            building.efficiency = Some(reader.u8()? as f32 / 255.0);
+            reader.u8()?; // optional efficiency
+        }
+        if version == 4 {
+            reader.i64()?; // fog visibility
+        }
+
+        for (name, ty) in block.map_or(&[][..], |b| &b.sync[..]) {
+            building.fields.insert(name.clone(), ty.read(reader)?);
+        }
+        Some(building)
+    }
+}
+
+/// One tile of the map. Ids are those of the
+/// content header the map was saved with.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tile {
+    pub floor: u16,
+    pub overlay: u16,
+    pub block: u16,
+    /// Whether a building covers the tile. Only the
+    /// tile at its center has the building's data.
+    pub building: bool,
+    /// `data`, `floorData`, `overlayData` and `extraData`,
+    /// for blocks without a building that save them.
+    pub data: Option<(u8, u8, u8, i32)>,
+}
+
+/// The tiles of a map, as written by `SaveVersion.writeMap`
+/// in both map files and the world data sent on join.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TileMap {
+    pub width: u16,
+    pub height: u16,
+    tiles: Vec<Tile>,
+    /// What each building saved, by the index of its center tile:
+    /// its block's save revision, then `Building.writeAll`.
+    pub buildings: BTreeMap<usize, Vec<u8>>,
+}
+
+impl TileMap {
+    /// A map of air on empty floor.
+    pub fn new(width: u16, height: u16) -> Self {
+        Self { width, height, tiles: vec![Tile::default(); width as usize * height as usize], buildings: BTreeMap::new() }
+    }
+
+    #[allow(dead_code)]
+    fn index(&self, x: i16, y: i16) -> Option<usize> {
+        let (x, y) = (usize::try_from(x).ok()?, usize::try_from(y).ok()?);
+        (x < self.width as usize && y < self.height as usize).then_some(y * self.width as usize + x)
+    }
+
+    #[allow(dead_code)]
+    pub fn get(&self, x: i16, y: i16) -> Option<&Tile> {
+        self.index(x, y).map(|i| &self.tiles[i])
+    }
+
+    #[allow(dead_code)]
+    pub fn get_mut(&mut self, x: i16, y: i16) -> Option<&mut Tile> {
+        self.index(x, y).map(|i| &mut self.tiles[i])
+    }
+
+    /// Tiles row by row, from the bottom left.
+    pub fn tiles(&self) -> &[Tile] {
+        &self.tiles
+    }
+
+    /// Reads what `SaveVersion.writeMap` wrote. Runs of equal
+    /// floors, and of equal blocks without data, are stored once.
+    pub fn read(reader: &mut Reader) -> Option<Self> {
+        let mut map = Self::new(reader.u16()?, reader.u16()?);
+        let len = map.tiles.len();
+        let mut i = 0;
+        while i < len {
+            let (floor, overlay) = (reader.u16()?, reader.u16()?);
+            let run = reader.u8()? as usize;
+            for tile in &mut map.tiles[i..(i + run + 1).min(len)] {
+                tile.floor = floor;
+                tile.overlay = overlay;
+            }
+            i += run + 1;
+        }
+
+        let mut i = 0;
+        while i < len {
+            let block = reader.u16()?;
+            let packed = reader.u8()?;
+            map.tiles[i].block = block;
+            if packed & 1 != 0 {
+                map.tiles[i].building = true;
+                if reader.bool()? {
+                    let chunk = reader.u16()? as usize;
+                    map.buildings.insert(i, reader.bytes(chunk)?.to_vec());
+                }
+            } else if packed & 2 != 0 {
+                map.tiles[i].data = Some((reader.u8()?, reader.u8()?, reader.u8()?, reader.i32()?));
+            } else {
+                let run = reader.u8()? as usize;
+                for tile in &mut map.tiles[i + 1..(i + run + 1).min(len)] {
+                    tile.block = block;
+                }
+                i += run;
+            }
+            i += 1;
+        }
+        Some(map)
+    }
+
+    pub fn write(&self, buf: &mut Writer) {
+        buf.u16(self.width);
+        buf.u16(self.height);
+        let mut i = 0;
+        while i < self.tiles.len() {
+            let tile = &self.tiles[i];
+            let run = self.tiles[i + 1..].iter().take(255)
+                .take_while(|t| t.floor == tile.floor && t.overlay == tile.overlay)
+                .count();
+            buf.u16(tile.floor);
+            buf.u16(tile.overlay);
+            buf.u8(run as u8);
+            i += run + 1;
+        }
+
+        let mut i = 0;
+        while i < self.tiles.len() {
+            let tile = &self.tiles[i];
+            buf.u16(tile.block);
+            buf.u8(tile.building as u8 | (tile.data.is_some() as u8) << 1);
+            if tile.building {
+                match self.buildings.get(&i) {
+                    Some(data) => {
+                        buf.bool(true);
+                        buf.u16(data.len() as u16);
+                        buf.bytes(data);
+                    },
+                    None => buf.bool(false),
+                }
+            } else if let Some((data, floor_data, overlay_data, extra_data)) = tile.data {
+                buf.u8(data);
+                buf.u8(floor_data);
+                buf.u8(overlay_data);
+                buf.i32(extra_data);
+            } else {
+                let run = self.tiles[i + 1..].iter().take(255)
+                    .take_while(|t| t.block == tile.block && !t.building && t.data.is_none())
+                    .count();
+                buf.u8(run as u8);
+                i += run;
+            }
+            i += 1;
+        }
+    }
+}
+
+/// A change to a tile, sent in one of the
+/// tile `Call` packets. `player` is the id of
+/// the player who made it, where known.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TileEvent {
+    Constructed { x: i16, y: i16, block: u16, rotation: u8, team: u8, config: TypeValue, player: Option<i32> },
+    Deconstructed { x: i16, y: i16, block: u16, player: Option<i32> },
+    /// `rotation` is the new rotation,
+    /// if the building was known.
+    Rotated { x: i16, y: i16, rotation: Option<u8>, player: Option<i32> },
+    Configured { x: i16, y: i16, value: TypeValue, player: Option<i32> },
+    /// Set outright, without being built.
+    Set { x: i16, y: i16, block: u16, team: u8, rotation: u8 },
+    /// Removed outright. `block` is what was there,
+    /// if the building was known.
+    Removed { x: i16, y: i16, block: Option<u16> },
+}
+
+/// What the bot knows about the map.
+#[derive(Clone, Debug, Default)]
+pub struct World {
+    /// The tiles, as of when the map was loaded.
+    pub tiles: TileMap,
+    /// The names of the ids in `tiles`.
+    pub header: ContentHeader,
+    buildings: HashMap<i32, Building>,
+    subscribers: Vec<mpsc::Sender<TileEvent>>,
+}
+
+fn unpack(pos: i32) -> (i16, i16) {
+    ((pos >> 16) as i16, pos as i16)
+}
+
+fn player(id: i32) -> Option<i32> {
+    Some(id).filter(|id| *id >= 0)
+}
+
+impl World {
+    /// Builds a world from a saved map. `header` gives the
+    /// names of the ids in `tiles`, which are looked up in
+    /// `content`. Buildings that can't be decoded are left out.
+    pub fn from_map(tiles: TileMap, header: &ContentHeader, content: &ContentRegistry) -> Self {
+        let mut world = Self::default();
+        for (index, data) in &tiles.buildings {
+            let (x, y) = (index % tiles.width as usize, index / tiles.width as usize);
+            let id = tiles.tiles[*index].block;
+            let block = header.name(content::kind::BLOCK, id).and_then(|name| content.blocks.by_name(name));
+            let mut reader = Reader::new(data);
+            let building = reader.u8().and_then(|_revision| {
+                Building::read(&mut reader, ((x as i32) << 16) | y as i32, block.map_or(id, |b| b.id), block)
+            });
+            if let Some(building) = building {
+                world.insert(building);
+            }
+        }
+        world.tiles = tiles;
+        world.header = header.clone();
+        world
+    }
+
+    /// Applies a `blockSnapshot`, returning how many
+    /// buildings it updated. Buildings carry no length,
+    /// so one that can't be decoded ends the snapshot.
+    pub fn apply(&mut self, content: &ContentRegistry, packet: &BlockSnapshotCallPacket) -> Result<usize, String> {
+        let mut reader = Reader::new(&packet.data);
+        for _ in 0..packet.amount.max(0) {
+            let (pos, block) = match (reader.i32(), reader.u16()) {
+                (Some(pos), Some(block)) => (pos, block),
+                _ => return Result::Err("snapshot ended early".to_string()),
+            };
+            match Building::read(&mut reader, pos, block, content.blocks.get(block)) {
+                Some(mut building) => {
+                    // snapshots don't carry the config
+                    if let Some(old) = self.buildings.get(&pos).filter(|old| old.block == block) {
+                        building.config = old.config.clone();
+                    }
+                    self.buildings.insert(pos, building);
+                },
+                None => return Result::Err(format!("can't decode block {} at {}, {}", block, pos >> 16, pos as i16)),
+            }
+        }
+        Ok(packet.amount.max(0) as usize)
+    }
+
+    /// Replaces the tiles and buildings with `world`'s,
+    /// keeping subscribers.
+    pub fn load(&mut self, world: World) {
+        self.tiles = world.tiles;
+        self.header = world.header;
+        self.buildings = world.buildings;
+    }
+
+    /// Returns a channel that receives every
+    /// `TileEvent` from now on.
+    pub fn subscribe(&mut self) -> mpsc::Receiver<TileEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    /// Applies `msg` if it is one of the tile `Call` packets,
+    /// returning the resulting event. Builders are matched
+    /// to players through `entities`.
+    pub fn apply_tile(&mut self, entities: &EntityTable, msg: &PacketMessage) -> Option<TileEvent> {
+        let builder = |unit: &UnitRef| match unit {
+            UnitRef::Standard(id) => entities.player_of(*id).map(|p| p.id),
+            _ => None,
+        };
+        let event = if let Some(p) = msg.downcast::<ConstructFinishCallPacket>() {
+            let (x, y) = unpack(p.tile);
+            self.insert(Building { pos: p.tile, block: p.block, team: p.team, rotation: p.rotation, enabled: true, config: p.config.clone(), ..Building::default() });
+            TileEvent::Constructed { x, y, block: p.block, rotation: p.rotation, team: p.team, config: p.config.clone(), player: builder(&p.builder) }
+        } else if let Some(p) = msg.downcast::<DeconstructFinishCallPacket>() {
+            let (x, y) = unpack(p.tile);
+            self.buildings.remove(&p.tile);
+            TileEvent::Deconstructed { x, y, block: p.block, player: builder(&p.builder) }
+        } else if let Some(p) = msg.downcast::<RotateBlockCallPacket>() {
+            let (x, y) = unpack(p.building);
+            let rotation = self.buildings.get_mut(&p.building).map(|b| {
+                b.rotation = (b.rotation + if p.direction { 1 } else { 3 }) % 4;
+                b.rotation
+            });
+            TileEvent::Rotated { x, y, rotation, player: player(p.player) }
+        } else if let Some(p) = msg.downcast::<TileConfigCallPacket>() {
+            let (x, y) = unpack(p.building);
+            if let Some(b) = self.buildings.get_mut(&p.building) {
+                b.config = p.value.clone();
+            }
+            TileEvent::Configured { x, y, value: p.value.clone(), player: player(p.player) }
+        } else if let Some(p) = msg.downcast::<SetTileCallPacket>() {
+            let (x, y) = unpack(p.tile);
+            let rotation = p.rotation.rem_euclid(4) as u8;
+            self.insert(Building { pos: p.tile, block: p.block, team: p.team, rotation, enabled: true, ..Building::default() });
+            TileEvent::Set { x, y, block: p.block, team: p.team, rotation }
+        } else if let Some(p) = msg.downcast::<RemoveTileCallPacket>() {
+            let (x, y) = unpack(p.tile);
+            TileEvent::Removed { x, y, block: self.buildings.remove(&p.tile).map(|b| b.block) }
+        } else {
+            return None;
+        };
+        self.subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+        Some(event)
+    }
+
+    pub fn building(&self, x: i16, y: i16) -> Option<&Building> {
+        self.buildings.get(&(((x as i32) << 16) | (y as u16 as i32)))
+    }
+
+    pub fn buildings(&self) -> impl Iterator<Item = &Building> {
+        self.buildings.values()
+    }
+
+    pub fn insert(&mut self, building: Building) {
+        self.buildings.insert(building.pos, building);
+    }
+
+    #[allow(dead_code)]
+    pub fn remove(&mut self, x: i16, y: i16) -> Option<Building> {
+        self.buildings.remove(&(((x as i32) << 16) | (y as u16 as i32)))
+    }
+
+    /// The cores of `team`, going by block name.
+    #[allow(dead_code)]
+    pub fn cores<'a>(&'a self, content: &'a ContentRegistry, team: u8) -> impl Iterator<Item = &'a Building> {
+        self.buildings().filter(move |b| {
+            b.team == team && content.blocks.get(b.block).is_some_and(|block| block.name.starts_with("core"))
+        })
+    }
+
+    /// Fails naming the blocks on the map or built since
+    /// that `content` has no entry for, as anything worked
+    /// out from their properties would be a guess.
+    pub fn check_content(&self, content: &ContentRegistry) -> Result<(), String> {
+        let name = |id: u16| self.header.name(content::kind::BLOCK, id).map_or_else(|| format!("block {}", id), str::to_string);
+        let ids: BTreeSet<u16> = self.tiles.tiles().iter().flat_map(|t| [t.floor, t.overlay, t.block]).collect();
+        let mut unknown: BTreeSet<String> = ids.into_iter()
+            .filter(|id| self.header.name(content::kind::BLOCK, *id).is_none_or(|name| content.blocks.by_name(name).is_none()))
+            .map(name)
+            .collect();
+        unknown.extend(self.buildings().filter(|b| content.blocks.get(b.block).is_none()).map(|b| name(b.block)));
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        let mut names: Vec<String> = unknown.iter().take(3).cloned().collect();
+        if unknown.len() > names.len() {
+            names.push(format!("{} more", unknown.len() - names.len()));
+        }
+        Err(format!("content dump {:?} has no {}; load a full dump", content.version, names.join(", ")))
+    }
+
+    /// The items in `team`'s cores, by item id.
+    /// All cores of a team share one inventory.
+    #[allow(dead_code)]
+    pub fn core_items(&self, content: &ContentRegistry, team: u8) -> BTreeMap<u16, i32> {
+        self.cores(content, team).next().map(|core| core.items.clone()).unwrap_or_default()
+    }
+}
+
+#[test]
+fn test_block_snapshot() {
+    use crate::minet::Writer;
+    let content = ContentRegistry::from_json(r#"{
+        "blocks": [{ "id": 10, "name": "core-shard", "size": 3, "has_items": true },
+                   { "id": 20, "name": "duo", "size": 1, "sync": ["reload: f32", "rotation: f32"] }]
+    }"#).unwrap();
+
+    let mut data = Writer::new();
+    // a core, as v146 writes it
+    data.i32((10 << 16) | 12);
+    data.u16(10);
+    data.f32(1100.0);
+    data.u8(0x80);
+    data.u8(1); // sharded
+    data.u8(3); // version
+    data.u8(1); // enabled
+    data.u8(1); // items only
+    data.u16(2);
+    data.u16(0);
+    data.i32(4000);
+    data.u16(1);
+    data.i32(250);
+    data.u8(255);
+    data.u8(0);
+    // a turret, as v126 writes it
+    data.i32((4 << 16) | 5);
+    data.u16(20);
+    data.f32(110.0);
+    data.u8(2); // rotation
+    data.u8(1);
+    data.bool(true); // consume module
+    data.f32(12.0);
+    data.f32(90.0);
+
+    let mut world = World::default();
+    let packet = BlockSnapshotCallPacket { amount: 2, data: data.0.clone() };
+    assert_eq!(world.apply(&content, &packet), Ok(2));
+
+    let core = world.building(10, 12).unwrap();
+    assert_eq!((core.x(), core.y(), core.team, core.enabled), (10, 12, 1, true));
+    assert_eq!(core.efficiency, Some(1.0));
+    assert_eq!(world.core_items(&content, 1), BTreeMap::from([(0, 4000), (1, 250)]));
+    assert!(world.core_items(&content, 2).is_empty());
+
+    let duo = world.building(4, 5).unwrap();
+    assert_eq!((duo.rotation, duo.health), (2, 110.0));
+    assert_eq!(duo.fields["rotation"], 90.0);
+
+    let truncated = BlockSnapshotCallPacket { amount: 2, data: data.0[..data.0.len() - 2].to_vec() };
+    assert!(World::default().apply(&content, &truncated).is_err());
+}
+
+#[test]
+fn test_tile_events() {
+    use crate::minet::entity::{Entity, EntityKind};
+    use crate::minet::protocol::Protocol;
+
+    fn msg<T: crate::minet::Packet + 'static>(packet: T) -> PacketMessage {
+        PacketMessage::with_protocol(&Protocol::fallback(), packet, false).unwrap()
+    }
+    let mut entities = EntityTable::default();
+    entities.insert(Entity {
+        id: 3, class: "player".to_string(), kind: EntityKind::Player, x: 0.0, y: 0.0, rotation: 0.0, health: 0.0, team: 1,
+        unit_type: None, controller: None, name: Some("builder".to_string()), unit: Some(40), fields: Default::default(), updated: 0,
+    });
+
+    let mut world = World::default();
+    let events = world.subscribe();
+    let built = ConstructFinishCallPacket {
+        tile: (5 << 16) | 6, block: 20, builder: UnitRef::Standard(40), rotation: 1, team: 1, config: TypeValue::Null,
+    };
+    assert_eq!(world.apply_tile(&entities, &msg(built)), Some(TileEvent::Constructed {
+        x: 5, y: 6, block: 20, rotation: 1, team: 1, config: TypeValue::Null, player: Some(3),
+    }));
+    assert_eq!(world.building(5, 6).unwrap().block, 20);
+
+    let rotated = RotateBlockCallPacket { player: 3, building: (5 << 16) | 6, direction: false };
+    world.apply_tile(&entities, &msg(rotated));
+    assert_eq!(world.building(5, 6).unwrap().rotation, 0);
+    let config = TileConfigCallPacket { player: -1, building: (5 << 16) | 6, value: TypeValue::Int(2) };
+    world.apply_tile(&entities, &msg(config));
+    assert_eq!(world.building(5, 6).unwrap().config, TypeValue::Int(2));
+
+    let broken = DeconstructFinishCallPacket { tile: (5 << 16) | 6, block: 20, builder: UnitRef::Block(0) };
+    world.apply_tile(&entities, &msg(broken));
+    assert!(world.building(5, 6).is_none());
+    let removed = RemoveTileCallPacket { tile: (5 << 16) | 6 };
+    assert_eq!(world.apply_tile(&entities, &msg(removed)), Some(TileEvent::Removed { x: 5, y: 6, block: None }));
+
+    assert_eq!(events.try_iter().collect::<Vec<_>>()[1..4], [
+        TileEvent::Rotated { x: 5, y: 6, rotation: Some(0), player: Some(3) },
+        TileEvent::Configured { x: 5, y: 6, value: TypeValue::Int(2), player: None },
+        TileEvent::Deconstructed { x: 5, y: 6, block: 20, player: None },
+    ]);
+    // no builder is still followed by an i32
+    let built = ConstructFinishCallPacket {
+        tile: (7 << 16) | 8, block: 21, builder: UnitRef::None, rotation: 2, team: 3, config: TypeValue::Int(9),
+    };
+    let mut buf = Writer::new();
+    crate::minet::Data::serialize(&msg(built), &mut buf);
+    let decoded = PacketMessage::deserialize_with(&buf.0, &Protocol::fallback()).unwrap();
+    assert_eq!(decoded.downcast::<ConstructFinishCallPacket>().unwrap().builder, UnitRef::None);
+    assert_eq!(world.apply_tile(&entities, &decoded), Some(TileEvent::Constructed {
+        x: 7, y: 8, block: 21, rotation: 2, team: 3, config: TypeValue::Int(9), player: None,
+    }));
+    let chat = crate::minet::SendChatMessageCallPacket { message: "hi".to_string() };
+    assert_eq!(world.apply_tile(&entities, &msg(chat)), None);
+}
@@ -0,0 +1,263 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use crossterm::{cursor, execute, queue, terminal};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crate::connection::Connection;
+use crate::minet::{self, Data};
+use crate::minet::client::Client;
+
+/// Lines of chat kept for display.
+const CHAT_LINES: usize = 200;
+/// Columns taken by the panel right of the minimap.
+const PANEL_WIDTH: u16 = 40;
+
+/// Packets and bytes received, per second,
+/// and the round trip time of the last ping.
+#[derive(Default)]
+struct Stats {
+    packets: u32,
+    bytes: usize,
+    packets_per_sec: u32,
+    bytes_per_sec: usize,
+    since: Option<Instant>,
+    ping: Option<Duration>,
+    ping_sent: Option<(i32, Instant)>,
+}
+
+impl Stats {
+    fn record(&mut self, len: usize) {
+        self.packets += 1;
+        self.bytes += len;
+    }
+
+    fn tick(&mut self, now: Instant) {
+        let since = *self.since.get_or_insert(now);
+        if now - since >= Duration::from_secs(1) {
+            self.packets_per_sec = self.packets;
+            self.bytes_per_sec = self.bytes;
+            self.packets = 0;
+            self.bytes = 0;
+            self.since = Some(now);
+        }
+    }
+}
+
+struct Tui {
+    client: Client,
+    connection: Connection,
+    chat: VecDeque<String>,
+    input: String,
+    stats: Stats,
+}
+
+/// Strips Mindustry's `[color]` tags.
+/// `[[` is an escaped `[`.
+fn strip_colors(text: &str) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('[') {
+        out.push_str(&rest[..start]);
+        if rest[start + 1..].starts_with('[') {
+            out.push('[');
+            rest = &rest[start + 2..];
+            continue
+        }
+        match rest[start..].find(']') {
+            Some(end) => rest = &rest[start + end + 1..],
+            None => {
+                rest = &rest[start..];
+                break
+            },
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+impl Tui {
+    fn send<T: minet::Packet + 'static>(&mut self, packet: T) -> io::Result<()> {
+        self.connection.send(&self.client.protocol, packet)
+    }
+
+    fn send_ping(&mut self, now: Instant) -> io::Result<()> {
+        let id = self.stats.ping_sent.map_or(0, |(id, _)| id + 1);
+        let mut buf = minet::Writer::new();
+        minet::FrameworkMessage::new(minet::Ping { id, is_reply: false }).serialize(&mut buf);
+        self.connection.send_tcp(&buf.0)?;
+        self.stats.ping_sent = Some((id, now));
+        Ok(())
+    }
+
+    fn receive(&mut self, frame: &[u8], now: Instant) {
+        self.stats.record(frame.len());
+        if frame.first() == Some(&0xfe) {
+            let ping = minet::FrameworkMessage::deserialize(frame);
+            if let (Some(ping), Some((id, sent))) = (ping.as_ref().and_then(|m| m.downcast::<minet::Ping>()), self.stats.ping_sent) {
+                if ping.is_reply && ping.id == id {
+                    self.stats.ping = Some(now - sent);
+                }
+            }
+            return
+        }
+        let msg = match minet::PacketMessage::deserialize_with(frame, &self.client.protocol) {
+            Some(msg) => msg,
+            None => return,
+        };
+        if let Some(chat) = msg.downcast::<minet::SendMessageCallPacket>() {
+            self.log(strip_colors(&chat.message));
+        }
+        if let Err(e) = self.client.handle(&msg) {
+            self.log(format!("! {}", e));
+        }
+    }
+
+    fn log(&mut self, line: String) {
+        self.chat.push_back(line);
+        if self.chat.len() > CHAT_LINES {
+            self.chat.pop_front();
+        }
+    }
+
+    /// Handles a key, returning false to quit.
+    fn key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> io::Result<bool> {
+        match code {
+            KeyCode::Esc => return Ok(false),
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return Ok(false),
+            KeyCode::Char(c) => self.input.push(c),
+            KeyCode::Backspace => { self.input.pop(); },
+            KeyCode::Enter if !self.input.is_empty() => {
+                let message = std::mem::take(&mut self.input);
+                self.send(minet::SendChatMessageCallPacket { message })?;
+            },
+            _ => {},
+        }
+        Ok(true)
+    }
+
+    /// Where to center the minimap: our unit,
+    /// or else the middle of the map.
+    fn center(&self) -> (i64, i64) {
+        let tiles = &self.client.world.tiles;
+        let player = self.client.world_data.as_ref().map(|data| data.player_id);
+        match player.and_then(|id| self.client.entities.unit_of(id).or_else(|| self.client.entities.get(id))) {
+            Some(unit) => ((unit.x / 8.0).round() as i64, (unit.y / 8.0).round() as i64),
+            None => (tiles.width as i64 / 2, tiles.height as i64 / 2),
+        }
+    }
+
+    fn panel(&self, rows: usize) -> Vec<String> {
+        let state = &self.client.state;
+        let content = &self.client.content;
+        let mut lines = vec![
+            format!("wave {} in {:.0}s, {} enemies", state.wave, state.wave_countdown_secs(), state.enemies),
+            format!("ping {}, {} pkt/s, {} kB/s",
+                self.stats.ping.map_or("?".to_string(), |ping| format!("{}ms", ping.as_millis())),
+                self.stats.packets_per_sec, self.stats.bytes_per_sec / 1000),
+        ];
+        let team = self.client.world_data.as_ref()
+            .and_then(|data| self.client.player(data.player_id))
+            .map_or(1, |player| player.team);
+        if let Some(items) = state.core_items.get(&team) {
+            let items: Vec<String> = items.iter()
+                .map(|(id, amount)| format!("{} {}", content.name(minet::content::kind::ITEM, *id).unwrap_or("?"), amount))
+                .collect();
+            lines.push(format!("core: {}", items.join(", ")));
+        }
+        lines.push(String::new());
+        lines.push(format!("players ({})", self.client.players().count()));
+        lines.extend(self.client.players().map(|p| format!(" {}{}", p.name, if p.admin { " (admin)" } else { "" })));
+        lines.push(String::new());
+        let room = rows.saturating_sub(lines.len());
+        lines.extend(self.chat.iter().skip(self.chat.len().saturating_sub(room)).cloned());
+        lines
+    }
+
+    fn draw(&self, out: &mut impl Write) -> io::Result<()> {
+        let (cols, rows) = terminal::size()?;
+        let map_cols = cols.saturating_sub(PANEL_WIDTH + 1);
+        let map_rows = rows.saturating_sub(1);
+        let image = minet::render::render(&self.client.world, &self.client.content, Some(&self.client.entities), 1);
+        let (cx, cy) = self.center();
+
+        queue!(out, terminal::Clear(terminal::ClearType::All))?;
+        // two tiles per cell, the upper as foreground
+        let pixel = |x: i64, y: i64| {
+            let row = image.height as i64 - 1 - y;
+            if x < 0 || row < 0 || x >= image.width as i64 || row >= image.height as i64 {
+                return Color::Black;
+            }
+            let [r, g, b] = image.get(x as u32, row as u32);
+            Color::Rgb { r, g, b }
+        };
+        for row in 0..map_rows {
+            queue!(out, cursor::MoveTo(0, row))?;
+            let y = cy + map_rows as i64 - 2 * row as i64;
+            for col in 0..map_cols {
+                let x = cx - map_cols as i64 / 2 + col as i64;
+                queue!(out, SetForegroundColor(pixel(x, y)), SetBackgroundColor(pixel(x, y - 1)), Print('▀'))?;
+            }
+        }
+        queue!(out, ResetColor)?;
+
+        for (row, line) in self.panel(map_rows as usize).iter().take(map_rows as usize).enumerate() {
+            let line: String = line.chars().take(PANEL_WIDTH as usize).collect();
+            queue!(out, cursor::MoveTo(map_cols + 1, row as u16), Print(line))?;
+        }
+        queue!(out, cursor::MoveTo(0, rows.saturating_sub(1)), Print(format!("> {}", self.input)))?;
+        out.flush()
+    }
+}
+
+/// Runs the bot as a terminal UI until the server
+/// disconnects or the operator quits with Esc.
+pub fn run(client: Client, connection: Connection) -> io::Result<()> {
+    let frames = connection.spawn_readers()?;
+    let mut tui = Tui { client, connection, chat: VecDeque::new(), input: String::new(), stats: Stats::default() };
+    let mut out = io::stdout();
+    terminal::enable_raw_mode()?;
+    execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+
+    let result = (|| {
+        let mut drawn = Instant::now() - Duration::from_secs(1);
+        loop {
+            let now = Instant::now();
+            loop {
+                match frames.try_recv() {
+                    Ok(frame) => tui.receive(&frame, now),
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+            tui.stats.tick(now);
+            if tui.stats.ping_sent.is_none_or(|(_, sent)| now - sent >= Duration::from_secs(2)) {
+                tui.send_ping(now)?;
+            }
+            if event::poll(Duration::from_millis(50))? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press && !tui.key(key.code, key.modifiers)? {
+                        return Ok(());
+                    }
+                }
+                tui.draw(&mut out)?;
+                drawn = Instant::now();
+            }
+            if drawn.elapsed() >= Duration::from_millis(250) {
+                tui.draw(&mut out)?;
+                drawn = Instant::now();
+            }
+        }
+    })();
+
+    execute!(out, cursor::Show, terminal::LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+    result
+}
+
+#[test]
+fn test_strip_colors() {
+    assert_eq!(strip_colors("[coral][[[white]anuke[coral]]:[white] hi"), "[anuke]: hi");
+    assert_eq!(strip_colors("a [b"), "a [b");
+}